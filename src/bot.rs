@@ -1,60 +1,158 @@
-use crate::config::{Config, SessionStorage};
+use crate::config::Config;
 use crate::matrix::listener::MatrixListener;
+use crate::matrix::login::login;
 use crate::matrix::responder::MatrixResponder;
+use crate::matrix::rooms::RoomAliases;
+use crate::reload::{reload_on_sighup, ConfigReloader};
+use crate::shutdown::{self, ShutdownSender};
+use crate::storage;
+use crate::supervisor::{supervise, CrashReporter, SupervisorError};
 use crate::webhook::listener::WebhookListener;
-use ruma_client::Client;
-use tokio::sync::mpsc;
-use tracing::{info, trace};
+use std::future::Future;
+use std::process;
+use std::sync::Arc;
+use tokio::join;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, trace};
 
 pub async fn init() {
     // Load config data
     let config = Config::load_config();
 
     // Matrix initalization and login
-    let mut session_storage = SessionStorage::load_storage();
-    let matrix_listener_client = Client::new(config.mx_url.clone(), session_storage.session);
-    let session = &matrix_listener_client
-        .log_in(config.mx_uname.localpart(), &config.mx_pass, None, None)
-        .await
-        .unwrap();
-
-    // Save returned session
-    trace!("Session retrived, saving session data...");
-    session_storage.session = Some(session.clone());
-    session_storage.save();
-    info!("Successfully logged in as {}", config.mx_uname);
+    let matrix_listener_client = login(&config).await;
+
+    // Resolve the room aliases used in the config
+    let aliases = Arc::new(RoomAliases::new(matrix_listener_client.clone()));
+    aliases.refresh(config.room_aliases()).await;
+    let reloader = Arc::new(ConfigReloader::new(config, aliases.clone()));
+    let config = reloader.current();
 
     // Clone required clients/servers and channels
     let matrix_responder_client = matrix_listener_client.clone();
     let (matrix_tx, matrix_rx) = mpsc::channel(8);
+    let matrix_rx = Arc::new(Mutex::new(matrix_rx));
     let webhook_tx = matrix_tx.clone();
-
-    // Create thread structures
-    let mut matrix_listener = MatrixListener::new(&config, matrix_tx);
-    let mut matrix_responder = MatrixResponder::new(matrix_rx);
-    let webhook_listener = WebhookListener::new(&config, webhook_tx);
-
-    // Spawn threads from thread structures, save their cached data when they exit
-    let matrix_listener_task = tokio::spawn(async move {
-        matrix_listener.start(matrix_listener_client).await;
-        matrix_listener.storage.save_storage();
+    let (shutdown_tx, shutdown_rx) = shutdown::channel();
+    let shutdown_tx = Arc::new(shutdown_tx);
+    let responder_shutdown_rx = shutdown_rx.clone();
+    let webhook_shutdown_rx = shutdown_rx.clone();
+    let crash_report_room = config.crash_report_room.as_ref().and_then(|room| {
+        let room_id = aliases.known().get(room);
+        if room_id.is_none() {
+            error!(
+                "Unable to find crash_report_room {}. Crashes won't be reported",
+                room
+            );
+        }
+        room_id
     });
-    let matrix_responder_task = tokio::spawn(async move {
-        matrix_responder.start(matrix_responder_client).await;
-        matrix_responder.storage.save_storage();
-    });
-    let webhook_listener_task = tokio::spawn(async move {
-        webhook_listener.start().await;
+    let crash_reporter = || {
+        crash_report_room.clone().map(|room_id| CrashReporter {
+            room_id,
+            send: matrix_tx.clone(),
+        })
+    };
+    let listener_reporter = crash_reporter();
+    let webhook_reporter = crash_reporter();
+
+    // Request a shutdown of all tasks on SIGTERM/SIGINT
+    let signal_shutdown_tx = shutdown_tx.clone();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        if signal_shutdown_tx.broadcast(true).is_err() {
+            trace!("All tasks have already exited");
+        }
     });
 
+    // Reload the config on SIGHUP
+    tokio::spawn(reload_on_sighup(reloader.clone()));
+
+    // Spawn supervised tasks that are rebuilt from config if they crash, saving their cached data when they exit
+    // The responder exits once the listener and webhook listener have stopped and its queue is drained
+    let listener_reloader = reloader.clone();
+    let listener_shutdown_rx = shutdown_rx.clone();
+    let matrix_listener_task = tokio::spawn(run_supervised(
+        supervise(
+            "matrix listener",
+            shutdown_rx,
+            listener_reporter,
+            move || {
+                let client = matrix_listener_client.clone();
+                let mut matrix_listener = MatrixListener::new(
+                    listener_reloader.clone(),
+                    client.clone(),
+                    matrix_tx.clone(),
+                );
+                let shutdown_rx = listener_shutdown_rx.clone();
+                async move {
+                    matrix_listener.start(client, shutdown_rx).await;
+                    matrix_listener.save_storage();
+                }
+            },
+        ),
+        shutdown_tx.clone(),
+    ));
+    let responder_supervisor_rx = responder_shutdown_rx.clone();
+    let matrix_responder_task = tokio::spawn(run_supervised(
+        supervise(
+            "matrix responder",
+            responder_supervisor_rx,
+            None,
+            move || {
+                let mut matrix_responder = MatrixResponder::new(matrix_rx.clone());
+                let client = matrix_responder_client.clone();
+                let shutdown_rx = responder_shutdown_rx.clone();
+                async move {
+                    matrix_responder.start(client, shutdown_rx).await;
+                    matrix_responder.storage.save_storage();
+                }
+            },
+        ),
+        shutdown_tx.clone(),
+    ));
+    let webhook_supervisor_rx = webhook_shutdown_rx.clone();
+    let webhook_listener_task = tokio::spawn(run_supervised(
+        supervise(
+            "webhook listener",
+            webhook_supervisor_rx,
+            webhook_reporter,
+            move || {
+                let webhook_listener =
+                    WebhookListener::new(reloader.subscribe(), aliases.clone(), webhook_tx.clone());
+                webhook_listener.start(webhook_shutdown_rx.clone())
+            },
+        ),
+        shutdown_tx,
+    ));
+
     // Join threads to main thread
-    matrix_listener_task
-        .await
-        .expect("The matrix listener task has panicked!");
-    matrix_responder_task
-        .await
-        .expect("The matrix responder task has panicked!");
-    webhook_listener_task
-        .await
-        .expect("The webhook listener task has panicked!");
+    let results = join!(
+        matrix_listener_task,
+        webhook_listener_task,
+        matrix_responder_task
+    );
+    storage::flush();
+    if !(results.0.unwrap_or(false) && results.1.unwrap_or(false) && results.2.unwrap_or(false)) {
+        process::exit(14)
+    }
+}
+
+/// Runs a supervisor and requests a shutdown of every other task if it gives up.
+///
+/// Returns `false` if the supervised task could not be kept running.
+async fn run_supervised(
+    supervisor: impl Future<Output = Result<(), SupervisorError>>,
+    shutdown_tx: Arc<ShutdownSender>,
+) -> bool {
+    match supervisor.await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("{}. Shutting down", e);
+            if shutdown_tx.broadcast(true).is_err() {
+                trace!("All tasks have already exited");
+            }
+            false
+        }
+    }
 }
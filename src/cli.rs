@@ -0,0 +1,254 @@
+//! Command line interface
+//!
+//! Running without a subcommand starts the bot. Every other subcommand runs once and exits with
+//! a non zero code if it fails.
+
+use crate::bot;
+use crate::config::{config_path, Config, ResponderStorage, SessionStorage};
+use crate::matrix::login::saved_client;
+use crate::matrix::rooms::resolve_room;
+use crate::matrix_handlers::responders::send_message;
+use crate::messages::MatrixMessageType;
+use crate::storage::{self, copy_state, RonStore, StorageError};
+use ruma::api::{
+    client::{
+        error::ErrorKind,
+        r0::{account::whoami, session::logout},
+    },
+    error::{FromHttpResponseError, ServerError},
+};
+use ruma_client::Client;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use structopt::StructOpt;
+use tracing::{error, info};
+
+#[derive(Debug, StructOpt)]
+/// Options and subcommand parsed from the command line
+pub struct Cli {
+    #[structopt(long, global = true, parse(from_os_str))]
+    /// Directory containing config.toml. Overrides MATRIX_BOT_CONFIG_DIR
+    config_dir: Option<PathBuf>,
+    #[structopt(long, global = true, parse(from_os_str))]
+    /// Directory the bot stores its state in. Overrides MATRIX_BOT_DATA_DIR
+    data_dir: Option<PathBuf>,
+    #[structopt(long, global = true, possible_values = &["error", "warn", "info", "debug", "trace"])]
+    /// Lowest level of log messages shown. Overrides MATRIX_BOT_LOG_LEVEL
+    log_level: Option<String>,
+    #[structopt(subcommand)]
+    /// Subcommand to run. Defaults to `run`
+    command: Option<CliCommand>,
+}
+
+#[derive(Debug, StructOpt)]
+// The about of the subcommand enum replaces the about of the whole program
+#[structopt(about = "A matrix bot for projects spanning many rooms and repos")]
+/// Subcommands that can be run
+enum CliCommand {
+    /// Runs the bot until it receives SIGTERM or SIGINT
+    Run,
+    /// Checks a config file for problems without logging in
+    CheckConfig {
+        #[structopt(parse(from_os_str))]
+        /// Config file to check. Defaults to config.toml in the config directory
+        file: Option<PathBuf>,
+    },
+    /// Sends a single notice to a room using the saved session
+    Send {
+        /// Room id or alias to send the message to
+        room: String,
+        /// Text of the message
+        message: String,
+    },
+    /// Shows the account and device of the saved session
+    Whoami,
+    /// Logs out the saved session and forgets it
+    Logout,
+    /// Writes all saved state to ron files in a directory
+    ExportState {
+        #[structopt(parse(from_os_str))]
+        /// Directory to write the ron files to. Must not already contain exported state
+        dir: PathBuf,
+    },
+    /// Replaces all saved state with the state in a directory of ron files
+    ImportState {
+        #[structopt(parse(from_os_str))]
+        /// Directory containing ron files written by export-state or an older version of the bot
+        dir: PathBuf,
+    },
+}
+
+impl Cli {
+    /// Sets the environment variables read by the rest of the program from the supplied flags.
+    ///
+    /// Must be called before logging is initialized or any config or state is loaded.
+    pub fn apply_overrides(&self) {
+        if let Some(v) = &self.config_dir {
+            env::set_var("MATRIX_BOT_CONFIG_DIR", v);
+        }
+        if let Some(v) = &self.data_dir {
+            env::set_var("MATRIX_BOT_DATA_DIR", v);
+        }
+        if let Some(v) = &self.log_level {
+            env::set_var("MATRIX_BOT_LOG_LEVEL", v);
+        }
+    }
+
+    /// Runs the requested subcommand
+    pub async fn run(self) {
+        match self.command.unwrap_or(CliCommand::Run) {
+            CliCommand::Run => bot::init().await,
+            CliCommand::CheckConfig { file } => check_config(file),
+            CliCommand::Send { room, message } => send(&room, message).await,
+            CliCommand::Whoami => whoami().await,
+            CliCommand::Logout => logout().await,
+            CliCommand::ExportState { dir } => export_state(&dir),
+            CliCommand::ImportState { dir } => import_state(&dir),
+        }
+    }
+}
+
+/// Validates a config file, defaulting to config.toml, then exits without logging in.
+///
+/// Every problem found is logged and the exit code is the same one a normal start would use.
+fn check_config(path: Option<PathBuf>) -> ! {
+    let path = path.unwrap_or_else(config_path);
+    match Config::load_from(&path) {
+        Ok(_) => {
+            info!("{} is valid", path.display());
+            process::exit(0)
+        }
+        Err(e) => {
+            error!("{}", e);
+            process::exit(e.code())
+        }
+    }
+}
+
+/// Returns a client using the saved session.
+///
+/// Exits the program if no session has been saved.
+fn session_client(config: &Config) -> Client {
+    match saved_client(config) {
+        Some(v) => v,
+        None => {
+            error!("No saved session found. Run the bot once to log in");
+            process::exit(12)
+        }
+    }
+}
+
+/// Sends a notice to a room and prints the id of the event
+async fn send(room: &str, message: String) {
+    let config = Config::load_config();
+    let client = session_client(&config);
+    let room_id = match resolve_room(&client, room).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(16)
+        }
+    };
+    let mut storage = ResponderStorage::load_storage();
+    let txn_id = storage.next_txn_id();
+    storage.save_storage();
+    match send_message(
+        &client,
+        &room_id,
+        &txn_id,
+        &MatrixMessageType::Notice(message),
+    )
+    .await
+    {
+        Ok(v) => println!("{}", v),
+        Err(e) => {
+            error!("Unable to send message due to error {:?}", e);
+            process::exit(16)
+        }
+    }
+}
+
+/// Prints the user and device of the saved session as reported by the homeserver
+async fn whoami() {
+    let config = Config::load_config();
+    let client = session_client(&config);
+    let device_id = SessionStorage::load_storage()
+        .session
+        .and_then(|s| s.identification)
+        .map(|i| i.device_id.to_string());
+    match client.request(whoami::Request::new()).await {
+        Ok(v) => match device_id {
+            Some(d) => println!("{} ({})", v.user_id, d),
+            None => println!("{}", v.user_id),
+        },
+        Err(e) => {
+            error!("Unable to check saved session due to error {:?}", e);
+            process::exit(16)
+        }
+    }
+}
+
+/// Logs out the saved session and removes it from storage.
+///
+/// The session is removed even if the homeserver has already forgotten it.
+async fn logout() {
+    let config = Config::load_config();
+    let client = session_client(&config);
+    match client.request(logout::Request::new()).await {
+        Ok(_) => info!("Logged out"),
+        Err(ruma_client::Error::FromHttpResponse(FromHttpResponseError::Http(
+            ServerError::Known(e),
+        ))) if matches!(e.kind, ErrorKind::UnknownToken { .. })
+            || e.status_code.as_u16() == 401 =>
+        {
+            info!("Saved session was already logged out")
+        }
+        Err(e) => {
+            error!("Unable to log out due to error {:?}", e);
+            process::exit(16)
+        }
+    }
+    SessionStorage::default().save();
+    info!("Removed saved session");
+}
+
+/// Copies all saved state into ron files in `dir`
+fn export_state(dir: &Path) {
+    let mut ron = RonStore::new(dir.to_path_buf());
+    if ron.exists() {
+        error!("{} already contains exported state", dir.display());
+        process::exit(10)
+    }
+    let result = fs::create_dir_all(dir)
+        .map_err(|e| StorageError::Io(dir.to_path_buf(), e))
+        .and_then(|_| storage::store().run(move |s| copy_state(s, &mut ron)));
+    match result {
+        Ok(_) => info!("Exported state to {}", dir.display()),
+        Err(e) => {
+            error!("Unable to export state due to error {}", e);
+            process::exit(10)
+        }
+    }
+}
+
+/// Replaces all saved state with the state in ron files in `dir`.
+///
+/// Messages already waiting in the outbox are dropped so they are not sent alongside the imported
+/// ones. Nothing is changed if the import fails.
+fn import_state(dir: &Path) {
+    let mut ron = RonStore::new(dir.to_path_buf());
+    if !ron.exists() {
+        error!("No exported state found in {}", dir.display());
+        process::exit(10)
+    }
+    let result = storage::store().run(move |store| store.replace_state(&mut ron));
+    match result {
+        Ok(_) => info!("Imported state from {}", dir.display()),
+        Err(e) => {
+            error!("Unable to import state due to error {}", e);
+            process::exit(10)
+        }
+    }
+}
@@ -1,37 +1,58 @@
 //! Structs and functions for loading and saving configuration and storage data.
 
+#[cfg(test)]
+mod tests;
+
+use crate::reload::ConfigReceiver;
+use crate::storage;
 use http::Uri;
 use reqwest::header::HeaderValue;
-use ruma::{RoomId, UserId};
+use ruma::{RoomAliasId, RoomId, UserId};
 use ruma_client::Session;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::env;
 use std::fmt::{Display, Formatter};
-use std::fs::{File, OpenOptions};
-use std::io::{ErrorKind, Read, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
 use std::process;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 use tracing::{error, info, trace};
 
 /// Constant representing the crate name.
 pub const NAME: &str = env!("CARGO_PKG_NAME");
 /// Constant representing the crate version.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Command prefix used if not set in the config.
+const DEFAULT_COMMAND_PREFIX: &str = "!";
+/// Names of all commandless actions in the order they run if not set in the config.
+pub const TRIGGER_NAMES: &[&str] = &[
+    "unit-conversion",
+    "github-search",
+    "github-url",
+    "link",
+    "ping",
+];
+/// Number of events handled at the same time if not set in the config.
+const DEFAULT_MAX_CONCURRENT_HANDLERS: usize = 4;
+/// Number of seconds GitHub search results are reused for if not set in the config.
+const DEFAULT_GITHUB_CACHE_SECONDS: u64 = 600;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// Configuration struct used at runtime. Loaded from RawConfig and its constituent parts.
 ///
 /// Does not have Option<T> fields for ease of use. If its blank it will be a default value or empty.
+/// Use `for_room` to get the settings for the room an event came from.
 pub struct MatrixListenerConfig {
     /// Matrix bot account homeserver URL.
     pub mx_url: Uri,
     /// Matrix bot account username.
     pub mx_uname: UserId,
     /// Matrix bot account password.
-    pub mx_pass: String,
+    pub mx_pass: Option<String>,
     /// Github access token as string.
     pub gh_access_token: String,
     /// Bool used to determine if unit conversions will be supported from plain text messages.
@@ -61,10 +82,69 @@ pub struct MatrixListenerConfig {
     pub group_pings: HashMap<String, HashSet<UserId>>,
     /// Hashset containing list of users that can initiate group pings
     pub group_ping_users: HashSet<UserId>,
+    /// Maximum number of events handled at the same time across all rooms.
+    pub max_concurrent_handlers: usize,
+    /// How long GitHub search results are reused for. Zero if they aren't cached.
+    pub github_cache_ttl: Duration,
+    /// Whether links to any GitHub repo are unfurled rather than only searchable repos.
+    pub github_urls_any_repo: bool,
+    /// Text a message must start with to be treated as a command.
+    pub command_prefix: String,
+    /// Commandless actions in the order they run in rooms without their own list.
+    pub trigger_order: Vec<String>,
+    /// Commandless actions in the order they run for rooms with their own list.
+    pub room_triggers: HashMap<RoomId, Vec<String>>,
+    /// Settings for rooms with their own section, with the section already applied.
+    rooms: HashMap<RoomId, MatrixListenerConfig>,
 }
 
+/// Webhook listener settings that follow config reloads
 pub struct WebhookListenerConfig {
-    pub token: String,
+    /// Most recently loaded config.
+    config: ConfigReceiver,
+}
+
+#[derive(Clone, Debug)]
+/// A single problem found while loading the config
+pub struct ConfigProblem {
+    /// Table the problem was found in. Empty if the problem is with the whole file.
+    pub path: String,
+    /// Key within the table the problem was found at. Empty if the problem is with the whole table.
+    pub key: String,
+    /// Description of the problem.
+    pub problem: String,
+    /// Code the program exits with if this is the first problem found at startup.
+    code: i32,
+}
+
+#[derive(Debug, Error)]
+/// Every problem found while loading a config file
+pub struct ConfigError {
+    /// File the config was loaded from.
+    pub file: PathBuf,
+    /// Problems in the order they were found. Never empty.
+    pub problems: Vec<ConfigProblem>,
+}
+
+#[derive(Debug, Default)]
+/// Collects problems while loading the config so they can all be reported at once
+struct Problems {
+    /// Problems found so far.
+    problems: Vec<ConfigProblem>,
+}
+
+/// Every place a single secret can be supplied from
+struct SecretSource<'a> {
+    /// Table the secret is set in.
+    path: &'static str,
+    /// Key the secret is set at. The file containing it is set at this key followed by `_file`.
+    key: &'static str,
+    /// Environment variable that takes precedence over both keys.
+    env: &'static str,
+    /// Secret set directly in the config.
+    value: Option<&'a String>,
+    /// File set in the config that contains the secret.
+    file: Option<&'a String>,
 }
 
 #[derive(Debug)]
@@ -77,7 +157,13 @@ pub struct Config {
     /// Matrix bot account username.
     pub mx_uname: UserId,
     /// Matrix bot account password.
-    pub mx_pass: String,
+    pub mx_pass: Option<String>,
+    /// Matrix bot account access token. Used instead of logging in with a password if set.
+    pub mx_access_token: Option<String>,
+    /// Bool used to determine if devices previously created by the bot are deleted after login.
+    pub mx_delete_old_devices: bool,
+    /// Bool used to determine if devices without a display name are deleted along with old devices.
+    pub mx_delete_unnamed_devices: bool,
     /// Github access token as string.
     gh_access_token: String,
     /// Bool used to determine if unit conversions will be supported from plain text messages.
@@ -91,12 +177,12 @@ pub struct Config {
     /// Text used in spellcheck correction feature.
     correction_text: String,
     /// List of all rooms to be excluded from spellcheck correction feature.
-    correction_exclusion: HashSet<RoomId>,
+    correction_exclusion: HashSet<RoomIdOrAlias>,
     /// List of all words that can be used to link URLs.
     linkers: HashSet<String>,
     /// List of matrix users that can invite the bot to rooms.
     admins: HashSet<UserId>,
-    help_rooms: HashSet<RoomId>,
+    help_rooms: HashSet<RoomIdOrAlias>,
     /// Hashmap containing short name for a repo as a key and the org/repo as a value.
     repos: HashMap<String, String>,
     /// Hashmap containing searched key and matching URL for linking.
@@ -107,9 +193,75 @@ pub struct Config {
     group_pings: HashMap<String, HashSet<UserId>>,
     /// Hashset containing list of users that can initiate group pings
     group_ping_users: HashSet<UserId>,
+    /// Maximum number of events handled at the same time across all rooms.
+    max_concurrent_handlers: usize,
+    /// How long GitHub search results are reused for. Zero if they aren't cached.
+    github_cache_ttl: Duration,
+    /// Whether links to any GitHub repo are unfurled rather than only searchable repos.
+    github_urls_any_repo: bool,
+    /// Text a message must start with to be treated as a command.
+    command_prefix: String,
+    /// Commandless actions in the order they run in rooms without their own list.
+    trigger_order: Vec<String>,
+    /// Commandless actions in the order they run for rooms with their own list.
+    room_triggers: HashMap<RoomIdOrAlias, Vec<String>>,
+    /// Settings that replace the global ones in specific rooms.
+    rooms: HashMap<RoomIdOrAlias, RoomConfig>,
+    /// Room crashes of the bot's subsystems are reported to.
+    pub crash_report_room: Option<RoomIdOrAlias>,
     pub webhook_token: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// A room given in the config by its id or by one of its aliases
+pub enum RoomIdOrAlias {
+    /// Internal id of the room.
+    Id(RoomId),
+    /// Alias that has to be resolved to find the room.
+    Alias(RoomAliasId),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Settings that replace the global ones in a single room. Unset settings use the global value.
+struct RoomConfig {
+    /// Bool used to determine if unit conversions will be supported from plain text messages.
+    enable_unit_conversions: Option<bool>,
+    /// Bool used to determine if the corrections feature is enabled or not.
+    enable_corrections: Option<bool>,
+    /// Hashmap containing short name for a repo as a key and the org/repo as a value.
+    repos: Option<HashMap<String, String>>,
+    /// Hashmap containing searched key and matching URL for linking.
+    links: Option<HashMap<String, Uri>>,
+    /// Expanded group pings and every user that is part of a group.
+    group_pings: Option<GroupPingSettings>,
+}
+
+#[derive(Debug, Default)]
+/// Names added, removed, and changed in a keyed section of the config
+pub struct KeyDiff {
+    /// Names only in the new config.
+    pub added: Vec<String>,
+    /// Names only in the old config.
+    pub removed: Vec<String>,
+    /// Names in both configs with a different value.
+    pub changed: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+/// Differences between a running config and a newly loaded one
+pub struct ConfigDiff {
+    /// Changes to searchable repos.
+    pub repos: KeyDiff,
+    /// Changes to linkable urls.
+    pub links: KeyDiff,
+    /// Changes to group pings.
+    pub groups: KeyDiff,
+    /// Other settings that changed and were applied.
+    pub settings: Vec<&'static str>,
+    /// Settings that changed but only take effect after a restart.
+    pub restart_required: Vec<&'static str>,
+}
+
 #[derive(Debug, Deserialize)]
 /// Struct that represents on disk configuration data.
 ///
@@ -127,14 +279,44 @@ pub struct RawConfig {
     linkable_urls: Option<HashMap<String, String>>,
     /// Hashmap containing group ping name as key and list of user IDs as the value.
     group_pings: Option<HashMap<String, Vec<String>>>,
+    /// Contains struct for enabling and ordering commandless actions.
+    commandless: Option<RawCommandless>,
+    /// Hashmap containing a room id or alias as key and the settings that replace the global ones in it.
+    rooms: Option<HashMap<String, RawRoom>>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Struct that contains raw commandless action config data.
+struct RawCommandless {
+    /// Commandless actions to run and the order to run them in.
+    order: Option<Vec<String>>,
+    /// Hashmap containing a room id or alias as key and the commandless actions to run in it as the value.
+    rooms: Option<HashMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Struct that contains raw configuration data for a single room.
+struct RawRoom {
+    /// Bool used to determine if unit conversions will be supported from plain text messages.
+    enable_unit_conversions: Option<bool>,
+    /// Bool used to determine if the corrections feature is enabled or not.
+    enable_corrections: Option<bool>,
+    /// Commandless actions to run in the room and the order to run them in.
+    commandless: Option<Vec<String>>,
+    /// Hashmap containing short name for a repo as a key and the org/repo as a value.
+    searchable_repos: Option<HashMap<String, String>>,
+    /// Hashmap containing searched key and matching URL for linking.
+    linkable_urls: Option<HashMap<String, String>>,
+    /// Hashmap containing group ping name as key and list of user IDs as the value.
+    group_pings: Option<HashMap<String, Vec<String>>>,
 }
 
 #[derive(Debug, Deserialize)]
 /// Struct that contains raw general configuration data.
 struct RawGeneral {
     /// List of matrix users that can invite the bot to rooms.
-    authorized_users: Option<HashSet<UserId>>,
-    help_rooms: Option<HashSet<RoomId>>,
+    authorized_users: Option<HashSet<String>>,
+    help_rooms: Option<HashSet<String>>,
     /// Bool used to determine if unit conversions will be supported from plain text messages.
     enable_unit_conversions: bool,
     /// Bool used to determine if the corrections feature is enabled or not.
@@ -148,10 +330,23 @@ struct RawGeneral {
     /// Text used in spellcheck correction feature. Requires two '{}' to operate properly.
     correction_text: Option<String>,
     /// List of all rooms to be excluded from spellcheck correction feature.
-    correction_exclusion: Option<HashSet<RoomId>>,
+    correction_exclusion: Option<HashSet<String>>,
     /// List of all words that can be used to link URLs.
     link_matchers: Option<HashSet<String>>,
-    webhook_token: String,
+    /// Maximum number of events handled at the same time across all rooms.
+    max_concurrent_handlers: Option<usize>,
+    /// Number of seconds GitHub search results are reused for. 0 disables the cache.
+    github_cache_seconds: Option<u64>,
+    /// Whether links to any GitHub repo are unfurled rather than only searchable repos.
+    github_urls_any_repo: Option<bool>,
+    /// Text a message must start with to be treated as a command.
+    command_prefix: Option<String>,
+    /// Room crashes of the bot's subsystems are reported to.
+    crash_report_room: Option<String>,
+    /// Token webhooks must supply. Required unless set by file or environment variable.
+    webhook_token: Option<String>,
+    /// File containing the webhook token.
+    webhook_token_file: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -160,41 +355,75 @@ struct RawMatrixAuthentication {
     /// Homeserver URL for bot account.
     url: String,
     /// Matrix username for bot account.
-    username: UserId,
-    /// Matrix password for bot account.
-    password: String,
+    username: String,
+    /// Matrix password for bot account. Required if no access token is supplied.
+    password: Option<String>,
+    /// File containing the matrix password for bot account.
+    password_file: Option<String>,
+    /// Matrix access token for bot account. Used instead of logging in with a password.
+    access_token: Option<String>,
+    /// File containing the matrix access token for bot account.
+    access_token_file: Option<String>,
+    /// Bool used to determine if devices previously created by the bot are deleted after login.
+    delete_old_devices: Option<bool>,
+    /// Bool used to determine if devices without a display name are deleted along with old devices.
+    delete_unnamed_devices: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 /// Struct that contains raw github authentication config data.
 struct RawGithubAuthentication {
     /// Access token as string.
-    access_token: String,
+    access_token: Option<String>,
+    /// File containing the access token.
+    access_token_file: Option<String>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Struct that contains the persistent matrix session
 pub struct SessionStorage {
     /// Matrix session data.
     pub session: Option<Session>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 /// Struct that contains persistent matrix listener data the bot modifies during runtime
 pub struct ListenerStorage {
     /// Last sync token.
     pub last_sync: Option<String>,
     /// Hashmap that contains a room id key and a system time of the last correction.
     pub last_correction_time: HashMap<RoomId, SystemTime>,
+    /// GitHub API budget reported by the most recent query. `None` if no query has been made.
+    #[serde(default)]
+    pub github_rate_limit: Option<GithubRateLimit>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+/// Budget of GitHub API points left as of the most recent query
+pub struct GithubRateLimit {
+    /// Points the most recent query cost.
+    pub cost: i64,
+    /// Points left until the budget resets.
+    pub remaining: i64,
+    /// Time the budget resets.
+    pub reset_at: SystemTime,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Struct that contains the last known room of every alias that has been resolved
+pub struct RoomAliasStorage {
+    /// Hashmap containing an alias as key and the id of the room it points to as the value.
+    pub aliases: HashMap<RoomAliasId, RoomId>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 /// Struct that contains persistent matrix responder data the bot modifies during runtime
 pub struct ResponderStorage {
     /// Transaction id for last sent message.
     pub last_txn_id: u64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 /// Enum you match on to determine if you are doing a case sensitive or insensitive checking
 pub enum SpellCheckKind {
     /// Variant that contains a case insesitive string
@@ -202,14 +431,14 @@ pub enum SpellCheckKind {
     /// Variant that contains a case sensitive string
     SpellCheckSensitive(SensitiveSpelling),
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 /// A struct representing a case insensitive string for comparion purposes.
 pub struct InsensitiveSpelling {
     /// The case insensitive string.
     spelling: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 /// A struct representing a case sensitive string for comparison purposes.
 pub struct SensitiveSpelling {
     /// The case sensitive string.
@@ -217,8 +446,14 @@ pub struct SensitiveSpelling {
 }
 
 impl MatrixListenerConfig {
-    pub fn new(config: &Config) -> Self {
-        Self {
+    /// Creates handler settings from a config, finding rooms given by alias in `aliases`.
+    ///
+    /// Rooms with aliases that have never been resolved are left out.
+    pub fn new(config: &Config, aliases: &RoomAliasStorage) -> Self {
+        let resolve = |rooms: &HashSet<RoomIdOrAlias>| -> HashSet<RoomId> {
+            rooms.iter().filter_map(|r| aliases.get(r)).collect()
+        };
+        let mut listener = Self {
             mx_url: config.mx_url.clone(),
             mx_uname: config.mx_uname.clone(),
             mx_pass: config.mx_pass.clone(),
@@ -228,83 +463,488 @@ impl MatrixListenerConfig {
             unit_conversion_exclusion: config.unit_conversion_exclusion.clone(),
             incorrect_spellings: config.incorrect_spellings.clone(),
             correction_text: config.correction_text.clone(),
-            correction_exclusion: config.correction_exclusion.clone(),
+            correction_exclusion: resolve(&config.correction_exclusion),
             linkers: config.linkers.clone(),
             admins: config.admins.clone(),
-            help_rooms: config.help_rooms.clone(),
+            help_rooms: resolve(&config.help_rooms),
             repos: config.repos.clone(),
             links: config.links.clone(),
             user_agent: config.user_agent.clone(),
             group_pings: config.group_pings.clone(),
             group_ping_users: config.group_ping_users.clone(),
+            max_concurrent_handlers: config.max_concurrent_handlers,
+            github_cache_ttl: config.github_cache_ttl,
+            github_urls_any_repo: config.github_urls_any_repo,
+            command_prefix: config.command_prefix.clone(),
+            trigger_order: config.trigger_order.clone(),
+            room_triggers: config
+                .room_triggers
+                .iter()
+                .filter_map(|(room, triggers)| Some((aliases.get(room)?, triggers.clone())))
+                .collect(),
+            rooms: HashMap::new(),
+        };
+        listener.rooms = config
+            .rooms
+            .iter()
+            .filter_map(|(room, settings)| Some((aliases.get(room)?, listener.with_room(settings))))
+            .collect();
+        listener
+    }
+
+    /// Returns the settings to use in a room, with its section of the config applied if it has one
+    pub fn for_room(&self, room_id: &RoomId) -> &Self {
+        self.rooms.get(room_id).unwrap_or(self)
+    }
+
+    /// Copies these settings, replacing any that are set for a room
+    fn with_room(&self, room: &RoomConfig) -> Self {
+        let mut config = self.clone();
+        if let Some(v) = room.enable_unit_conversions {
+            config.enable_unit_conversions = v;
+        }
+        if let Some(v) = room.enable_corrections {
+            config.enable_corrections = v;
         }
+        if let Some(v) = &room.repos {
+            config.repos = v.clone();
+        }
+        if let Some(v) = &room.links {
+            config.links = v.clone();
+        }
+        if let Some((groups, users)) = &room.group_pings {
+            config.group_pings = groups.clone();
+            config.group_ping_users = users.clone();
+        }
+        config
+    }
+}
+
+impl WebhookListenerConfig {
+    /// Creates webhook settings that always use the most recently loaded config
+    pub fn new(config: ConfigReceiver) -> Self {
+        Self { config }
+    }
+
+    /// Token requests must supply to send messages
+    pub fn token(&self) -> String {
+        self.config.borrow().webhook_token.clone()
+    }
+}
+
+impl ConfigError {
+    /// Creates an error for a problem that prevents the file from being loaded at all
+    fn file(file: &Path, code: i32, problem: String) -> Self {
+        let mut problems = Problems::default();
+        problems.add(code, "", "", problem);
+        Self {
+            file: file.to_path_buf(),
+            problems: problems.problems,
+        }
+    }
+
+    /// Code to exit with at startup. Taken from the first problem found.
+    pub fn code(&self) -> i32 {
+        self.problems.first().map(|p| p.code).unwrap_or(1)
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Found {} problem(s) in {}",
+            self.problems.len(),
+            self.file.display()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n\t{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.path.is_empty(), self.key.is_empty()) {
+            (true, _) => write!(f, "{}", self.problem),
+            (false, true) => write!(f, "[{}] {}", self.path, self.problem),
+            (false, false) => write!(f, "[{}] {}: {}", self.path, self.key, self.problem),
+        }
+    }
+}
+
+impl Problems {
+    /// Records a problem at a key in a table of the config
+    fn add(&mut self, code: i32, path: &str, key: &str, problem: impl Into<String>) {
+        self.problems.push(ConfigProblem {
+            path: path.to_string(),
+            key: key.to_string(),
+            problem: problem.into(),
+            code,
+        });
+    }
+
+    /// Returns the value if it is valid, otherwise records the error as a problem
+    fn check<T, E: Display>(
+        &mut self,
+        code: i32,
+        path: &str,
+        key: &str,
+        result: Result<T, E>,
+    ) -> Option<T> {
+        match result {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.add(code, path, key, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Returns `true` if no problems have been found
+    fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl SecretSource<'_> {
+    /// Returns the value of the environment variable if it is set and not empty
+    fn env_value(&self) -> Option<String> {
+        env::var(self.env).ok().filter(|v| !v.is_empty())
+    }
+
+    /// Returns `true` if the secret is supplied in any way
+    fn supplied(&self) -> bool {
+        self.value.is_some() || self.file.is_some() || self.env_value().is_some()
+    }
+
+    /// Returns the secret, reading it from a file if needed.
+    ///
+    /// Relative files are found from `dir`. Returns `None` if the secret is not supplied or is
+    /// invalid, recording a problem in the latter case.
+    fn load(&self, dir: &Path, code: i32, problems: &mut Problems) -> Option<String> {
+        if let Some(v) = self.env_value() {
+            trace!("Using {} from {}", self.key, self.env);
+            return Some(v);
+        }
+        let file_key = format!("{}_file", self.key);
+        let secret = match (self.value, self.file) {
+            (None, None) => return None,
+            (Some(_), Some(_)) => {
+                problems.add(
+                    code,
+                    self.path,
+                    self.key,
+                    format!("Only one of {} and {} can be set", self.key, file_key),
+                );
+                return None;
+            }
+            (Some(v), None) => v.clone(),
+            (None, Some(file)) => {
+                let file = dir.join(file);
+                match fs::read_to_string(&file) {
+                    // Editors and `echo` usually end files with a newline that isn't part of the secret
+                    Ok(v) => v.trim_end_matches(&['\r', '\n'][..]).to_string(),
+                    Err(e) => {
+                        problems.add(
+                            code,
+                            self.path,
+                            &file_key,
+                            format!("Unable to read {} due to error {}", file.display(), e),
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+        if secret.is_empty() {
+            let key = if self.value.is_some() {
+                self.key
+            } else {
+                file_key.as_str()
+            };
+            problems.add(code, self.path, key, "Secret must not be empty");
+            return None;
+        }
+        Some(secret)
+    }
+}
+
+impl KeyDiff {
+    /// Compares the keys and values of two sections of the config
+    fn new<V: PartialEq>(old: &HashMap<String, V>, new: &HashMap<String, V>) -> Self {
+        let mut diff = Self::default();
+        for (key, value) in new {
+            match old.get(key) {
+                Some(v) if v != value => diff.changed.push(key.clone()),
+                Some(_) => (),
+                None => diff.added.push(key.clone()),
+            }
+        }
+        for key in old.keys() {
+            if !new.contains_key(key) {
+                diff.removed.push(key.clone())
+            }
+        }
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    /// Returns `true` if nothing was added, removed, or changed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for KeyDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if !self.added.is_empty() {
+            parts.push(format!("added {}", self.added.join(", ")));
+        }
+        if !self.removed.is_empty() {
+            parts.push(format!("removed {}", self.removed.join(", ")));
+        }
+        if !self.changed.is_empty() {
+            parts.push(format!("changed {}", self.changed.join(", ")));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl ConfigDiff {
+    /// Returns `true` if the new config is the same as the old one
+    pub fn is_empty(&self) -> bool {
+        self.repos.is_empty()
+            && self.links.is_empty()
+            && self.groups.is_empty()
+            && self.settings.is_empty()
+            && self.restart_required.is_empty()
+    }
+}
+
+impl Display for ConfigDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No changes");
+        }
+        let mut lines = Vec::new();
+        for (name, diff) in &[
+            ("Repos", &self.repos),
+            ("Links", &self.links),
+            ("Groups", &self.groups),
+        ] {
+            if !diff.is_empty() {
+                lines.push(format!("{}: {}", name, diff));
+            }
+        }
+        if !self.settings.is_empty() {
+            lines.push(format!("Changed: {}", self.settings.join(", ")));
+        }
+        if !self.restart_required.is_empty() {
+            lines.push(format!(
+                "Requires a restart: {}",
+                self.restart_required.join(", ")
+            ));
+        }
+        write!(f, "{}", lines.join("\n"))
     }
 }
 
 impl Config {
+    /// Lists what changed between this config and a newly loaded one
+    pub fn diff(&self, new: &Config) -> ConfigDiff {
+        let mut settings = Vec::new();
+        let mut changed = |name, differs| {
+            if differs {
+                settings.push(name)
+            }
+        };
+        changed("authorized_users", self.admins != new.admins);
+        changed("help_rooms", self.help_rooms != new.help_rooms);
+        changed(
+            "unit conversions",
+            self.enable_unit_conversions != new.enable_unit_conversions
+                || self.unit_conversion_exclusion != new.unit_conversion_exclusion,
+        );
+        changed(
+            "corrections",
+            self.enable_corrections != new.enable_corrections
+                || self.incorrect_spellings != new.incorrect_spellings
+                || self.correction_text != new.correction_text
+                || self.correction_exclusion != new.correction_exclusion,
+        );
+        changed("link_matchers", self.linkers != new.linkers);
+        changed(
+            "github access token",
+            self.gh_access_token != new.gh_access_token,
+        );
+        changed("command_prefix", self.command_prefix != new.command_prefix);
+        changed(
+            "github_cache_seconds",
+            self.github_cache_ttl != new.github_cache_ttl,
+        );
+        changed(
+            "github_urls_any_repo",
+            self.github_urls_any_repo != new.github_urls_any_repo,
+        );
+        changed(
+            "commandless",
+            self.trigger_order != new.trigger_order || self.room_triggers != new.room_triggers,
+        );
+        changed("rooms", self.rooms != new.rooms);
+        changed("webhook_token", self.webhook_token != new.webhook_token);
+
+        let mut restart_required = Vec::new();
+        let mut requires_restart = |name, differs| {
+            if differs {
+                restart_required.push(name)
+            }
+        };
+        requires_restart(
+            "matrix_authentication",
+            self.mx_url != new.mx_url
+                || self.mx_uname != new.mx_uname
+                || self.mx_pass != new.mx_pass
+                || self.mx_access_token != new.mx_access_token
+                || self.mx_delete_old_devices != new.mx_delete_old_devices
+                || self.mx_delete_unnamed_devices != new.mx_delete_unnamed_devices,
+        );
+        requires_restart(
+            "max_concurrent_handlers",
+            self.max_concurrent_handlers != new.max_concurrent_handlers,
+        );
+        requires_restart(
+            "crash_report_room",
+            self.crash_report_room != new.crash_report_room,
+        );
+
+        ConfigDiff {
+            repos: KeyDiff::new(&self.repos, &new.repos),
+            links: KeyDiff::new(&self.links, &new.links),
+            groups: KeyDiff::new(&self.group_pings, &new.group_pings),
+            settings,
+            restart_required,
+        }
+    }
+
+    /// Returns every alias used to refer to a room in the config
+    pub fn room_aliases(&self) -> HashSet<RoomAliasId> {
+        self.help_rooms
+            .iter()
+            .chain(&self.correction_exclusion)
+            .chain(self.room_triggers.keys())
+            .chain(self.rooms.keys())
+            .chain(&self.crash_report_room)
+            .filter_map(|r| r.alias().cloned())
+            .collect()
+    }
+
+    /// Returns the tables and keys that refer to a room by its id, like `[general] help_rooms`
+    pub fn room_references(&self, room_id: &RoomId) -> Vec<String> {
+        let room = RoomIdOrAlias::Id(room_id.clone());
+        let mut references = Vec::new();
+        if self.help_rooms.contains(&room) {
+            references.push("[general] help_rooms".to_string());
+        }
+        if self.correction_exclusion.contains(&room) {
+            references.push("[general] correction_exclusion".to_string());
+        }
+        if self.crash_report_room.as_ref() == Some(&room) {
+            references.push("[general] crash_report_room".to_string());
+        }
+        if self.room_triggers.contains_key(&room) {
+            references.push(format!("[commandless.rooms] {}", room_id));
+        }
+        if self.rooms.contains_key(&room) {
+            references.push(format!("[{}]", room_path(room_id.as_str())));
+        }
+        references
+    }
+
     /// Loads bot config from config.toml.
     ///
-    /// Exits program if loading fails.
+    /// Exits program if loading fails, after logging every problem found.
+    pub fn load_config() -> Self {
+        match Self::try_load_config() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{}", e);
+                process::exit(e.code())
+            }
+        }
+    }
+
+    /// Loads bot config from config.toml, returning every problem found instead of exiting.
+    pub fn try_load_config() -> Result<Self, ConfigError> {
+        Self::load_from(&config_path())
+    }
+
+    /// Loads bot config from the supplied file, returning every problem found.
     ///
     /// Due to the desired structure of the config.toml, this function loads configuration from
     /// a number intermediate structs into the final config struct type used by the program.
     ///
     /// If something is disabled, the value in the final struct is just "new" or "blank" but
     /// does not utilize Option<T> for ease of use and matching later on in the program.
-    pub fn load_config() -> Self {
-        let path = match env::var("MATRIX_BOT_CONFIG_DIR") {
-            Ok(v) => [&v, "config.toml"].iter().collect::<PathBuf>(),
-            Err(_) => ["config.toml"].iter().collect::<PathBuf>(),
-        };
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
         // File Load Section
         let mut file = match File::open(path) {
             Ok(v) => v,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    error!("Unable to find file config.toml");
-                    process::exit(1);
-                }
-                ErrorKind::PermissionDenied => {
-                    error!("Permission denied when opening file config.toml");
-                    process::exit(1);
-                }
-                _ => {
-                    error!("Unable to open file due to unexpected error {:?}", e);
-                    process::exit(1);
-                }
-            },
-        };
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Ok(_) => (), // If read is successful, do nothing
             Err(e) => {
-                error!("Unable to read file contents due to error {:?}", e);
-                process::exit(2)
+                let problem = match e.kind() {
+                    ErrorKind::NotFound => "Unable to find file".to_string(),
+                    ErrorKind::PermissionDenied => {
+                        "Permission denied when opening file".to_string()
+                    }
+                    _ => format!("Unable to open file due to unexpected error {:?}", e),
+                };
+                return Err(ConfigError::file(path, 1, problem));
             }
+        };
+        let mut contents = String::new();
+        if let Err(e) = file.read_to_string(&mut contents) {
+            return Err(ConfigError::file(
+                path,
+                2,
+                format!("Unable to read file contents due to error {:?}", e),
+            ));
         }
         let toml: RawConfig = match toml::from_str(&contents) {
             Ok(v) => v,
-            Err(e) => {
-                error!("Invalid toml. Error is {:?}", e);
-                process::exit(3)
-            }
+            Err(e) => return Err(ConfigError::file(path, 3, format!("Invalid toml: {}", e))),
         };
 
-        // Set variables and exit/error if set improperly
-        let (repos, gh_access_token) = load_github_settings(&toml);
-        let (linkers, links) = load_linker_settings(&toml);
+        // Set variables and record every setting that is set improperly
+        let mut problems = Problems::default();
+        // Secret files are found relative to the config file
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let (repos, gh_access_token) = load_github_settings(&toml, dir, &mut problems);
+        let (linkers, links) = load_linker_settings(&toml, &mut problems);
         let unit_conversion_exclusion = load_unit_conversion_settings(&toml);
         let (incorrect_spellings, correction_text, correction_exclusion) =
-            load_spell_correct_settings(&toml);
-        let admins = load_admin_settings(&toml);
-        let help_rooms = load_help_settings(&toml);
-        let (mx_url, mx_uname, mx_pass, enable_corrections, enable_unit_conversions) = (
-            toml.matrix_authentication
-                .url
-                .parse()
-                .expect("Invalid homeserver URL"),
-            toml.matrix_authentication.username.clone(),
-            toml.matrix_authentication.password.clone(),
+            load_spell_correct_settings(&toml, &mut problems);
+        let admins = load_admin_settings(&toml, &mut problems);
+        let help_rooms = load_help_settings(&toml, &mut problems);
+        let (mx_pass, mx_access_token, mx_delete_old_devices, mx_delete_unnamed_devices) =
+            load_matrix_auth_settings(&toml, dir, &mut problems);
+        let mx_url = parse_url(
+            &mut problems,
+            11,
+            "matrix_authentication",
+            "url",
+            &toml.matrix_authentication.url,
+        );
+        let mx_uname = problems.check(
+            11,
+            "matrix_authentication",
+            "username",
+            UserId::try_from(toml.matrix_authentication.username.as_str()),
+        );
+        let (enable_corrections, enable_unit_conversions) = (
             toml.general.enable_corrections,
             toml.general.enable_unit_conversions,
         );
@@ -318,30 +958,98 @@ impl Config {
                 ),
             };
 
-        let (group_pings, group_ping_users) = load_group_ping_settings(&toml);
-        let webhook_token = toml.general.webhook_token;
+        let (group_pings, group_ping_users) = load_group_ping_settings(&toml, &mut problems);
+        let max_concurrent_handlers = load_concurrency_settings(&toml, &mut problems);
+        let github_cache_ttl = Duration::from_secs(
+            toml.general
+                .github_cache_seconds
+                .unwrap_or(DEFAULT_GITHUB_CACHE_SECONDS),
+        );
+        let github_urls_any_repo = toml.general.github_urls_any_repo.unwrap_or(false);
+        let command_prefix = load_command_prefix_settings(&toml, &mut problems);
+        let (trigger_order, room_triggers) = load_trigger_settings(&toml, &mut problems);
+        let rooms = load_room_settings(&toml, &linkers, &mut problems);
+        let crash_report_room = toml.general.crash_report_room.as_ref().and_then(|v| {
+            problems.check(
+                13,
+                "general",
+                "crash_report_room",
+                RoomIdOrAlias::try_from(v.as_str()),
+            )
+        });
+        let webhook_token = load_webhook_settings(&toml, dir, &mut problems);
 
         // Return value
-        Config {
-            mx_url,
-            mx_uname,
-            mx_pass,
-            gh_access_token,
-            enable_unit_conversions,
-            enable_corrections,
-            unit_conversion_exclusion,
-            incorrect_spellings,
-            correction_text,
-            correction_exclusion,
-            linkers,
-            admins,
-            help_rooms,
-            repos,
-            links,
-            user_agent,
-            group_pings,
-            group_ping_users,
-            webhook_token,
+        match (mx_url, mx_uname) {
+            (Some(mx_url), Some(mx_uname)) if problems.is_empty() => Ok(Config {
+                mx_url,
+                mx_uname,
+                mx_pass,
+                mx_access_token,
+                mx_delete_old_devices,
+                mx_delete_unnamed_devices,
+                gh_access_token,
+                enable_unit_conversions,
+                enable_corrections,
+                unit_conversion_exclusion,
+                incorrect_spellings,
+                correction_text,
+                correction_exclusion,
+                linkers,
+                admins,
+                help_rooms,
+                repos,
+                links,
+                user_agent,
+                group_pings,
+                group_ping_users,
+                max_concurrent_handlers,
+                github_cache_ttl,
+                github_urls_any_repo,
+                command_prefix,
+                trigger_order,
+                room_triggers,
+                rooms,
+                crash_report_room,
+                webhook_token,
+            }),
+            _ => Err(ConfigError {
+                file: path.to_path_buf(),
+                problems: problems.problems,
+            }),
+        }
+    }
+}
+
+impl RoomIdOrAlias {
+    /// Returns the alias if the room was given by one
+    pub fn alias(&self) -> Option<&RoomAliasId> {
+        match self {
+            Self::Id(_) => None,
+            Self::Alias(v) => Some(v),
+        }
+    }
+}
+
+impl TryFrom<&str> for RoomIdOrAlias {
+    type Error = String;
+
+    fn try_from(room: &str) -> Result<Self, Self::Error> {
+        if let Ok(v) = RoomId::try_from(room) {
+            return Ok(Self::Id(v));
+        }
+        match RoomAliasId::try_from(room) {
+            Ok(v) => Ok(Self::Alias(v)),
+            Err(_) => Err(format!("{} is not a room id or alias", room)),
+        }
+    }
+}
+
+impl Display for RoomIdOrAlias {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(v) => write!(f, "{}", v),
+            Self::Alias(v) => write!(f, "{}", v),
         }
     }
 }
@@ -349,83 +1057,68 @@ impl Config {
 impl SessionStorage {
     /// Load of bot storage. Used only for startup.
     ///
-    /// If the file doesnt exist, creates and writes a default storage file.
+    /// Returns an empty session if none has been saved yet.
     ///
-    /// If file exists, attempts load and will exit program if it fails.
+    /// Exits the program if the storage database cannot be read.
     pub fn load_storage() -> Self {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "session.ron".to_string()].iter().collect::<PathBuf>(),
-            Err(_) => ["session.ron"].iter().collect::<PathBuf>(),
-        };
-        let mut file = match File::open(path) {
+        match storage::store().run(|s| s.load_session()) {
             Ok(v) => v,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    let ron = Self::default();
-                    trace!("The next save is a default save");
-                    Self::save(&ron);
-                    return ron;
-                }
-                ErrorKind::PermissionDenied => {
-                    error!("Permission denied when opening file session.ron");
-                    process::exit(1);
-                }
-                _ => {
-                    error!("Unable to open file: {}", e);
-                    process::exit(1);
-                }
-            },
-        };
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Ok(_) => (), // If read is successful, do nothing
             Err(e) => {
-                error!("Unable to read file contents: {}", e);
-                process::exit(2)
-            }
-        }
-        let ron: Self = match ron::from_str(&contents) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to load session.ron due to invalid ron: {}", e);
+                error!("Unable to load session data due to error {}", e);
                 process::exit(3)
             }
-        };
-        ron
+        }
     }
-    /// Saves all bot associated storage data.
+    /// Queues a save of all bot associated storage data.
     ///
     /// One of the few functions that can terminate the program if it doesnt go well.
     pub fn save(&self) {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "session.ron".to_string()].iter().collect::<PathBuf>(),
-            Err(_) => ["session.ron"].iter().collect::<PathBuf>(),
-        };
-        let ron = match ron::to_string(self) {
-            Ok(v) => v,
+        let storage = self.clone();
+        storage::store().queue(move |s| match s.save_session(&storage) {
+            Ok(_) => trace!("Saved session!"),
             Err(e) => {
-                error!(
-                    "Unable to format session.ron as ron, this should never occur. Error is {}",
-                    e
-                );
-                process::exit(7)
+                error!("Unable to write session data: {}", e);
+                process::exit(10)
             }
-        };
-        let mut file = match OpenOptions::new().write(true).create(true).open(path) {
+        })
+    }
+}
+
+impl RoomAliasStorage {
+    /// Load of bot storage. Used only for startup.
+    ///
+    /// Returns no aliases if none have been saved yet.
+    ///
+    /// Exits the program if the storage database cannot be read.
+    pub fn load_storage() -> Self {
+        match storage::store().run(|s| s.load_room_aliases()) {
             Ok(v) => v,
             Err(e) => {
-                error!("Unable to open session.ron due to error {:?}", e);
-                process::exit(9)
-            }
-        };
-        match file.write_all(ron.as_bytes()) {
-            Ok(_) => {
-                trace!("Saved Session!");
+                error!("Unable to load room aliases due to error {}", e);
+                process::exit(3)
             }
+        }
+    }
+
+    /// Queues a save of every known alias.
+    ///
+    /// One of the few functions that can terminate the program if it doesnt go well.
+    pub fn save_storage(&self) {
+        let storage = self.clone();
+        storage::store().queue(move |s| match s.save_room_aliases(&storage) {
+            Ok(_) => trace!("Saved room aliases!"),
             Err(e) => {
-                error!("Unable to write session data: {}", e);
+                error!("Unable to write room aliases: {}", e);
                 process::exit(10)
             }
+        })
+    }
+
+    /// Returns the id of a room, looking up the last known room for aliases
+    pub fn get(&self, room: &RoomIdOrAlias) -> Option<RoomId> {
+        match room {
+            RoomIdOrAlias::Id(v) => Some(v.clone()),
+            RoomIdOrAlias::Alias(v) => self.aliases.get(v).cloned(),
         }
     }
 }
@@ -433,92 +1126,65 @@ impl SessionStorage {
 impl ListenerStorage {
     /// Load of bot storage. Used only for startup.
     ///
-    /// If the file doesnt exist, creates and writes a default storage file.
+    /// Returns default data if none has been saved yet.
     ///
-    /// If file exists, attempts load and will exit program if it fails.
+    /// Exits the program if the storage database cannot be read.
     pub fn load_storage() -> Self {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "matrix_listener.ron".to_string()]
-                .iter()
-                .collect::<PathBuf>(),
-            Err(_) => ["matrix_listener.ron"].iter().collect::<PathBuf>(),
-        };
-        let mut file = match File::open(path) {
-            Ok(v) => v,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    let ron = Self::default();
-                    trace!("The next save is a default save");
-                    Self::save_storage(&ron);
-                    return ron;
-                }
-                ErrorKind::PermissionDenied => {
-                    error!("Permission denied when opening file matrix_listener.ron");
-                    process::exit(1);
-                }
-                _ => {
-                    error!("Unable to open file: {}", e);
-                    process::exit(1);
-                }
-            },
-        };
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Ok(_) => (), // If read is successful, do nothing
-            Err(e) => {
-                error!("Unable to read file contents: {}", e);
-                process::exit(2)
-            }
-        }
-        let ron: Self = match ron::from_str(&contents) {
+        match storage::store().run(|s| s.load_listener()) {
             Ok(v) => v,
             Err(e) => {
-                error!(
-                    "Unable to load matrix_listener.ron due to invalid ron: {}",
-                    e
-                );
+                error!("Unable to load matrix listener data due to error {}", e);
                 process::exit(3)
             }
-        };
-        ron
+        }
     }
 
-    /// Saves all bot associated storage data.
+    /// Queues a save of all bot associated storage data.
     ///
     /// One of the few functions that can terminate the program if it doesnt go well.
     pub fn save_storage(&self) {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "matrix_listener.ron".to_string()]
-                .iter()
-                .collect::<PathBuf>(),
-            Err(_) => ["matrix_listener.ron"].iter().collect::<PathBuf>(),
-        };
-        let ron = match ron::to_string(self) {
-            Ok(v) => v,
-            Err(e) => {
-                error!(
-                    "Unable to format matrix_listener.ron as ron, this should never occur. Error is {}", e
-                );
-                process::exit(7)
-            }
-        };
-        let mut file = match OpenOptions::new().write(true).create(true).open(path) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to open matrix_listener.ron due to error {:?}", e);
-                process::exit(9)
-            }
-        };
-        match file.write_all(ron.as_bytes()) {
-            Ok(_) => {
-                trace!("Saved Session!");
-            }
+        let storage = self.clone();
+        storage::store().queue(move |s| match s.save_listener(&storage) {
+            Ok(_) => trace!("Saved matrix listener data!"),
             Err(e) => {
-                error!("Unable to write matrix_listener data: {}", e);
+                error!("Unable to write matrix listener data: {}", e);
                 process::exit(10)
             }
+        })
+    }
+
+    /// Moves everything stored for a room to the room that replaced it
+    pub fn replace_room(&mut self, old_room: &RoomId, new_room: &RoomId) {
+        if let Some(v) = self.last_correction_time.remove(old_room) {
+            self.last_correction_time.insert(new_room.clone(), v);
+        }
+    }
+
+    /// Returns the time GitHub queries can resume if the next query could exceed the budget.
+    ///
+    /// GitHub restarts the cooldown if a query is made while the budget is exhausted, so the
+    /// next query is assumed to cost as much as the last one.
+    pub fn github_rate_limited_until(&self) -> Option<SystemTime> {
+        match &self.github_rate_limit {
+            Some(v) if v.remaining < v.cost && v.reset_at > SystemTime::now() => Some(v.reset_at),
+            _ => None,
         }
     }
+
+    /// Reserves the GitHub budget for one query, assuming it costs as much as the last one.
+    ///
+    /// Returns the time queries can resume instead if the query could exceed the budget. Checking
+    /// and reserving happen together so concurrent searches can't all spend the last of it.
+    pub fn reserve_github_query(&mut self) -> Result<(), SystemTime> {
+        if let Some(v) = self.github_rate_limited_until() {
+            return Err(v);
+        }
+        if let Some(v) = &mut self.github_rate_limit {
+            v.remaining -= v.cost;
+        }
+        Ok(())
+    }
+
     /// Checks that the correction time cooldown for a specific room has passed.
     ///
     /// Returns true if there has never been a correction done in the room before.
@@ -536,98 +1202,44 @@ impl ListenerStorage {
 impl ResponderStorage {
     /// Load of bot storage. Used only for startup.
     ///
-    /// If the file doesnt exist, creates and writes a default storage file.
+    /// Returns default data if none has been saved yet.
     ///
-    /// If file exists, attempts load and will exit program if it fails.
+    /// Exits the program if the storage database cannot be read.
     pub fn load_storage() -> Self {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "matrix_responder.ron".to_string()]
-                .iter()
-                .collect::<PathBuf>(),
-            Err(_) => ["matrix_responder.ron"].iter().collect::<PathBuf>(),
-        };
-        let mut file = match File::open(path) {
-            Ok(v) => v,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    let ron = Self::default();
-                    trace!("The next save is a default save");
-                    Self::save_storage(&ron);
-                    return ron;
-                }
-                ErrorKind::PermissionDenied => {
-                    error!("Permission denied when opening file matrix_responder.ron");
-                    process::exit(1);
-                }
-                _ => {
-                    error!("Unable to open file: {}", e);
-                    process::exit(1);
-                }
-            },
-        };
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Ok(_) => (), // If read is successful, do nothing
-            Err(e) => {
-                error!("Unable to read file contents: {}", e);
-                process::exit(2)
-            }
-        }
-        let ron: Self = match ron::from_str(&contents) {
+        match storage::store().run(|s| s.load_responder()) {
             Ok(v) => v,
             Err(e) => {
-                error!(
-                    "Unable to load matrix_responder.ron due to invalid ron: {}",
-                    e
-                );
+                error!("Unable to load matrix responder data due to error {}", e);
                 process::exit(3)
             }
-        };
-        ron
+        }
     }
 
-    /// Saves all bot associated storage data.
+    /// Queues a save of all bot associated storage data.
     ///
     /// One of the few functions that can terminate the program if it doesnt go well.
     pub fn save_storage(&self) {
-        let path = match env::var("MATRIX_BOT_DATA_DIR") {
-            Ok(v) => [v, "matrix_responder.ron".to_string()]
-                .iter()
-                .collect::<PathBuf>(),
-            Err(_) => ["matrix_responder.ron"].iter().collect::<PathBuf>(),
-        };
-        let ron = match ron::to_string(self) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to format matrix_responder.ron as ron, this should never occur. Error is {}", e);
-                process::exit(7)
-            }
-        };
-        let mut file = match OpenOptions::new().write(true).create(true).open(path) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Unable to open matrix_responder.ron due to error {:?}", e);
-                process::exit(9)
-            }
-        };
-        match file.write_all(ron.as_bytes()) {
-            Ok(_) => {
-                trace!("Saved Session!");
-            }
+        let storage = self.clone();
+        storage::store().queue(move |s| match s.save_responder(&storage) {
+            Ok(_) => trace!("Saved matrix responder data!"),
             Err(e) => {
-                error!("Unable to write matrix_listener data: {}", e);
+                error!("Unable to write matrix responder data: {}", e);
                 process::exit(10)
             }
-        }
+        })
     }
 
-    // FIXME: This needs to be an idempotent/unique ID per txn to be spec compliant
-    /// Sets the last_txn_id to a new value then returns it
+    /// Sets the last_txn_id to a new value then returns a transaction id built from it
     ///
-    /// Must be saved after it is used successfully or you can cause homeserver issues
+    /// Ids are prefixed with the time they were generated so they remain unique even if
+    /// storage is lost. Must be saved after it is used or the counter will be reused.
     pub fn next_txn_id(&mut self) -> String {
         self.last_txn_id += 1;
-        self.last_txn_id.to_string()
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        format!("{}.{}", millis, self.last_txn_id)
     }
 }
 
@@ -668,50 +1280,413 @@ impl Display for SensitiveSpelling {
     }
 }
 
-fn load_github_settings(toml: &RawConfig) -> (HashMap<String, String>, String) {
-    match &toml.searchable_repos {
-        Some(r) => match &toml.github_authentication {
-            Some(g) => (r.clone(), g.access_token.clone()),
-            None => {
-                error!("Searchable repos configured, but no github access token found. Unable to continue...");
-                process::exit(4)
-            }
-        },
-        None => {
-            info!("No searchable repos found. Disabling feature...");
-            (HashMap::new(), String::new())
+/// Path of config.toml, inside `MATRIX_BOT_CONFIG_DIR` if it is set
+pub fn config_path() -> PathBuf {
+    match env::var("MATRIX_BOT_CONFIG_DIR") {
+        Ok(v) => [&v, "config.toml"].iter().collect::<PathBuf>(),
+        Err(_) => ["config.toml"].iter().collect::<PathBuf>(),
+    }
+}
+
+/// Parses a URL, recording a problem if it is invalid or has no scheme
+fn parse_url(problems: &mut Problems, code: i32, path: &str, key: &str, url: &str) -> Option<Uri> {
+    match url.parse::<Uri>() {
+        Ok(v) if v.scheme().is_some() && v.host().is_some() => Some(v),
+        Ok(_) => {
+            problems.add(
+                code,
+                path,
+                key,
+                format!("URL {} must start with http:// or https://", url),
+            );
+            None
+        }
+        Err(e) => {
+            problems.add(code, path, key, format!("Invalid URL {}: {}", url, e));
+            None
+        }
+    }
+}
+
+/// Parses a list of room ids and aliases, recording a problem for each invalid one
+fn parse_room_ids(
+    problems: &mut Problems,
+    code: i32,
+    path: &str,
+    key: &str,
+    rooms: &HashSet<String>,
+) -> HashSet<RoomIdOrAlias> {
+    rooms
+        .iter()
+        .filter_map(|room| problems.check(code, path, key, RoomIdOrAlias::try_from(room.as_str())))
+        .collect()
+}
+
+/// Parses a user id, recording a problem if it is invalid
+fn parse_user_id(
+    problems: &mut Problems,
+    code: i32,
+    path: &str,
+    key: &str,
+    user: &str,
+) -> Option<UserId> {
+    problems.check(
+        code,
+        path,
+        key,
+        UserId::try_from(user).map_err(|e| format!("Invalid user id {}: {}", user, e)),
+    )
+}
+
+/// Returns `true` if a repo is in the form owner/name
+fn valid_repo(repo: &str) -> bool {
+    let parts: Vec<&str> = repo.split('/').collect();
+    parts.len() == 2
+        && parts.iter().all(|p| {
+            !p.is_empty()
+                && p.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        })
+}
+
+fn load_matrix_auth_settings(
+    toml: &RawConfig,
+    dir: &Path,
+    problems: &mut Problems,
+) -> (Option<String>, Option<String>, bool, bool) {
+    let auth = &toml.matrix_authentication;
+    let password = SecretSource {
+        path: "matrix_authentication",
+        key: "password",
+        env: "MATRIX_BOT_MATRIX_PASSWORD",
+        value: auth.password.as_ref(),
+        file: auth.password_file.as_ref(),
+    };
+    let access_token = SecretSource {
+        path: "matrix_authentication",
+        key: "access_token",
+        env: "MATRIX_BOT_MATRIX_ACCESS_TOKEN",
+        value: auth.access_token.as_ref(),
+        file: auth.access_token_file.as_ref(),
+    };
+    if !password.supplied() && !access_token.supplied() {
+        problems.add(
+            11,
+            "matrix_authentication",
+            "",
+            "You must provide either a password or an access token for the bot account. Set password, password_file, or MATRIX_BOT_MATRIX_PASSWORD, or access_token, access_token_file, or MATRIX_BOT_MATRIX_ACCESS_TOKEN",
+        );
+    }
+    let delete_old_devices = auth.delete_old_devices.unwrap_or(false);
+    if delete_old_devices && !password.supplied() {
+        problems.add(
+            11,
+            "matrix_authentication",
+            "delete_old_devices",
+            "Deleting old devices requires a password for the bot account",
+        );
+    }
+    let delete_unnamed_devices = auth.delete_unnamed_devices.unwrap_or(false);
+    if delete_unnamed_devices && !delete_old_devices {
+        problems.add(
+            11,
+            "matrix_authentication",
+            "delete_unnamed_devices",
+            "Deleting unnamed devices requires delete_old_devices to be true",
+        );
+    }
+    (
+        password.load(dir, 11, problems),
+        access_token.load(dir, 11, problems),
+        delete_old_devices,
+        delete_unnamed_devices,
+    )
+}
+
+/// Webhook token from the config, a file, or `MATRIX_BOT_WEBHOOK_TOKEN`
+fn load_webhook_settings(toml: &RawConfig, dir: &Path, problems: &mut Problems) -> String {
+    let webhook_token = SecretSource {
+        path: "general",
+        key: "webhook_token",
+        env: "MATRIX_BOT_WEBHOOK_TOKEN",
+        value: toml.general.webhook_token.as_ref(),
+        file: toml.general.webhook_token_file.as_ref(),
+    };
+    if !webhook_token.supplied() {
+        problems.add(
+            13,
+            "general",
+            "webhook_token",
+            "No webhook token found. Set webhook_token, webhook_token_file, or MATRIX_BOT_WEBHOOK_TOKEN",
+        );
+    }
+    webhook_token.load(dir, 13, problems).unwrap_or_default()
+}
+
+fn load_concurrency_settings(toml: &RawConfig, problems: &mut Problems) -> usize {
+    match toml.general.max_concurrent_handlers {
+        Some(0) => {
+            problems.add(
+                13,
+                "general",
+                "max_concurrent_handlers",
+                "Must be at least 1",
+            );
+            DEFAULT_MAX_CONCURRENT_HANDLERS
         }
+        Some(v) => v,
+        None => DEFAULT_MAX_CONCURRENT_HANDLERS,
     }
 }
 
-fn load_linker_settings(toml: &RawConfig) -> (HashSet<String>, HashMap<String, Uri>) {
-    match &toml.linkable_urls {
-        Some(d) => match &toml.general.link_matchers {
-            Some(m) => {
-                if !d.is_empty() {
-                    let d = d
-                        .iter()
-                        .map(|(k, v)| (k.clone(), v.parse().expect("Invalid URL")))
-                        .collect();
+fn load_command_prefix_settings(toml: &RawConfig, problems: &mut Problems) -> String {
+    match &toml.general.command_prefix {
+        Some(v) if v.is_empty() || v.contains(char::is_whitespace) => {
+            problems.add(
+                13,
+                "general",
+                "command_prefix",
+                "Must not be empty or contain whitespace",
+            );
+            DEFAULT_COMMAND_PREFIX.to_string()
+        }
+        Some(v) => v.clone(),
+        None => DEFAULT_COMMAND_PREFIX.to_string(),
+    }
+}
+
+/// Commandless action order for all rooms and for rooms with their own order.
+type TriggerSettings = (Vec<String>, HashMap<RoomIdOrAlias, Vec<String>>);
 
-                    (m.clone(), d)
-                } else {
-                    error!("Link matchers exists but none are set. Exiting...");
-                    process::exit(1)
+fn load_trigger_settings(toml: &RawConfig, problems: &mut Problems) -> TriggerSettings {
+    let commandless = toml.commandless.as_ref();
+    let order = match commandless.and_then(|c| c.order.as_ref()) {
+        Some(v) => validate_trigger_names(v, problems, "commandless", "order"),
+        None => TRIGGER_NAMES.iter().map(|t| t.to_string()).collect(),
+    };
+    let mut rooms = HashMap::new();
+    for (room, triggers) in commandless
+        .and_then(|c| c.rooms.as_ref())
+        .into_iter()
+        .flatten()
+    {
+        let triggers = validate_trigger_names(triggers, problems, "commandless.rooms", room);
+        if let Some(room_id) = problems.check(
+            13,
+            "commandless.rooms",
+            room,
+            RoomIdOrAlias::try_from(room.as_str()),
+        ) {
+            rooms.insert(room_id, triggers);
+        }
+    }
+    for (room, settings) in toml.rooms.iter().flatten() {
+        let triggers = match &settings.commandless {
+            Some(v) => v,
+            None => continue,
+        };
+        let path = room_path(room);
+        let triggers = validate_trigger_names(triggers, problems, &path, "commandless");
+        // Invalid room ids are recorded along with the rest of the room's settings
+        if let Ok(room_id) = RoomIdOrAlias::try_from(room.as_str()) {
+            match rooms.entry(room_id) {
+                Entry::Occupied(_) => problems.add(
+                    13,
+                    &path,
+                    "commandless",
+                    "Commandless actions for this room are also set in [commandless.rooms]",
+                ),
+                Entry::Vacant(v) => {
+                    v.insert(triggers);
                 }
             }
-            None => {
-                info!("No link matchers found. Disabling feature...");
-                (HashSet::new(), HashMap::new())
+        }
+    }
+    (order, rooms)
+}
+
+/// Settings that replace the global ones in rooms with their own section
+fn load_room_settings(
+    toml: &RawConfig,
+    linkers: &HashSet<String>,
+    problems: &mut Problems,
+) -> HashMap<RoomIdOrAlias, RoomConfig> {
+    let mut rooms = HashMap::new();
+    for (room, raw) in toml.rooms.iter().flatten() {
+        let path = room_path(room);
+        let room_id = problems.check(13, &path, "", RoomIdOrAlias::try_from(room.as_str()));
+        let repos = raw.searchable_repos.as_ref().map(|r| {
+            validate_repos(r, &format!("{}.searchable_repos", path), problems);
+            r.clone()
+        });
+        let links = raw.linkable_urls.as_ref().map(|l| {
+            if !l.is_empty() && linkers.is_empty() {
+                problems.add(
+                    1,
+                    &path,
+                    "linkable_urls",
+                    "Linkable urls are set but no link_matchers are set in [general]",
+                );
             }
-        },
+            parse_links(l, &format!("{}.linkable_urls", path), problems)
+        });
+        let group_pings = raw
+            .group_pings
+            .as_ref()
+            .map(|g| parse_group_pings(g, &format!("{}.group_pings", path), problems));
+        if let Some(room_id) = room_id {
+            rooms.insert(
+                room_id,
+                RoomConfig {
+                    enable_unit_conversions: raw.enable_unit_conversions,
+                    enable_corrections: raw.enable_corrections,
+                    repos,
+                    links,
+                    group_pings,
+                },
+            );
+        }
+    }
+    rooms
+}
+
+/// Table name of a room's section in the config
+fn room_path(room: &str) -> String {
+    format!("rooms.\"{}\"", room)
+}
+
+/// Records a problem for each of the supplied names that is not a commandless action
+fn validate_trigger_names(
+    names: &[String],
+    problems: &mut Problems,
+    path: &str,
+    key: &str,
+) -> Vec<String> {
+    let mut triggers = Vec::new();
+    for name in names {
+        let name = name.to_lowercase();
+        if !TRIGGER_NAMES.contains(&name.as_str()) {
+            problems.add(
+                13,
+                path,
+                key,
+                format!(
+                    "Unknown commandless action {}. Valid actions are {}",
+                    name,
+                    TRIGGER_NAMES.join(", ")
+                ),
+            );
+        } else if !triggers.contains(&name) {
+            triggers.push(name);
+        }
+    }
+    triggers
+}
+
+fn load_github_settings(
+    toml: &RawConfig,
+    dir: &Path,
+    problems: &mut Problems,
+) -> (HashMap<String, String>, String) {
+    let repos = match &toml.searchable_repos {
+        Some(r) => {
+            validate_repos(r, "searchable_repos", problems);
+            r.clone()
+        }
         None => {
-            info!("No linkable urls found. Disabling feature...");
-            (HashSet::new(), HashMap::new())
+            info!("No searchable repos found. Disabling feature...");
+            HashMap::new()
+        }
+    };
+    let room_repos = toml
+        .rooms
+        .iter()
+        .flatten()
+        .any(|(_, r)| r.searchable_repos.iter().any(|r| !r.is_empty()));
+    let any_repo = toml.general.github_urls_any_repo.unwrap_or(false);
+    if toml.searchable_repos.is_none() && !room_repos && !any_repo {
+        return (repos, String::new());
+    }
+    let auth = toml.github_authentication.as_ref();
+    let access_token = SecretSource {
+        path: "github_authentication",
+        key: "access_token",
+        env: "MATRIX_BOT_GITHUB_ACCESS_TOKEN",
+        value: auth.and_then(|g| g.access_token.as_ref()),
+        file: auth.and_then(|g| g.access_token_file.as_ref()),
+    };
+    if !access_token.supplied() {
+        problems.add(
+            4,
+            "github_authentication",
+            "access_token",
+            "Searchable repos or github_urls_any_repo configured, but no github access token found. Set access_token, access_token_file, or MATRIX_BOT_GITHUB_ACCESS_TOKEN",
+        );
+        return (HashMap::new(), String::new());
+    }
+    (
+        repos,
+        access_token.load(dir, 4, problems).unwrap_or_default(),
+    )
+}
+
+/// Records a problem for each repo that is not in the form owner/name
+fn validate_repos(repos: &HashMap<String, String>, path: &str, problems: &mut Problems) {
+    for (name, repo) in repos {
+        if !valid_repo(repo) {
+            problems.add(
+                4,
+                path,
+                name,
+                format!("Repo {} must be in the form owner/name", repo),
+            );
         }
     }
 }
 
+fn load_linker_settings(
+    toml: &RawConfig,
+    problems: &mut Problems,
+) -> (HashSet<String>, HashMap<String, Uri>) {
+    // Link matchers are kept even without global links so rooms can set their own links
+    let linkers = match &toml.general.link_matchers {
+        Some(m) => m.clone(),
+        None => {
+            info!("No link matchers found. Disabling feature...");
+            HashSet::new()
+        }
+    };
+    let links = match &toml.linkable_urls {
+        Some(d) if d.is_empty() && !linkers.is_empty() => {
+            problems.add(
+                1,
+                "linkable_urls",
+                "",
+                "Link matchers exist but no linkable urls are set",
+            );
+            HashMap::new()
+        }
+        Some(d) => parse_links(d, "linkable_urls", problems),
+        None => {
+            info!("No linkable urls found. Disabling feature...");
+            HashMap::new()
+        }
+    };
+    (linkers, links)
+}
+
+/// Parses linkable urls, recording a problem for each invalid one
+fn parse_links(
+    links: &HashMap<String, String>,
+    path: &str,
+    problems: &mut Problems,
+) -> HashMap<String, Uri> {
+    links
+        .iter()
+        .filter_map(|(k, v)| parse_url(problems, 1, path, k, v).map(|v| (k.clone(), v)))
+        .collect()
+}
+
 fn load_unit_conversion_settings(toml: &RawConfig) -> HashSet<String> {
     match &toml.general.unit_conversion_exclusion {
         Some(v) => {
@@ -728,90 +1703,88 @@ fn load_unit_conversion_settings(toml: &RawConfig) -> HashSet<String> {
     }
 }
 
-fn load_spell_correct_settings(toml: &RawConfig) -> (Vec<SpellCheckKind>, String, HashSet<RoomId>) {
-    if toml.general.enable_corrections {
-        match &toml.general.insensitive_corrections {
-            Some(i) => match &toml.general.sensitive_corrections {
-                Some(s) => match &toml.general.correction_text {
-                    Some(c) => match &toml.general.correction_exclusion {
-                        Some(e) => {
-                            let e = if !e.is_empty() {
-                                e.clone()
-                            } else {
-                                info!(
-                                    "Empty list found. No rooms will be excluded from corrections"
-                                );
-                                HashSet::new()
-                            };
-                            let mut spk = Vec::new();
-                            for spelling in i {
-                                spk.push(SpellCheckKind::SpellCheckInsensitive(
-                                    InsensitiveSpelling {
-                                        spelling: spelling.clone(),
-                                    },
-                                ));
-                            }
-                            for spelling in s {
-                                spk.push(SpellCheckKind::SpellCheckSensitive(SensitiveSpelling {
-                                    spelling: spelling.clone(),
-                                }));
-                            }
-                            (spk, c.to_string(), e)
-                        }
-                        None => {
-                            let mut spk = Vec::new();
-                            for spelling in i {
-                                spk.push(SpellCheckKind::SpellCheckInsensitive(
-                                    InsensitiveSpelling {
-                                        spelling: spelling.clone(),
-                                    },
-                                ));
-                            }
-                            for spelling in s {
-                                spk.push(SpellCheckKind::SpellCheckSensitive(SensitiveSpelling {
-                                    spelling: spelling.clone(),
-                                }));
-                            }
-                            info!("No list found. No rooms will be excluded from corrections");
-                            (spk, c.to_string(), HashSet::new())
-                        }
-                    },
-                    None => {
-                        error!(
-                            "No correction text provided even though corrections have been enabled"
-                        );
-                        process::exit(5)
-                    }
-                },
-                None => {
-                    error!("No case sensitive corrections provided even though corrections have been enabled");
-                    process::exit(5)
-                }
-            },
-            None => {
-                error!("No case insensitive corrections provided even though corrections have been enabled");
-                process::exit(5)
-            }
-        }
-    } else {
+fn load_spell_correct_settings(
+    toml: &RawConfig,
+    problems: &mut Problems,
+) -> (Vec<SpellCheckKind>, String, HashSet<RoomIdOrAlias>) {
+    let general = &toml.general;
+    if !general.enable_corrections {
         info!("Disabling corrections feature");
-        (Vec::new(), String::new(), HashSet::new())
+        return (Vec::new(), String::new(), HashSet::new());
+    }
+    let required = [
+        (
+            "insensitive_corrections",
+            general.insensitive_corrections.is_none(),
+        ),
+        (
+            "sensitive_corrections",
+            general.sensitive_corrections.is_none(),
+        ),
+        ("correction_text", general.correction_text.is_none()),
+    ];
+    for (key, missing) in &required {
+        if *missing {
+            problems.add(
+                5,
+                "general",
+                key,
+                "Must be set when corrections are enabled",
+            );
+        }
+    }
+    let exclusion = match &general.correction_exclusion {
+        Some(e) if !e.is_empty() => {
+            parse_room_ids(problems, 5, "general", "correction_exclusion", e)
+        }
+        Some(_) => {
+            info!("Empty list found. No rooms will be excluded from corrections");
+            HashSet::new()
+        }
+        None => {
+            info!("No list found. No rooms will be excluded from corrections");
+            HashSet::new()
+        }
+    };
+    let mut spk = Vec::new();
+    for spelling in general.insensitive_corrections.iter().flatten() {
+        spk.push(SpellCheckKind::SpellCheckInsensitive(InsensitiveSpelling {
+            spelling: spelling.clone(),
+        }));
+    }
+    for spelling in general.sensitive_corrections.iter().flatten() {
+        spk.push(SpellCheckKind::SpellCheckSensitive(SensitiveSpelling {
+            spelling: spelling.clone(),
+        }));
     }
+    (
+        spk,
+        general.correction_text.clone().unwrap_or_default(),
+        exclusion,
+    )
 }
 
-fn load_admin_settings(toml: &RawConfig) -> HashSet<UserId> {
+fn load_admin_settings(toml: &RawConfig, problems: &mut Problems) -> HashSet<UserId> {
     match &toml.general.authorized_users {
-        Some(v) => v.clone(),
-        None => {
-            error!("You must provide at least 1 authorized user");
-            process::exit(6)
+        Some(v) if !v.is_empty() => v
+            .iter()
+            .filter_map(|user| parse_user_id(problems, 6, "general", "authorized_users", user))
+            .collect(),
+        _ => {
+            problems.add(
+                6,
+                "general",
+                "authorized_users",
+                "You must provide at least 1 authorized user",
+            );
+            HashSet::new()
         }
     }
 }
 
-fn load_help_settings(toml: &RawConfig) -> HashSet<RoomId> {
+fn load_help_settings(toml: &RawConfig, problems: &mut Problems) -> HashSet<RoomIdOrAlias> {
     match &toml.general.help_rooms {
-        Some(v) => v.clone(),
+        Some(v) => parse_room_ids(problems, 13, "general", "help_rooms", v),
         None => {
             info!("No help rooms specified. Allowing all rooms.");
             HashSet::new()
@@ -819,65 +1792,54 @@ fn load_help_settings(toml: &RawConfig) -> HashSet<RoomId> {
     }
 }
 
-fn load_group_ping_settings(
-    toml: &RawConfig,
-) -> (HashMap<String, HashSet<UserId>>, HashSet<UserId>) {
+/// Expanded group pings and every user that is part of a group.
+type GroupPingSettings = (HashMap<String, HashSet<UserId>>, HashSet<UserId>);
+
+fn load_group_ping_settings(toml: &RawConfig, problems: &mut Problems) -> GroupPingSettings {
     match &toml.group_pings {
-        Some(v) => {
-            let mut group_ping_users = HashSet::new();
-            let groups = v.clone();
-            for group in groups {
-                for user in group.1 {
-                    if user.starts_with('@') {
-                        let user_id = UserId::try_from(user.clone()).expect(
-                            "Somehow got an alias in a part of code meant to handle UserIds",
-                        );
-                        group_ping_users.insert(user_id);
-                    }
-                }
-            }
+        Some(v) => parse_group_pings(v, "group_pings", problems),
+        None => {
+            info!("No group pings defined. Disabling feature...");
+            (HashMap::new(), HashSet::new())
+        }
+    }
+}
 
-            let mut expanded_groups: HashMap<String, HashSet<UserId>> = HashMap::new();
-            for (group, users) in v {
-                let mut expanded_users: HashSet<UserId> = HashSet::new();
-
-                for user in users {
-                    if user.starts_with('%') {
-                        // If user is an alias, expand it to list of users and insert them
-                        let alias = user.replace("%", "");
-                        match v.get(&alias) {
-                            // If list of users found, insert them
-                            Some(g) => {
-                                for u in g {
-                                    if u.starts_with('@') {
-                                        let user_id = UserId::try_from(u.clone()).expect("Somehow got an alias in a part of code meant to handle UserIds");
-                                        expanded_users.insert(user_id);
-                                    }
-                                }
-                            }
-                            // If list of users are not found, print error to console and move on
-                            None => error!(
-                                "Group alias %{} has no corresponding group. Ignoring...",
-                                alias
-                            ),
-                        }
-                    } else {
-                        // If user is not alias, just insert it
-                        let user_id = UserId::try_from(user.clone()).expect(
-                            "Somehow got an alias in a part of code meant to handle UserIds",
+/// Expands group aliases and parses users, recording a problem for each invalid one
+fn parse_group_pings(
+    groups: &HashMap<String, Vec<String>>,
+    path: &str,
+    problems: &mut Problems,
+) -> GroupPingSettings {
+    let mut group_ping_users = HashSet::new();
+    let mut expanded_groups: HashMap<String, HashSet<UserId>> = HashMap::new();
+    for (group, users) in groups {
+        let mut expanded_users: HashSet<UserId> = HashSet::new();
+        for user in users {
+            if let Some(alias) = user.strip_prefix('%') {
+                // If user is an alias, expand it to the users in the group it refers to
+                match groups.get(alias) {
+                    Some(g) => {
+                        // Users in the aliased group are checked when that group is loaded
+                        expanded_users.extend(
+                            g.iter()
+                                .filter(|u| u.starts_with('@'))
+                                .filter_map(|u| UserId::try_from(u.as_str()).ok()),
                         );
-                        expanded_users.insert(user_id);
                     }
+                    None => problems.add(
+                        7,
+                        path,
+                        group,
+                        format!("Group alias %{} has no corresponding group", alias),
+                    ),
                 }
-
-                expanded_groups.insert(group.to_string(), expanded_users);
+            } else if let Some(user_id) = parse_user_id(problems, 7, path, group, user) {
+                group_ping_users.insert(user_id.clone());
+                expanded_users.insert(user_id);
             }
-
-            (expanded_groups, group_ping_users)
-        }
-        None => {
-            info!("No group pings defined. Disabling feature...");
-            (HashMap::new(), HashSet::new())
         }
+        expanded_groups.insert(group.to_string(), expanded_users);
     }
+    (expanded_groups, group_ping_users)
 }
@@ -0,0 +1,364 @@
+use crate::config::{Config, MatrixListenerConfig, RoomAliasStorage};
+use ruma::{RoomAliasId, RoomId};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("matrix-bot-config-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+const MINIMAL: &str = r#"
+[general]
+authorized_users = ['@admin:homeserver.com']
+enable_unit_conversions = true
+enable_corrections = false
+webhook_token = "token"
+
+[matrix_authentication]
+url = 'https://matrix.homeserver.com'
+username = '@bot:homeserver.com'
+password = 'password'
+"#;
+
+#[test]
+fn sample_config_is_valid() {
+    if let Err(e) = Config::load_from(Path::new("sample_config.toml")) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn minimal_config_is_valid() {
+    let path = write_config("minimal", MINIMAL);
+    assert!(Config::load_from(&path).is_ok());
+}
+
+#[test]
+fn missing_file() {
+    let e = Config::load_from(Path::new("does/not/exist.toml")).unwrap_err();
+    assert_eq!(e.problems.len(), 1);
+    assert_eq!(e.code(), 1);
+}
+
+#[test]
+fn invalid_toml() {
+    let path = write_config("invalid_toml", "[general");
+    let e = Config::load_from(&path).unwrap_err();
+    assert_eq!(e.problems.len(), 1);
+    assert_eq!(e.code(), 3);
+}
+
+#[test]
+fn reports_every_problem() {
+    let path = write_config(
+        "every_problem",
+        r#"
+[general]
+authorized_users = ['admin']
+help_rooms = ['not-a-room']
+enable_unit_conversions = true
+enable_corrections = true
+max_concurrent_handlers = 0
+webhook_token = "token"
+
+[matrix_authentication]
+url = 'matrix.homeserver.com'
+username = '@bot:homeserver.com'
+
+[github_authentication]
+access_token = 'token'
+
+[searchable_repos]
+jf = 'jellyfin'
+
+[group_pings]
+backend = ['@user1:homeserver.com', '%missing']
+"#,
+    );
+    let e = Config::load_from(&path).unwrap_err();
+    let problems: Vec<(&str, &str)> = e
+        .problems
+        .iter()
+        .map(|p| (p.path.as_str(), p.key.as_str()))
+        .collect();
+    for expected in &[
+        ("searchable_repos", "jf"),
+        ("general", "insensitive_corrections"),
+        ("general", "sensitive_corrections"),
+        ("general", "correction_text"),
+        ("general", "authorized_users"),
+        ("general", "help_rooms"),
+        ("matrix_authentication", ""),
+        ("matrix_authentication", "url"),
+        ("group_pings", "backend"),
+        ("general", "max_concurrent_handlers"),
+    ] {
+        assert!(
+            problems.contains(expected),
+            "{:?} not found in {:?}",
+            expected,
+            problems
+        );
+    }
+    assert_eq!(problems.len(), 10);
+    // The exit code is taken from the first problem found
+    assert_eq!(e.code(), 4);
+}
+
+#[test]
+fn unknown_commandless_action() {
+    let path = write_config(
+        "unknown_action",
+        &format!(
+            "{}\n[commandless]\norder = ['link', 'dance']\n\n[commandless.rooms]\n'bad' = ['ping']\n",
+            MINIMAL
+        ),
+    );
+    let e = Config::load_from(&path).unwrap_err();
+    assert_eq!(e.problems.len(), 2);
+    assert_eq!(e.problems[0].path, "commandless");
+    assert_eq!(e.problems[0].key, "order");
+    assert_eq!(e.problems[1].path, "commandless.rooms");
+    assert_eq!(e.problems[1].key, "bad");
+}
+
+#[test]
+fn secrets_from_files() {
+    let path = write_config(
+        "secret_files",
+        &MINIMAL
+            .replace(
+                "webhook_token = \"token\"",
+                "webhook_token_file = 'webhook'",
+            )
+            .replace("password = 'password'", "password_file = 'password'"),
+    );
+    let dir = path.parent().unwrap();
+    fs::write(dir.join("webhook"), "webhook-secret\n").unwrap();
+    fs::write(dir.join("password"), "password-secret").unwrap();
+    let config = Config::load_from(&path).unwrap();
+    assert_eq!(config.webhook_token, "webhook-secret");
+    assert_eq!(config.mx_pass.as_deref(), Some("password-secret"));
+}
+
+#[test]
+fn secret_problems() {
+    let path = write_config(
+        "secret_problems",
+        &MINIMAL.replace("webhook_token = \"token\"", "").replace(
+            "password = 'password'",
+            "password = 'password'\npassword_file = 'password'\naccess_token_file = 'missing'",
+        ),
+    );
+    let e = Config::load_from(&path).unwrap_err();
+    let problems: Vec<(&str, &str)> = e
+        .problems
+        .iter()
+        .map(|p| (p.path.as_str(), p.key.as_str()))
+        .collect();
+    assert_eq!(
+        problems,
+        vec![
+            ("matrix_authentication", "password"),
+            ("matrix_authentication", "access_token_file"),
+            ("general", "webhook_token"),
+        ]
+    );
+}
+
+#[test]
+fn secret_from_environment() {
+    // No other test relies on the github access token, so setting it can't affect them
+    std::env::set_var("MATRIX_BOT_GITHUB_ACCESS_TOKEN", "env-secret");
+    let path = write_config(
+        "secret_env",
+        &format!(
+            "{}\n[searchable_repos]\njf = 'jellyfin/jellyfin'\n",
+            MINIMAL
+        ),
+    );
+    let result = Config::load_from(&path);
+    std::env::remove_var("MATRIX_BOT_GITHUB_ACCESS_TOKEN");
+    assert_eq!(result.unwrap().gh_access_token, "env-secret");
+}
+
+#[test]
+fn room_settings_replace_global_settings() {
+    let path = write_config(
+        "rooms",
+        &format!(
+            r#"{}
+[github_authentication]
+access_token = 'token'
+
+[searchable_repos]
+jf = 'jellyfin/jellyfin'
+
+[rooms."!web:homeserver.com"]
+enable_unit_conversions = false
+commandless = ['github-search']
+searchable_repos = {{ web = 'jellyfin/jellyfin-web' }}
+group_pings = {{ web = ['@user1:homeserver.com'] }}
+"#,
+            MINIMAL
+        ),
+    );
+    let config = MatrixListenerConfig::new(
+        &Config::load_from(&path).unwrap(),
+        &RoomAliasStorage::default(),
+    );
+    let web = RoomId::try_from("!web:homeserver.com").unwrap();
+    let other = RoomId::try_from("!other:homeserver.com").unwrap();
+    let room = config.for_room(&web);
+    assert!(!room.enable_unit_conversions);
+    assert!(room.repos.contains_key("web") && !room.repos.contains_key("jf"));
+    assert!(room.group_pings.contains_key("web"));
+    assert_eq!(config.room_triggers[&web], vec!["github-search"]);
+    let global = config.for_room(&other);
+    assert!(global.enable_unit_conversions);
+    assert!(global.repos.contains_key("jf"));
+    assert!(global.group_pings.is_empty());
+}
+
+#[test]
+fn room_problems() {
+    let path = write_config(
+        "room_problems",
+        &format!(
+            r#"{}
+[commandless.rooms]
+'!web:homeserver.com' = ['link']
+
+[rooms."!web:homeserver.com"]
+commandless = ['ping']
+searchable_repos = {{ web = 'jellyfin' }}
+
+[rooms."web"]
+enable_corrections = false
+"#,
+            MINIMAL
+        ),
+    );
+    let e = Config::load_from(&path).unwrap_err();
+    let problems: Vec<(&str, &str)> = e
+        .problems
+        .iter()
+        .map(|p| (p.path.as_str(), p.key.as_str()))
+        .collect();
+    for expected in &[
+        ("github_authentication", "access_token"),
+        ("rooms.\"!web:homeserver.com\".searchable_repos", "web"),
+        ("rooms.\"!web:homeserver.com\"", "commandless"),
+        ("rooms.\"web\"", ""),
+    ] {
+        assert!(
+            problems.contains(expected),
+            "{:?} not found in {:?}",
+            expected,
+            problems
+        );
+    }
+    assert_eq!(problems.len(), 4);
+}
+
+#[test]
+fn rooms_given_by_alias() {
+    let path = write_config(
+        "aliases",
+        &format!(
+            "{}\n[rooms.'#web:homeserver.com']\nenable_unit_conversions = false\n\n[rooms.'#unknown:homeserver.com']\nenable_unit_conversions = false\n",
+            MINIMAL.replace(
+                "enable_unit_conversions",
+                "help_rooms = ['#help:homeserver.com', '!help:homeserver.com']\nenable_unit_conversions"
+            )
+        ),
+    );
+    let config = Config::load_from(&path).unwrap();
+    let mut aliases: Vec<String> = config
+        .room_aliases()
+        .iter()
+        .map(|a| a.as_str().to_string())
+        .collect();
+    aliases.sort();
+    assert_eq!(
+        aliases,
+        vec![
+            "#help:homeserver.com",
+            "#unknown:homeserver.com",
+            "#web:homeserver.com"
+        ]
+    );
+
+    let mut storage = RoomAliasStorage::default();
+    for (alias, room_id) in &[
+        ("#help:homeserver.com", "!alias-help:homeserver.com"),
+        ("#web:homeserver.com", "!web:homeserver.com"),
+    ] {
+        storage.aliases.insert(
+            RoomAliasId::try_from(*alias).unwrap(),
+            RoomId::try_from(*room_id).unwrap(),
+        );
+    }
+    let listener = MatrixListenerConfig::new(&config, &storage);
+    assert_eq!(listener.help_rooms.len(), 2);
+    assert!(listener
+        .help_rooms
+        .contains(&RoomId::try_from("!alias-help:homeserver.com").unwrap()));
+    let web = RoomId::try_from("!web:homeserver.com").unwrap();
+    assert!(!listener.for_room(&web).enable_unit_conversions);
+    // Rooms with aliases that have never been resolved use the global settings
+    assert_eq!(listener.rooms.len(), 1);
+}
+
+#[test]
+fn references_to_room_by_id() {
+    let path = write_config(
+        "room_references",
+        &format!(
+            r#"{}
+[commandless.rooms]
+'!old:homeserver.com' = ['unit-conversion']
+
+[rooms."!old:homeserver.com"]
+enable_corrections = true
+"#,
+            MINIMAL.replace(
+                "enable_unit_conversions",
+                "help_rooms = ['!old:homeserver.com', '#old:homeserver.com']\nenable_unit_conversions",
+            )
+        ),
+    );
+    let config = Config::load_from(&path).unwrap();
+    let old = RoomId::try_from("!old:homeserver.com").unwrap();
+    assert_eq!(
+        config.room_references(&old),
+        vec![
+            "[general] help_rooms",
+            "[commandless.rooms] !old:homeserver.com",
+            "[rooms.\"!old:homeserver.com\"]",
+        ]
+    );
+    let other = RoomId::try_from("!other:homeserver.com").unwrap();
+    assert!(config.room_references(&other).is_empty());
+}
+
+#[test]
+fn invalid_room() {
+    let path = write_config(
+        "invalid_room",
+        &MINIMAL.replace(
+            "enable_unit_conversions",
+            "help_rooms = ['help']\nenable_unit_conversions",
+        ),
+    );
+    let e = Config::load_from(&path).unwrap_err();
+    assert_eq!(e.problems.len(), 1);
+    assert_eq!(e.problems[0].problem, "help is not a room id or alias");
+}
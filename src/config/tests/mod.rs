@@ -0,0 +1,2 @@
+mod load_tests;
+mod storage_tests;
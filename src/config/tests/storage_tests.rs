@@ -0,0 +1,37 @@
+use crate::config::{GithubRateLimit, ListenerStorage};
+use std::time::{Duration, SystemTime};
+
+fn rate_limited_storage(remaining: i64) -> ListenerStorage {
+    ListenerStorage {
+        github_rate_limit: Some(GithubRateLimit {
+            cost: 2,
+            remaining,
+            reset_at: SystemTime::now() + Duration::from_secs(3600),
+        }),
+        ..ListenerStorage::default()
+    }
+}
+
+#[test]
+fn reserving_spends_the_budget() {
+    let mut storage = rate_limited_storage(5);
+    assert!(storage.reserve_github_query().is_ok());
+    assert!(storage.reserve_github_query().is_ok());
+    assert_eq!(1, storage.github_rate_limit.as_ref().unwrap().remaining);
+    assert!(storage.reserve_github_query().is_err());
+}
+
+#[test]
+fn reserving_without_a_known_budget() {
+    let mut storage = ListenerStorage::default();
+    assert!(storage.reserve_github_query().is_ok());
+    assert!(storage.github_rate_limit.is_none());
+}
+
+#[test]
+fn budget_resets() {
+    let mut storage = rate_limited_storage(0);
+    storage.github_rate_limit.as_mut().unwrap().reset_at =
+        SystemTime::now() - Duration::from_secs(1);
+    assert!(storage.reserve_github_query().is_ok());
+}
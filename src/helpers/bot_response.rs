@@ -12,11 +12,61 @@ pub struct MatrixNoticeResponse {
     /// List of converted units for response building
     conversions: Option<Vec<ConvertedUnit>>,
     /// List of gh search results for response building
-    gh_results: Option<Vec<Url>>,
+    gh_results: Option<Vec<GithubResult>>,
     /// List of link results for response building
     links: Option<Vec<Url>>,
 }
 
+#[derive(Clone, Debug)]
+/// An issue, pull request, commit, or release found by a GitHub search
+pub struct GithubResult {
+    /// What kind of thing the result is
+    pub kind: GithubResultKind,
+    /// Shown after the kind. `#123` for issues and pull requests, the hash of a commit, or the
+    /// tag of a release
+    pub id: String,
+    /// Title of the issue, pull request, or release, or the first line of a commit message
+    pub title: String,
+    /// State of the result. `None` for commits and releases that aren't prereleases
+    pub state: Option<GithubResultState>,
+    /// Login of the author, or their git name for commits. `None` if the account has been deleted
+    pub author: Option<String>,
+    /// Names of the labels applied to the issue or pull request
+    pub labels: Vec<String>,
+    /// Number of comments. `None` for releases, which can't be commented on
+    pub comments: Option<i64>,
+    /// Link to the result
+    pub url: Url,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Kinds of results a GitHub search can find
+pub enum GithubResultKind {
+    /// An issue
+    Issue,
+    /// A pull request
+    PullRequest,
+    /// A commit
+    Commit,
+    /// A release
+    Release,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// States a result can be in
+pub enum GithubResultState {
+    /// Open and ready for review if a pull request
+    Open,
+    /// Closed without being merged
+    Closed,
+    /// Pull request that has been merged
+    Merged,
+    /// Open pull request that is not ready for review
+    Draft,
+    /// Release that isn't ready for production
+    Prerelease,
+}
+
 #[derive(Debug, Default)]
 pub struct MatrixFormattedTextResponse {
     /// List of users that will be pinged for response building
@@ -35,10 +85,10 @@ impl MatrixNoticeResponse {
     pub fn set_unit_conversions(&mut self, conversions: Vec<ConvertedUnit>) {
         self.conversions = Some(conversions)
     }
-    /// Sets member gh_results with supplied list of Urls
+    /// Sets member gh_results with supplied list of GithubResults
     ///
     /// Will overwrite if suppled a second time
-    pub fn set_gh_results(&mut self, gh_results: Vec<Url>) {
+    pub fn set_gh_results(&mut self, gh_results: Vec<GithubResult>) {
         self.gh_results = Some(gh_results)
     }
     /// Sets member links with supplied list of Urls
@@ -51,6 +101,74 @@ impl MatrixNoticeResponse {
     pub fn is_some(&self) -> bool {
         self.conversions.is_some() || self.gh_results.is_some() || self.links.is_some()
     }
+    /// Formats every member field as html, with each gh result in its own paragraph
+    pub fn format_text(&self) -> Option<String> {
+        if !self.is_some() {
+            return None;
+        }
+        let mut formatted_text = String::new();
+        if let Some(v) = &self.conversions {
+            for s in v {
+                formatted_text.push_str(&escape_html(&s.to_string()));
+                formatted_text.push_str("<br>\n");
+            }
+        }
+        if let Some(v) = &self.gh_results {
+            for s in v {
+                formatted_text.push_str(&s.format_text());
+            }
+        }
+        if let Some(v) = &self.links {
+            for s in v {
+                let url = escape_html(s.as_str());
+                formatted_text.push_str(&format!("<a href=\"{}\">{}</a><br>\n", url, url));
+            }
+        }
+        Some(formatted_text.trim_end_matches("<br>\n").to_string())
+    }
+}
+
+impl GithubResult {
+    /// Formats the result as an html paragraph linking to it
+    pub fn format_text(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(v) = self.state {
+            details.push(v.to_string());
+        }
+        if let Some(v) = &self.author {
+            details.push(format!("by {}", escape_html(v)));
+        }
+        details.extend(self.comments_text());
+        let mut formatted_text = format!(
+            "<p><b>{} {}</b> <a href=\"{}\">{}</a>",
+            self.kind,
+            escape_html(&self.id),
+            escape_html(self.url.as_str()),
+            escape_html(&self.title)
+        );
+        if !details.is_empty() {
+            formatted_text.push_str("<br>\n");
+            formatted_text.push_str(&details.join(" · "));
+        }
+        if !self.labels.is_empty() {
+            let labels: Vec<String> = self
+                .labels
+                .iter()
+                .map(|l| format!("<code>{}</code>", escape_html(l)))
+                .collect();
+            formatted_text.push_str("<br>\n");
+            formatted_text.push_str(&labels.join(" "));
+        }
+        formatted_text.push_str("</p>\n");
+        formatted_text
+    }
+    /// Returns the number of comments with the correct plural if the result can have comments
+    fn comments_text(&self) -> Option<String> {
+        match self.comments? {
+            1 => Some("1 comment".to_string()),
+            n => Some(format!("{} comments", n)),
+        }
+    }
 }
 
 impl MatrixFormattedTextResponse {
@@ -88,15 +206,18 @@ impl MatrixFormattedNoticeResponse {
         match &mut self.errors {
             Some(v) => {
                 for e in errors {
-                    v.push(e)
+                    if !v.contains(&e) {
+                        v.push(e)
+                    }
                 }
             }
             None => self.errors = Some(errors),
         }
     }
-    // pub fn is_some(&self) -> bool {
-    //     self.errors.is_some()
-    // }
+    /// Returns `true` if any member field is `Some`
+    pub fn is_some(&self) -> bool {
+        self.errors.is_some()
+    }
     pub fn format_text(&self) -> Option<String> {
         match &self.errors {
             Some(v) => {
@@ -148,6 +269,50 @@ impl fmt::Display for MatrixNoticeResponse {
     }
 }
 
+impl fmt::Display for GithubResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.kind, self.id, self.title)?;
+        let mut details = Vec::new();
+        if let Some(v) = self.state {
+            details.push(v.to_string());
+        }
+        if let Some(v) = &self.author {
+            details.push(format!("by {}", v));
+        }
+        details.extend(self.comments_text());
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        if !self.labels.is_empty() {
+            write!(f, " [{}]", self.labels.join(", "))?;
+        }
+        write!(f, "\n{}", self.url)
+    }
+}
+
+impl fmt::Display for GithubResultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GithubResultKind::Issue => write!(f, "Issue"),
+            GithubResultKind::PullRequest => write!(f, "Pull request"),
+            GithubResultKind::Commit => write!(f, "Commit"),
+            GithubResultKind::Release => write!(f, "Release"),
+        }
+    }
+}
+
+impl fmt::Display for GithubResultState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GithubResultState::Open => write!(f, "open"),
+            GithubResultState::Closed => write!(f, "closed"),
+            GithubResultState::Merged => write!(f, "merged"),
+            GithubResultState::Draft => write!(f, "draft"),
+            GithubResultState::Prerelease => write!(f, "prerelease"),
+        }
+    }
+}
+
 impl fmt::Display for MatrixFormattedTextResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut response = String::new();
@@ -181,3 +346,19 @@ impl fmt::Display for MatrixFormattedNoticeResponse {
         write!(f, "{}", response)
     }
 }
+
+/// Escapes text so it can be placed in html
+pub(super) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
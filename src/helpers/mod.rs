@@ -1,4 +1,11 @@
 //! Exports various helper functions and types
+//!
+//! Relevant tests are in a test submodule
+//!
+//! Tests cover the text and html built for GitHub results, including escaping user content
+
+#[cfg(test)]
+mod tests;
 
 mod bot_response;
 mod check_format;
@@ -7,7 +14,8 @@ mod convert_unit;
 
 // Public re-exports
 pub use bot_response::{
-    MatrixFormattedNoticeResponse, MatrixFormattedTextResponse, MatrixNoticeResponse,
+    GithubResult, GithubResultKind, GithubResultState, MatrixFormattedNoticeResponse,
+    MatrixFormattedTextResponse, MatrixNoticeResponse,
 };
 pub use check_format::check_format;
 pub use clean_text::clean_text;
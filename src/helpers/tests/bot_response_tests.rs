@@ -0,0 +1,124 @@
+use crate::helpers::bot_response::escape_html;
+use crate::helpers::{GithubResult, GithubResultKind, GithubResultState};
+use reqwest::Url;
+
+fn issue() -> GithubResult {
+    GithubResult {
+        kind: GithubResultKind::Issue,
+        id: "#1234".to_string(),
+        title: "Playback fails".to_string(),
+        state: Some(GithubResultState::Open),
+        author: Some("user1".to_string()),
+        labels: vec!["bug".to_string(), "playback".to_string()],
+        comments: Some(1),
+        url: Url::parse("https://github.com/jellyfin/jellyfin/issues/1234").unwrap(),
+    }
+}
+
+#[test]
+fn escapes_html() {
+    assert_eq!(
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;",
+        escape_html("<a href=\"x\">Tom & Jerry's</a>")
+    );
+    assert_eq!("Plain text", escape_html("Plain text"));
+}
+
+#[test]
+fn issue_text() {
+    assert_eq!(
+        "Issue #1234: Playback fails (open, by user1, 1 comment) [bug, playback]\nhttps://github.com/jellyfin/jellyfin/issues/1234",
+        issue().to_string()
+    );
+}
+
+#[test]
+fn issue_html() {
+    assert_eq!(
+        "<p><b>Issue #1234</b> <a href=\"https://github.com/jellyfin/jellyfin/issues/1234\">Playback fails</a><br>\nopen · by user1 · 1 comment<br>\n<code>bug</code> <code>playback</code></p>\n",
+        issue().format_text()
+    );
+}
+
+#[test]
+fn user_content_is_escaped_in_html() {
+    let result = GithubResult {
+        title: "<script>alert(1)</script> & more".to_string(),
+        author: Some("<b>user1</b>".to_string()),
+        labels: vec!["a&b".to_string()],
+        ..issue()
+    };
+    let html = result.format_text();
+    assert!(!html.contains("<script>"));
+    assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt; &amp; more"));
+    assert!(html.contains("by &lt;b&gt;user1&lt;/b&gt;"));
+    assert!(html.contains("<code>a&amp;b</code>"));
+    // Plain text is sent as is
+    assert!(result
+        .to_string()
+        .contains("<script>alert(1)</script> & more"));
+}
+
+#[test]
+fn no_labels() {
+    let result = GithubResult {
+        labels: Vec::new(),
+        ..issue()
+    };
+    assert_eq!(
+        "Issue #1234: Playback fails (open, by user1, 1 comment)\nhttps://github.com/jellyfin/jellyfin/issues/1234",
+        result.to_string()
+    );
+    assert!(!result.format_text().contains("<code>"));
+}
+
+#[test]
+fn closed_and_merged_states() {
+    let closed = GithubResult {
+        state: Some(GithubResultState::Closed),
+        comments: Some(3),
+        ..issue()
+    };
+    assert!(closed
+        .to_string()
+        .contains("(closed, by user1, 3 comments)"));
+    let merged = GithubResult {
+        kind: GithubResultKind::PullRequest,
+        state: Some(GithubResultState::Merged),
+        ..issue()
+    };
+    assert!(merged.to_string().starts_with("Pull request #1234"));
+    assert!(merged.format_text().contains("merged · by user1"));
+}
+
+#[test]
+fn missing_author() {
+    let result = GithubResult {
+        author: None,
+        ..issue()
+    };
+    assert!(result.to_string().contains("(open, 1 comment)"));
+    assert!(!result.format_text().contains("by "));
+}
+
+#[test]
+fn release_without_details() {
+    let result = GithubResult {
+        kind: GithubResultKind::Release,
+        id: "v10.6.0".to_string(),
+        title: "10.6.0".to_string(),
+        state: None,
+        author: None,
+        labels: Vec::new(),
+        comments: None,
+        url: Url::parse("https://github.com/jellyfin/jellyfin/releases/tag/v10.6.0").unwrap(),
+    };
+    assert_eq!(
+        "Release v10.6.0: 10.6.0\nhttps://github.com/jellyfin/jellyfin/releases/tag/v10.6.0",
+        result.to_string()
+    );
+    assert_eq!(
+        "<p><b>Release v10.6.0</b> <a href=\"https://github.com/jellyfin/jellyfin/releases/tag/v10.6.0\">10.6.0</a></p>\n",
+        result.format_text()
+    );
+}
@@ -0,0 +1 @@
+mod bot_response_tests;
@@ -8,6 +8,10 @@
 //!
 //!     List is configrable but requires at least 1 entry
 //!
+//! - ### Follows rooms when they are upgraded
+//!
+//!     Joins the replacement room and lets authorized users know about config that still refers to the old room
+//!
 //! - ### A jokey interjection that can correct pesky users that misspell your project name
 //!    
 //!     Off by default
@@ -30,10 +34,16 @@
 //!     This can be turned off by not supplying any repos to search
 //!    
 //!     Searches are parsed from message text if they match 'jf#123' or 'jf #123'
+//!
+//!     Replies show the title, state, author, labels, and comment count of each issue or pull
 //!    
 //!     The left side of the # is configrable and can point to any repo
 //!    
-//!     Uses GraphQL to be API cost effective (REST might require 2 hits depending on returned result)
+//!     Uses GraphQL to be API cost effective (REST might require 2 hits depending on returned result). Every search in a message is sent as a single query
+//!
+//!     Results are cached for 10 minutes by default, and authorized users can clear the cache early with `!github-refresh`
+//!
+//!     Pasted links to issues, pulls, commits, and releases get the same summary. Only links to searchable repos are unfurled unless `github_urls_any_repo` is set
 //!
 //! - ### A configrable general purpose linker
 //!    
@@ -55,8 +65,24 @@
 //!
 //! Configure the bot as required with the help of the comments
 //!
+//! Secrets can be kept out of `config.toml` by reading them from files or `MATRIX_BOT_*` environment variables as described in the sample config
+//!
+//! Features, repos, links, and group pings can be changed for individual rooms in a `[rooms."!id:server"]` section
+//!
+//! Rooms can be given by alias anywhere a room id is accepted, including the webhook `room_id`. Aliases are resolved at startup and on reload, and the last known room is kept if an alias stops resolving
+//!
 //! `./matrix-bot` to run
 //!
+//! `./matrix-bot check-config [FILE]` to check a config file for problems without starting the bot
+//!
+//! `./matrix-bot send ROOM MESSAGE`, `whoami`, and `logout` use the saved session without starting the bot
+//!
+//! `./matrix-bot export-state DIR` and `import-state DIR` copy the saved state to or from ron files
+//!
+//! `--config-dir`, `--data-dir`, and `--log-level` override `MATRIX_BOT_CONFIG_DIR`, `MATRIX_BOT_DATA_DIR`, and `MATRIX_BOT_LOG_LEVEL`. Run `./matrix-bot help` for more
+//!
+//! Most changes to `config.toml` can be applied without a restart by sending the bot `SIGHUP` or having an authorized user send `!reload`
+//!
 //! I hope you enjoy your experience and please report and issues or feature requests you might have
 
 #![forbid(unsafe_code)]
@@ -64,6 +90,7 @@
 #![warn(clippy::missing_docs_in_private_items)]
 
 mod bot;
+mod cli;
 mod config;
 mod events;
 mod helpers;
@@ -73,9 +100,15 @@ mod matrix_handlers;
 mod messages;
 mod queries;
 mod regex;
+mod reload;
+mod shutdown;
+mod storage;
+mod supervisor;
 mod webhook;
 mod webhook_handlers;
 
+use structopt::StructOpt;
+
 #[macro_use]
 extern crate rocket;
 #[macro_use]
@@ -84,6 +117,9 @@ extern crate assign;
 #[tokio::main]
 #[allow(clippy::missing_docs_in_private_items)]
 async fn main() {
+    let cli = cli::Cli::from_args();
+    cli.apply_overrides();
     logging::init();
-    bot::init().await
+    cli.run().await;
+    storage::flush();
 }
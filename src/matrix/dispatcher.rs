@@ -0,0 +1,236 @@
+//! Concurrent handling of room events
+//!
+//! Events from different rooms are handled concurrently up to a configured limit while events
+//! from the same room are always handled in the order they were received.
+//!
+//! Handler data is rebuilt the first time an event is handled after the config is reloaded.
+//!
+//! Every event reports back once it has been handled so the sync token is only advanced past
+//! events that were not lost.
+
+use crate::config::Config;
+use crate::matrix_handlers::listeners::{handle_text_event, ListenerContext};
+use crate::messages::MatrixMessage;
+use crate::reload::ConfigReceiver;
+use async_trait::async_trait;
+use ruma::{events::room::message::TextMessageEventContent, RoomId, UserId};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+/// Number of events that can be waiting in a single room before the sync loop waits for them
+const ROOM_QUEUE_SIZE: usize = 32;
+
+/// Handler data along with the config it was built from
+struct CurrentListener {
+    /// Config the handler data was built from.
+    config: Arc<Config>,
+    /// Configuration, storage, and clients used by handlers.
+    listener: Arc<ListenerContext>,
+}
+
+/// Handles the text events queued for a room
+#[async_trait]
+pub trait TextHandler: Send + Sync + 'static {
+    /// Handles a single text event. Returning means the event was handled.
+    async fn handle_text(
+        &self,
+        room_id: &RoomId,
+        content: &TextMessageEventContent,
+        sender: &UserId,
+    );
+}
+
+/// Data shared by every handler
+pub struct HandlerContext {
+    /// Handler data built from the most recently used config.
+    current: Mutex<CurrentListener>,
+    /// Most recently loaded config.
+    configs: ConfigReceiver,
+    /// Channel used to send messages to the responder.
+    send: Sender<MatrixMessage>,
+    /// Limits how many events are handled at once across all rooms.
+    permits: Semaphore,
+}
+
+/// A text event waiting to be handled
+struct TextEvent {
+    /// Content of the message
+    content: TextMessageEventContent,
+    /// User that sent the message
+    sender: UserId,
+    /// Told once the event has been handled. Dropped without sending if the event is lost.
+    handled: oneshot::Sender<()>,
+}
+
+/// Queue and task handling the events of a single room
+struct RoomWorker {
+    /// Queue of events waiting to be handled in the room
+    queue: Sender<TextEvent>,
+    /// Task handling the queue
+    task: JoinHandle<()>,
+}
+
+/// Hands events to per room workers that run concurrently up to `max_concurrent_handlers`
+pub struct EventDispatcher<H = HandlerContext> {
+    /// Data shared by every handler
+    context: Arc<H>,
+    /// Workers for each room an event has been received in
+    rooms: HashMap<RoomId, RoomWorker>,
+    /// Reports for events queued since `wait_handled` was last called
+    pending: Vec<oneshot::Receiver<()>>,
+}
+
+impl EventDispatcher {
+    /// Creates a dispatcher without any running workers.
+    ///
+    /// `listener` must have been built from `config`.
+    pub fn new(
+        listener: ListenerContext,
+        config: Arc<Config>,
+        configs: ConfigReceiver,
+        send: Sender<MatrixMessage>,
+    ) -> Self {
+        let permits = Semaphore::new(listener.config.max_concurrent_handlers);
+        let context = Arc::new(HandlerContext {
+            current: Mutex::new(CurrentListener {
+                config,
+                listener: Arc::new(listener),
+            }),
+            configs,
+            send,
+            permits,
+        });
+        Self::with_handler(context)
+    }
+
+    /// Returns handler data built from the most recently loaded config
+    pub fn listener(&self) -> Arc<ListenerContext> {
+        self.context.listener()
+    }
+}
+
+impl<H: TextHandler> EventDispatcher<H> {
+    /// Creates a dispatcher handing events to `handler` without any running workers
+    pub fn with_handler(handler: Arc<H>) -> Self {
+        Self {
+            context: handler,
+            rooms: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Queues a text event to be handled after all earlier events from the same room.
+    ///
+    /// Waits if the room already has `ROOM_QUEUE_SIZE` events waiting.
+    pub async fn dispatch_text(
+        &mut self,
+        room_id: &RoomId,
+        content: TextMessageEventContent,
+        sender: UserId,
+    ) {
+        let (handled, report) = oneshot::channel();
+        self.pending.push(report);
+        let mut event = TextEvent {
+            content,
+            sender,
+            handled,
+        };
+        // A worker only stops early if a handler panicked, in which case it is replaced once
+        for _ in 0..2 {
+            let context = &self.context;
+            let worker = self
+                .rooms
+                .entry(room_id.clone())
+                .or_insert_with(|| RoomWorker::spawn(room_id.clone(), context.clone()));
+            match worker.queue.send(event).await {
+                Ok(_) => return,
+                Err(e) => {
+                    error!(
+                        "Worker for room {} stopped unexpectedly. Restarting it",
+                        room_id
+                    );
+                    self.rooms.remove(room_id);
+                    event = e.0;
+                }
+            }
+        }
+        error!("Unable to handle event in room {}. Dropping it", room_id);
+    }
+
+    /// Waits until every event queued since the last call has been handled or lost.
+    ///
+    /// Returns `false` if any of them was lost, such as when its handler panicked.
+    pub async fn wait_handled(&mut self) -> bool {
+        let mut handled = true;
+        for report in mem::take(&mut self.pending) {
+            handled &= report.await.is_ok();
+        }
+        handled
+    }
+
+    /// Stops accepting events and waits until every queued event has been handled
+    pub async fn finish(&mut self) {
+        for (room_id, worker) in self.rooms.drain() {
+            drop(worker.queue);
+            if let Err(e) = worker.task.await {
+                error!("Worker for room {} failed due to error {}", room_id, e);
+            }
+        }
+    }
+}
+
+impl HandlerContext {
+    /// Returns handler data built from the most recently loaded config, rebuilding it if needed
+    fn listener(&self) -> Arc<ListenerContext> {
+        let config = self.configs.borrow().clone();
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        if !Arc::ptr_eq(&current.config, &config) {
+            debug!("Config was reloaded. Rebuilding handler data");
+            current.listener = Arc::new(current.listener.with_config(&config));
+            current.config = config;
+        }
+        current.listener.clone()
+    }
+}
+
+#[async_trait]
+impl TextHandler for HandlerContext {
+    async fn handle_text(
+        &self,
+        room_id: &RoomId,
+        content: &TextMessageEventContent,
+        sender: &UserId,
+    ) {
+        let _permit = self.permits.acquire().await;
+        let listener = self.listener();
+        let mut send = self.send.clone();
+        handle_text_event(content, sender, room_id, &listener, &mut send).await;
+    }
+}
+
+impl RoomWorker {
+    /// Spawns a task handling the events of a room in order
+    fn spawn<H: TextHandler>(room_id: RoomId, context: Arc<H>) -> Self {
+        debug!("Starting worker for room {}", room_id);
+        let (queue, recv) = mpsc::channel(ROOM_QUEUE_SIZE);
+        let task = tokio::spawn(run_room(room_id, recv, context));
+        Self { queue, task }
+    }
+}
+
+/// Handles events from the queue one at a time until it is closed
+async fn run_room<H: TextHandler>(room_id: RoomId, mut recv: Receiver<TextEvent>, context: Arc<H>) {
+    while let Some(event) = recv.recv().await {
+        context
+            .handle_text(&room_id, &event.content, &event.sender)
+            .await;
+        // The sync loop may have stopped waiting if it is shutting down
+        let _ = event.handled.send(());
+    }
+    debug!("Worker for room {} stopped", room_id);
+}
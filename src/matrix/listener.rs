@@ -1,139 +1,367 @@
 //! Structs and functions that represent functional bots and allow for easy loading
 //! plus main loop initialization.
 
-use crate::config::{Config, ListenerStorage, MatrixListenerConfig};
-use crate::matrix_handlers::listeners::{handle_invite_event, handle_text_event};
+use crate::config::{ListenerStorage, MatrixListenerConfig};
+use crate::matrix::dispatcher::{EventDispatcher, TextHandler};
+use crate::matrix_handlers::listeners::{
+    handle_invite_event, handle_tombstone_event, ListenerContext,
+};
 use crate::messages::MatrixMessage;
+use crate::reload::ConfigReloader;
+use crate::shutdown::{wait_for_shutdown, ShutdownReceiver};
 use ruma::{
-    api::client::r0::sync::sync_events,
+    api::client::r0::{
+        filter::{create_filter, FilterDefinition, LazyLoadOptions, RoomEventFilter, RoomFilter},
+        sync::sync_events::{self, Filter},
+    },
     events::{
         room::message::{MessageEventContent, Relation},
-        AnyStrippedStateEvent, AnySyncMessageEvent, AnySyncRoomEvent, SyncMessageEvent,
+        AnyStrippedStateEvent, AnySyncMessageEvent, AnySyncRoomEvent, AnySyncStateEvent,
+        SyncMessageEvent,
     },
     presence::PresenceState,
 };
 use ruma_client::Client;
-use std::time::Duration;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, trace};
+use tokio::time::{delay_for, delay_until, Instant as TokioInstant};
+use tracing::{debug, error, info, trace, warn};
+
+/// Delay before the first retry of a failed sync
+const INITIAL_SYNC_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay between retries of a failed sync
+const MAX_SYNC_RETRY_DELAY: Duration = Duration::from_secs(120);
+/// Minimum time between writes of listener storage. Storage is always saved on shutdown.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Event types the bot handles in room timelines
+const TIMELINE_EVENT_TYPES: &[&str] = &["m.room.message", "m.room.member", "m.room.tombstone"];
+/// Event types the bot needs from room state
+const STATE_EVENT_TYPES: &[&str] = &["m.room.member", "m.room.tombstone"];
 
 /// Struct representing all required data for a functioning bot instance.
 pub struct MatrixListener {
-    /// Storage data.
-    pub storage: ListenerStorage,
-    /// Configuration data.
-    pub config: MatrixListenerConfig,
-    /// Reqwest client used for external API calls.
-    pub api_client: reqwest::Client,
+    /// Storage data. Shared with the handlers.
+    pub storage: Arc<Mutex<ListenerStorage>>,
     send: Sender<MatrixMessage>,
+    /// Hands text events to handlers running concurrently.
+    dispatcher: EventDispatcher,
+    /// Time storage was last written
+    last_save: Instant,
+    /// Whether a sync advanced storage since it was last written
+    unsaved: bool,
 }
 
 impl MatrixListener {
     /// Loads storage data, config data, and then creates a reqwest client and then returns a Bot instance.
-    pub fn new(config: &Config, send: Sender<MatrixMessage>) -> Self {
-        let storage = ListenerStorage::load_storage();
-        let config = MatrixListenerConfig::new(&config);
-        let api_client = reqwest::Client::new();
+    ///
+    /// Handlers always use the most recently loaded config from the reloader.
+    pub fn new(reloader: Arc<ConfigReloader>, client: Client, send: Sender<MatrixMessage>) -> Self {
+        let storage = Arc::new(Mutex::new(ListenerStorage::load_storage()));
+        let configs = reloader.subscribe();
+        let config = reloader.current();
+        let listener = ListenerContext::new(&config, storage.clone(), client, reloader);
+        let dispatcher = EventDispatcher::new(listener, config, configs, send.clone());
         Self {
             storage,
-            config,
-            api_client,
             send,
+            dispatcher,
+            last_save: Instant::now(),
+            unsaved: false,
         }
     }
 
+    /// Saves all listener storage data
+    pub fn save_storage(&self) {
+        self.lock_storage().save_storage();
+    }
+
     /// Used to start main program loop for the bot.
-    /// Will login then loop forever while waiting on new sync data from the homeserver.
-    pub async fn start(&mut self, client: Client) {
+    /// Will loop while waiting on new sync data from the homeserver until a shutdown is requested.
+    ///
+    /// Failed syncs are retried with exponential backoff. If there is no saved sync token, the first
+    /// sync only records the token so old messages are not replied to. A batch is synced again the
+    /// same way if any of its events were lost before being handled.
+    ///
+    /// Storage changes skipped by the save interval are written once it ends, even while waiting
+    /// on a sync.
+    pub async fn start(&mut self, client: Client, mut shutdown: ShutdownReceiver) {
+        let timeline_types: Vec<String> =
+            TIMELINE_EVENT_TYPES.iter().map(|t| t.to_string()).collect();
+        let state_types: Vec<String> = STATE_EVENT_TYPES.iter().map(|t| t.to_string()).collect();
+        let filter_definition = sync_filter_definition(&timeline_types, &state_types);
+        let config = self.dispatcher.listener().config.clone();
+        let filter_id = upload_filter(&client, &config, &filter_definition).await;
+        let mut delay = INITIAL_SYNC_RETRY_DELAY;
         loop {
+            let filter = match &filter_id {
+                Some(v) => Filter::FilterId(v),
+                None => Filter::FilterDefinition(filter_definition.clone()),
+            };
+            let last_sync = self.lock_storage().last_sync.clone();
             let req = assign!(sync_events::Request::new(),
                 {
-                    filter: None,
-                    since: match &self.storage.last_sync {
-                        Some(v) => Some(v.as_str()),
-                        None => None
-                    },
+                    filter: Some(&filter),
+                    since: last_sync.as_deref(),
                     full_state: false,
                     set_presence: &PresenceState::Unavailable,
                     timeout: Some(Duration::new(30, 0))
                 }
             );
-            let response = match client.request(req).await {
-                Ok(v) => Some(v),
+            let request = client.request(req);
+            tokio::pin!(request);
+            let response = loop {
+                let next_save = TokioInstant::from_std(self.last_save + SAVE_INTERVAL);
+                tokio::select! {
+                    response = &mut request => break Some(response),
+                    _ = delay_until(next_save), if self.unsaved => self.flush_storage(),
+                    _ = wait_for_shutdown(&mut shutdown) => break None,
+                }
+            };
+            let response = match response {
+                Some(v) => v,
+                None => {
+                    info!("Shutdown requested. Stopping matrix sync loop.");
+                    break;
+                }
+            };
+
+            let v = match response {
+                Ok(v) => v,
                 Err(e) => {
-                    debug!("Line 73: {:?}", e);
-                    None
+                    warn!("Sync failed due to error {:?}. Retrying in {:?}", e, delay);
+                    if self.unsaved {
+                        self.flush_storage();
+                    }
+                    if !wait_to_retry(delay, &mut shutdown).await {
+                        info!("Shutdown requested. Stopping matrix sync loop.");
+                        break;
+                    }
+                    delay = cmp::min(delay * 2, MAX_SYNC_RETRY_DELAY);
+                    continue;
                 }
             };
 
-            match response {
-                Some(v) => {
-                    for (room_id, joined_room) in &v.rooms.join {
-                        for raw_event in &joined_room.timeline.events {
-                            let event = raw_event.deserialize();
-                            match event {
-                                Ok(AnySyncRoomEvent::Message(
-                                    AnySyncMessageEvent::RoomMessage(SyncMessageEvent {
-                                        content: MessageEventContent::Text(t),
-                                        sender,
-                                        ..
-                                    }),
-                                )) => {
-                                    if matches!(t.relates_to, Some(Relation::Replacement(_))) {
-                                        debug!("Message is an edit, skipping handling");
-                                        continue;
-                                    }
-                                    handle_text_event(
-                                        &t,
-                                        &sender,
-                                        room_id,
-                                        &mut self.storage,
-                                        &self.config,
-                                        &self.api_client,
-                                        &mut self.send,
-                                    )
-                                    .await;
-                                }
-                                Ok(_) => {}
-                                Err(e) => {
-                                    debug!("{:?}", e);
-                                    trace!("Content: {:?}", raw_event.json())
+            if last_sync.is_none() {
+                info!(
+                    "No previous sync token found. Skipping historical messages from initial sync"
+                );
+            } else {
+                for (room_id, joined_room) in &v.rooms.join {
+                    for raw_event in &joined_room.timeline.events {
+                        let event = raw_event.deserialize();
+                        match event {
+                            Ok(AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(
+                                SyncMessageEvent {
+                                    content: MessageEventContent::Text(t),
+                                    sender,
+                                    ..
+                                },
+                            ))) => {
+                                if matches!(t.relates_to, Some(Relation::Replacement(_))) {
+                                    debug!("Message is an edit, skipping handling");
+                                    continue;
                                 }
+                                self.dispatcher.dispatch_text(room_id, t, sender).await;
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                debug!("{:?}", e);
+                                trace!("Content: {:?}", raw_event.json())
                             }
-                            self.storage.last_sync = Some(v.next_batch.clone());
-                            self.storage.save_storage();
                         }
                     }
-                    for (room_id, invited_room) in &v.rooms.invite {
-                        trace!("Invited room data: {:?}", invited_room);
-                        for raw_event in &invited_room.invite_state.events {
-                            let event = raw_event.deserialize();
-                            match event {
-                                Ok(AnyStrippedStateEvent::RoomMember(s)) => {
-                                    trace!("Invited by {}", s.sender);
-                                    handle_invite_event(
-                                        &s.sender,
-                                        &room_id,
-                                        &self.config,
-                                        &mut self.send,
-                                    )
-                                    .await;
-                                    trace!("Handled invite event")
-                                }
-                                Ok(_) => {
-                                    // FIXME: Reject invite if there is no known sender
-                                    error!("No known inviter. Will not join room. If you see this, report it.");
-                                }
-                                Err(e) => {
-                                    debug!("{:?}", e);
-                                    trace!("Content: {:?}", raw_event.json())
-                                }
-                            }
+                }
+            }
+            // Upgrades are followed even on the initial sync so the bot never stays in a dead room.
+            // Replacements the bot is already in were followed before and are skipped.
+            let mut upgrades = HashMap::new();
+            for (room_id, joined_room) in &v.rooms.join {
+                let state = joined_room
+                    .state
+                    .events
+                    .iter()
+                    .filter_map(|e| e.deserialize().ok());
+                let timeline_state =
+                    joined_room
+                        .timeline
+                        .events
+                        .iter()
+                        .filter_map(|e| match e.deserialize() {
+                            Ok(AnySyncRoomEvent::State(s)) => Some(s),
+                            _ => None,
+                        });
+                for event in state.chain(timeline_state) {
+                    if let AnySyncStateEvent::RoomTombstone(t) = event {
+                        if v.rooms.join.contains_key(&t.content.replacement_room) {
+                            debug!(
+                                "Already in room {} replacing {}",
+                                t.content.replacement_room, room_id
+                            );
+                            continue;
                         }
+                        upgrades.insert(room_id, (t.content.replacement_room, t.sender));
                     }
                 }
-                None => debug!("Response deserialization failed. Doing nothing this loop."),
             }
+            for (old_room, (new_room, sender)) in upgrades {
+                let listener = self.dispatcher.listener();
+                handle_tombstone_event(old_room, &new_room, &sender, &listener, &mut self.send)
+                    .await;
+            }
+            for (room_id, invited_room) in &v.rooms.invite {
+                trace!("Invited room data: {:?}", invited_room);
+                for raw_event in &invited_room.invite_state.events {
+                    let event = raw_event.deserialize();
+                    match event {
+                        Ok(AnyStrippedStateEvent::RoomMember(s)) => {
+                            trace!("Invited by {}", s.sender);
+                            let listener = self.dispatcher.listener();
+                            handle_invite_event(
+                                &s.sender,
+                                &room_id,
+                                &listener.config,
+                                &mut self.send,
+                            )
+                            .await;
+                            trace!("Handled invite event")
+                        }
+                        Ok(_) => {
+                            // FIXME: Reject invite if there is no known sender
+                            error!(
+                                "No known inviter. Will not join room. If you see this, report it."
+                            );
+                        }
+                        Err(e) => {
+                            debug!("{:?}", e);
+                            trace!("Content: {:?}", raw_event.json())
+                        }
+                    }
+                }
+            }
+
+            if !finish_batch(&mut self.dispatcher, &self.storage, &v.next_batch).await {
+                warn!(
+                    "Not every event in the batch was handled. Syncing it again in {:?}",
+                    delay
+                );
+                if !wait_to_retry(delay, &mut shutdown).await {
+                    info!("Shutdown requested. Stopping matrix sync loop.");
+                    break;
+                }
+                delay = cmp::min(delay * 2, MAX_SYNC_RETRY_DELAY);
+                continue;
+            }
+            delay = INITIAL_SYNC_RETRY_DELAY;
+            self.save_storage_debounced();
+        }
+        debug!("Waiting for queued events to be handled");
+        self.dispatcher.finish().await;
+    }
+
+    /// Saves storage if `SAVE_INTERVAL` has passed since the last save, otherwise marks it unsaved
+    /// so the sync loop writes it once the interval ends
+    fn save_storage_debounced(&mut self) {
+        self.unsaved = true;
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.flush_storage();
+        }
+    }
+
+    /// Saves storage and restarts the save interval
+    fn flush_storage(&mut self) {
+        self.save_storage();
+        self.last_save = Instant::now();
+        self.unsaved = false;
+    }
+
+    /// Returns exclusive access to the storage data
+    fn lock_storage(&self) -> MutexGuard<'_, ListenerStorage> {
+        match self.storage.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner(),
+        }
+    }
+}
+
+/// Waits until every event dispatched from a batch has been handled, then records `next_batch`
+/// as the sync token.
+///
+/// Returns `false` without advancing the token if any event was lost, so a crash or restart never
+/// skips events that were queued but not yet handled.
+pub(crate) async fn finish_batch<H: TextHandler>(
+    dispatcher: &mut EventDispatcher<H>,
+    storage: &Mutex<ListenerStorage>,
+    next_batch: &str,
+) -> bool {
+    if !dispatcher.wait_handled().await {
+        return false;
+    }
+    let mut storage = match storage.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner(),
+    };
+    storage.last_sync = Some(next_batch.to_string());
+    true
+}
+
+/// Waits `delay` before retrying a sync. Returns `false` if a shutdown was requested instead.
+async fn wait_to_retry(delay: Duration, shutdown: &mut ShutdownReceiver) -> bool {
+    tokio::select! {
+        _ = delay_for(delay) => true,
+        _ = wait_for_shutdown(shutdown) => false,
+    }
+}
+
+/// Builds the sync filter limiting responses to the events the bot handles.
+///
+/// Room members are lazy loaded so only the members relevant to returned events are included.
+fn sync_filter_definition<'a>(
+    timeline_types: &'a [String],
+    state_types: &'a [String],
+) -> FilterDefinition<'a> {
+    assign!(FilterDefinition::ignore_all(), {
+        room: assign!(RoomFilter::default(), {
+            timeline: assign!(RoomEventFilter::default(), {
+                types: Some(timeline_types),
+                lazy_load_options: LazyLoadOptions::Enabled {
+                    include_redundant_members: false,
+                },
+            }),
+            state: assign!(RoomEventFilter::default(), {
+                types: Some(state_types),
+                lazy_load_options: LazyLoadOptions::Enabled {
+                    include_redundant_members: false,
+                },
+            }),
+            ephemeral: RoomEventFilter::ignore_all(),
+            account_data: RoomEventFilter::ignore_all(),
+        }),
+    })
+}
+
+/// Uploads the sync filter and returns its id.
+///
+/// Returns `None` if the upload fails, in which case the filter is sent with every sync instead.
+async fn upload_filter(
+    client: &Client,
+    config: &MatrixListenerConfig,
+    filter: &FilterDefinition<'_>,
+) -> Option<String> {
+    let req = create_filter::Request::new(&config.mx_uname, filter.clone());
+    match client.request(req).await {
+        Ok(v) => {
+            debug!("Uploaded sync filter {}", v.filter_id);
+            Some(v.filter_id)
+        }
+        Err(e) => {
+            warn!(
+                "Unable to upload sync filter due to error {:?}. Sending it with each sync instead",
+                e
+            );
+            None
         }
     }
 }
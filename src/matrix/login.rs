@@ -0,0 +1,224 @@
+//! Session handling for the bot account
+//!
+//! Saved sessions are reused for as long as the homeserver accepts them so restarts do not
+//! create a new device every time.
+
+use crate::config::{Config, SessionStorage};
+use ruma::{
+    api::{
+        client::{
+            error::ErrorKind,
+            r0::{
+                account::whoami,
+                device::{delete_devices, get_devices},
+                uiaa::{AuthData, UiaaResponse},
+            },
+        },
+        error::{FromHttpResponseError, ServerError},
+    },
+    DeviceId, DeviceIdBox,
+};
+use ruma_client::{Client, Session};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::process;
+use tracing::{debug, error, info, trace, warn};
+
+/// Display name given to devices created by the bot. Used to find its old devices.
+pub const DEVICE_DISPLAY_NAME: &str = "matrix-bot";
+
+/// Result of checking a session against the homeserver
+enum SessionState {
+    /// The homeserver accepted the session for the configured user
+    Valid,
+    /// The homeserver rejected the session or it belongs to a different user
+    Rejected,
+}
+
+/// Returns a client for the bot account, reusing the saved session where possible.
+///
+/// Tries the saved session, then the configured access token, then a password login that
+/// reuses the saved device. Exits the program if no valid session can be established.
+pub async fn login(config: &Config) -> Client {
+    let mut session_storage = SessionStorage::load_storage();
+    let saved_device_id = session_storage
+        .session
+        .as_ref()
+        .and_then(|s| s.identification.as_ref())
+        .map(|i| i.device_id.clone());
+
+    if let Some(session) = session_storage.session.clone() {
+        let client = Client::new(config.mx_url.clone(), Some(session));
+        if let SessionState::Valid = validate_session(&client, config).await {
+            info!("Reusing saved session for {}", config.mx_uname);
+            cleanup_devices(&client, config, saved_device_id.as_deref()).await;
+            return client;
+        }
+        info!("Saved session was rejected by the homeserver");
+    }
+
+    if let Some(access_token) = &config.mx_access_token {
+        let session = Session {
+            access_token: access_token.clone(),
+            identification: None,
+        };
+        let client = Client::new(config.mx_url.clone(), Some(session.clone()));
+        match validate_session(&client, config).await {
+            SessionState::Valid => {
+                trace!("Configured access token is valid, saving session data...");
+                session_storage.session = Some(session);
+                session_storage.save();
+                info!(
+                    "Successfully logged in as {} using access token",
+                    config.mx_uname
+                );
+                return client;
+            }
+            SessionState::Rejected => error!("Configured access token was rejected"),
+        }
+    }
+
+    let password = match &config.mx_pass {
+        Some(v) => v,
+        None => {
+            error!("Unable to log in as no valid session or password is available");
+            process::exit(12)
+        }
+    };
+    let client = Client::new(config.mx_url.clone(), None);
+    let session = match client
+        .log_in(
+            config.mx_uname.localpart(),
+            password,
+            saved_device_id.as_deref(),
+            Some(DEVICE_DISPLAY_NAME),
+        )
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to log in due to error {:?}", e);
+            process::exit(12)
+        }
+    };
+
+    // Save returned session
+    trace!("Session retrived, saving session data...");
+    let device_id = session.identification.as_ref().map(|i| i.device_id.clone());
+    session_storage.session = Some(session);
+    session_storage.save();
+    info!("Successfully logged in as {}", config.mx_uname);
+    cleanup_devices(&client, config, device_id.as_deref()).await;
+    client
+}
+
+/// Returns a client using the saved session without checking it with the homeserver.
+///
+/// Returns `None` if no session has been saved.
+pub fn saved_client(config: &Config) -> Option<Client> {
+    SessionStorage::load_storage()
+        .session
+        .map(|session| Client::new(config.mx_url.clone(), Some(session)))
+}
+
+/// Checks that the session used by `client` is accepted by the homeserver and belongs to the
+/// configured user.
+///
+/// Errors other than an unknown token are assumed to be transient and the session is kept.
+async fn validate_session(client: &Client, config: &Config) -> SessionState {
+    match client.request(whoami::Request::new()).await {
+        Ok(v) if v.user_id == config.mx_uname => SessionState::Valid,
+        Ok(v) => {
+            warn!(
+                "Session belongs to {} instead of {}. Discarding it",
+                v.user_id, config.mx_uname
+            );
+            SessionState::Rejected
+        }
+        Err(ruma_client::Error::FromHttpResponse(FromHttpResponseError::Http(
+            ServerError::Known(e),
+        ))) if matches!(e.kind, ErrorKind::UnknownToken { .. }) => SessionState::Rejected,
+        Err(e) => {
+            warn!(
+                "Unable to validate session due to error {:?}. Assuming it is still valid",
+                e
+            );
+            SessionState::Valid
+        }
+    }
+}
+
+/// Deletes devices previously created by the bot if enabled in the config.
+///
+/// Only devices named `DEVICE_DISPLAY_NAME` are considered to be created by the bot, so devices
+/// the user logged in with elsewhere are kept. Versions of the bot before devices were named left
+/// devices without a display name, which are only deleted if `delete_unnamed_devices` is also set.
+/// The current device is never deleted.
+async fn cleanup_devices(client: &Client, config: &Config, current: Option<&DeviceId>) {
+    if !config.mx_delete_old_devices {
+        return;
+    }
+    let (password, current) = match (&config.mx_pass, current) {
+        (Some(p), Some(c)) => (p, c),
+        _ => {
+            warn!("Current device is unknown. Not deleting old devices");
+            return;
+        }
+    };
+    let devices = match client.request(get_devices::Request::new()).await {
+        Ok(v) => v.devices,
+        Err(e) => {
+            error!("Unable to list devices due to error {:?}", e);
+            return;
+        }
+    };
+    let old_devices: Vec<DeviceIdBox> = devices
+        .into_iter()
+        .filter(|d| *d.device_id != *current)
+        .filter(|d| match d.display_name.as_deref() {
+            Some(name) => name == DEVICE_DISPLAY_NAME,
+            None => config.mx_delete_unnamed_devices,
+        })
+        .map(|d| d.device_id)
+        .collect();
+    if old_devices.is_empty() {
+        debug!("No old devices to delete");
+        return;
+    }
+
+    // The first request is expected to fail and returns the session used for authentication
+    let session = match client
+        .request(delete_devices::Request::new(&old_devices))
+        .await
+    {
+        Ok(_) => {
+            info!("Deleted {} old devices", old_devices.len());
+            return;
+        }
+        Err(ruma_client::Error::FromHttpResponse(FromHttpResponseError::Http(
+            ServerError::Known(UiaaResponse::AuthResponse(v)),
+        ))) => v.session,
+        Err(e) => {
+            error!("Unable to delete old devices due to error {:?}", e);
+            return;
+        }
+    };
+    let mut auth_parameters = BTreeMap::new();
+    auth_parameters.insert(
+        "identifier".to_string(),
+        json!({ "type": "m.id.user", "user": config.mx_uname.localpart() }),
+    );
+    auth_parameters.insert("user".to_string(), json!(config.mx_uname.localpart()));
+    auth_parameters.insert("password".to_string(), json!(password));
+    let req = assign!(delete_devices::Request::new(&old_devices), {
+        auth: Some(AuthData::DirectRequest {
+            kind: "m.login.password",
+            session: session.as_deref(),
+            auth_parameters,
+        })
+    });
+    match client.request(req).await {
+        Ok(_) => info!("Deleted {} old devices", old_devices.len()),
+        Err(e) => error!("Unable to delete old devices due to error {:?}", e),
+    }
+}
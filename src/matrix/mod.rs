@@ -1,2 +1,14 @@
+//! Clients that sync with and send to the homeserver
+//!
+//! Relevant tests are in a test submodule
+//!
+//! Tests cover advancing the sync token only once every event in a batch has been handled
+
+#[cfg(test)]
+mod tests;
+
+pub mod dispatcher;
 pub mod listener;
+pub mod login;
 pub mod responder;
+pub mod rooms;
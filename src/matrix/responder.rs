@@ -2,70 +2,118 @@
 //! plus main loop initialization.
 
 use crate::config::ResponderStorage;
-use crate::matrix_handlers::responders::{
-    accept_invite, reject_invite, send_formatted_notice, send_formatted_text, send_notice,
-    send_plain_text,
+use crate::matrix_handlers::responders::{accept_invite, reject_invite, send_message, SendError};
+use crate::messages::{
+    MatrixInviteType, MatrixMessage, MatrixMessageError, MatrixMessageResult, MatrixMessageType,
+    OutboxMessage,
 };
-use crate::messages::{MatrixInviteType, MatrixMessage, MatrixMessageType};
+use crate::shutdown::{is_shutdown, wait_for_shutdown, ShutdownReceiver};
+use crate::storage;
+use ruma::RoomId;
 use ruma_client::Client;
-use tokio::sync::mpsc::Receiver;
-use tracing::info;
+use std::cmp;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::join;
+use tokio::sync::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::time::{delay_for, interval_at, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Delay before the first retry of a failed send
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay between retries of a failed send
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// Attempts made to send a message before it is left for the background retries
+const MAX_SEND_ATTEMPTS: u32 = 4;
+/// Delay between background retries of messages that could not be sent right away
+const DEFERRED_RETRY_DELAY: Duration = Duration::from_secs(60);
 
 /// Struct representing all required data for a functioning bot instance.
 pub struct MatrixResponder {
     /// Storage data.
     pub storage: ResponderStorage,
-    recv: Receiver<MatrixMessage>,
+    /// Channel messages to send are received from. Shared so it survives a restart of the responder.
+    recv: Arc<Mutex<Receiver<MatrixMessage>>>,
 }
 
 impl MatrixResponder {
     /// Loads storage data, config data, and then creates a reqwest client and then returns a Bot instance.
-    pub fn new(recv: Receiver<MatrixMessage>) -> Self {
+    pub fn new(recv: Arc<Mutex<Receiver<MatrixMessage>>>) -> Self {
         let storage = ResponderStorage::load_storage();
         Self { storage, recv }
     }
 
     /// Used to start main program loop for the bot.
-    /// Will login then loop forever while waiting on new sync data from the homeserver.
-    pub async fn start(&mut self, client: Client) {
+    /// Will first resend any messages left in the outbox, then loop forever while waiting on new
+    /// messages until the channel is closed and empty.
+    ///
+    /// Messages that can't be sent within a few attempts are retried in the background so one
+    /// failing room or a homeserver outage doesn't hold up every message behind it.
+    pub async fn start(&mut self, client: Client, shutdown: ShutdownReceiver) {
+        let (deferred_tx, deferred_rx) = mpsc::unbounded_channel();
+        join!(
+            self.send_queued(&client, deferred_tx, shutdown.clone()),
+            retry_deferred(&client, deferred_rx, shutdown)
+        );
+    }
+
+    /// Sends messages from the outbox and then the channel, deferring those that can't be sent yet
+    async fn send_queued(
+        &mut self,
+        client: &Client,
+        deferred: UnboundedSender<OutboxMessage>,
+        mut shutdown: ShutdownReceiver,
+    ) {
+        let pending = match storage::store().run_async(|s| s.load_outbox()).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to load outbox due to error {}", e);
+                Vec::new()
+            }
+        };
+        if !pending.is_empty() {
+            info!("Resending {} messages left in the outbox", pending.len());
+        }
+        for message in pending {
+            if let Err(MatrixMessageError::Queued) = deliver(client, &message, &mut shutdown).await
+            {
+                defer(&deferred, message);
+            }
+        }
+
+        let recv = self.recv.clone();
+        let mut recv = recv.lock().await;
         loop {
-            match self.recv.recv().await {
-                Some(v) => match v.message {
-                    MatrixMessageType::Notice(m) => {
-                        send_notice(&client, &v.room_id, &mut self.storage, m).await
-                    }
-                    MatrixMessageType::FormattedText(m) => {
-                        send_formatted_text(
-                            &v.room_id,
-                            &mut self.storage,
-                            m.plain_text,
-                            m.formatted_text,
-                            &client,
-                        )
-                        .await
-                    }
-                    MatrixMessageType::Text(m) => {
-                        send_plain_text(&v.room_id, &mut self.storage, m, &client).await
-                    }
-                    MatrixMessageType::Invite(m) => match m.kind {
-                        MatrixInviteType::Accept => {
-                            accept_invite(&m.sender, &v.room_id, &client).await
+            match recv.recv().await {
+                Some(v) => {
+                    let result = match v.message {
+                        MatrixMessageType::Invite(m) => {
+                            match m.kind {
+                                MatrixInviteType::Accept => {
+                                    accept_invite(&m.sender, &v.room_id, client).await
+                                }
+                                MatrixInviteType::Reject => {
+                                    reject_invite(&m.sender, &v.room_id, client).await
+                                }
+                            }
+                            Err(MatrixMessageError::NotAMessage)
+                        }
+                        message => {
+                            let message = self.enqueue(v.room_id, message).await;
+                            let result = deliver(client, &message, &mut shutdown).await;
+                            if let Err(MatrixMessageError::Queued) = result {
+                                defer(&deferred, message);
+                            }
+                            result
                         }
-                        MatrixInviteType::Reject => {
-                            reject_invite(&m.sender, &v.room_id, &client).await
+                    };
+                    if let Some(resp) = v.resp {
+                        if resp.send(result).is_err() {
+                            debug!("Producer stopped waiting for the result of a message");
                         }
-                    },
-                    MatrixMessageType::FormattedNotice(m) => {
-                        send_formatted_notice(
-                            &v.room_id,
-                            &mut self.storage,
-                            m.plain_text,
-                            m.formatted_text,
-                            &client,
-                        )
-                        .await
                     }
-                },
+                }
                 None => {
                     info!("Matrix channel closed and empty. Exiting thread.");
                     break;
@@ -73,4 +121,138 @@ impl MatrixResponder {
             }
         }
     }
+
+    /// Assigns a transaction id to a message and saves it to the outbox
+    async fn enqueue(&mut self, room_id: RoomId, message: MatrixMessageType) -> OutboxMessage {
+        let message = OutboxMessage {
+            txn_id: self.storage.next_txn_id(),
+            room_id,
+            message,
+        };
+        self.storage.save_storage();
+        let outbox_message = message.clone();
+        let result = storage::store()
+            .run_async(move |s| s.push_outbox(&outbox_message))
+            .await;
+        if let Err(e) = result {
+            error!(
+                "Unable to save message {} to outbox due to error {}. It will be lost if sending fails",
+                message.txn_id, e
+            );
+        }
+        message
+    }
+}
+
+/// Hands a message that could not be sent yet to the background retries.
+///
+/// The message stays in the outbox, so it is still sent on next start if this fails.
+fn defer(deferred: &UnboundedSender<OutboxMessage>, message: OutboxMessage) {
+    if deferred.send(message).is_err() {
+        debug!("Background retries have stopped. Message will be sent on next start");
+    }
+}
+
+/// Retries deferred messages until they are sent or a shutdown is requested
+async fn retry_deferred(
+    client: &Client,
+    mut deferred: UnboundedReceiver<OutboxMessage>,
+    mut shutdown: ShutdownReceiver,
+) {
+    let mut waiting: Vec<OutboxMessage> = Vec::new();
+    let mut retry = interval_at(Instant::now() + DEFERRED_RETRY_DELAY, DEFERRED_RETRY_DELAY);
+    loop {
+        tokio::select! {
+            message = deferred.recv() => match message {
+                Some(v) => waiting.push(v),
+                None => break,
+            },
+            _ = retry.tick(), if !waiting.is_empty() => {
+                info!("Retrying {} messages that could not be sent", waiting.len());
+                let mut unsent = Vec::new();
+                for message in waiting.drain(..) {
+                    if let Err(MatrixMessageError::Queued) =
+                        deliver(client, &message, &mut shutdown).await
+                    {
+                        unsent.push(message);
+                    }
+                }
+                waiting = unsent;
+            }
+            _ = wait_for_shutdown(&mut shutdown) => break,
+        }
+    }
+    if !waiting.is_empty() {
+        warn!(
+            "{} messages could not be sent. They will be retried on next start",
+            waiting.len()
+        );
+    }
+}
+
+/// Sends a message from the outbox, retrying with backoff until the homeserver acknowledges it.
+///
+/// Messages are removed from the outbox once sent or if they can never be sent. Messages still
+/// unsent after `MAX_SEND_ATTEMPTS`, or after a single attempt once a shutdown has been
+/// requested, are kept in the outbox and `Queued` is returned.
+async fn deliver(
+    client: &Client,
+    message: &OutboxMessage,
+    shutdown: &mut ShutdownReceiver,
+) -> MatrixMessageResult {
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut attempts = 0;
+    let result = loop {
+        attempts += 1;
+        match send_message(client, &message.room_id, &message.txn_id, &message.message).await {
+            Ok(v) => {
+                debug!("Message {} sent as event {}", message.txn_id, v);
+                break Ok(v);
+            }
+            Err(SendError::Fatal(e)) => {
+                error!(
+                    "Unable to send message {} to room {} due to error {}. Dropping it",
+                    message.txn_id, message.room_id, e
+                );
+                break Err(MatrixMessageError::Rejected(e));
+            }
+            Err(SendError::Retry(retry_after)) => {
+                if is_shutdown(shutdown) {
+                    warn!(
+                        "Unable to send message {} before shutdown. It will be retried on next start",
+                        message.txn_id
+                    );
+                    return Err(MatrixMessageError::Queued);
+                }
+                if attempts >= MAX_SEND_ATTEMPTS {
+                    warn!(
+                        "Unable to send message {} to room {} after {} attempts. Retrying in the background",
+                        message.txn_id, message.room_id, attempts
+                    );
+                    return Err(MatrixMessageError::Queued);
+                }
+                let wait = retry_after.unwrap_or(delay);
+                warn!(
+                    "Unable to send message {} to room {}. Retrying in {:?}",
+                    message.txn_id, message.room_id, wait
+                );
+                tokio::select! {
+                    _ = delay_for(wait) => (),
+                    _ = wait_for_shutdown(shutdown) => (),
+                }
+                delay = cmp::min(delay * 2, MAX_RETRY_DELAY);
+            }
+        }
+    };
+    let txn_id = message.txn_id.clone();
+    if let Err(e) = storage::store()
+        .run_async(move |s| s.remove_outbox(&txn_id))
+        .await
+    {
+        error!(
+            "Unable to remove message {} from outbox due to error {}",
+            message.txn_id, e
+        );
+    }
+    result
 }
@@ -0,0 +1,125 @@
+//! Helpers for working with rooms given by id or alias
+
+use crate::config::{RoomAliasStorage, RoomIdOrAlias};
+use ruma::{api::client::r0::alias::get_alias, RoomAliasId, RoomId};
+use ruma_client::Client;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::{Mutex, MutexGuard};
+use tracing::{error, info, trace};
+
+/// Last known room of every alias used by the bot.
+///
+/// Aliases are saved once resolved so the rooms they point to are known at startup even if the
+/// homeserver can't be asked, and so a room keeps working if its alias is removed.
+pub struct RoomAliases {
+    /// Client used to ask the homeserver's room directory.
+    client: Client,
+    /// Every alias resolved so far.
+    storage: Mutex<RoomAliasStorage>,
+}
+
+impl RoomAliases {
+    /// Loads the saved aliases
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            storage: Mutex::new(RoomAliasStorage::load_storage()),
+        }
+    }
+
+    /// Returns a copy of every alias resolved so far
+    pub fn known(&self) -> RoomAliasStorage {
+        self.lock_storage().clone()
+    }
+
+    /// Asks the homeserver for the room of every alias, saving any that changed.
+    ///
+    /// Aliases that no longer resolve keep their last known room.
+    pub async fn refresh(&self, aliases: HashSet<RoomAliasId>) {
+        let mut changed = false;
+        for alias in aliases {
+            match self.client.request(get_alias::Request::new(&alias)).await {
+                Ok(v) => changed |= self.update(alias, v.room_id),
+                Err(e) => {
+                    let known = self.lock_storage().aliases.get(&alias).cloned();
+                    match known {
+                        Some(room_id) => error!(
+                            "Room alias {} no longer resolves due to error {:?}. Using last known room {}",
+                            alias, e, room_id
+                        ),
+                        None => error!(
+                            "Unable to resolve room alias {} due to error {:?}. Ignoring it",
+                            alias, e
+                        ),
+                    }
+                }
+            }
+        }
+        if changed {
+            self.lock_storage().save_storage();
+        }
+    }
+
+    /// Returns the id of a room, only asking the homeserver about aliases that have never resolved
+    pub async fn resolve(&self, room: &RoomIdOrAlias) -> Option<RoomId> {
+        let known = self.lock_storage().get(room);
+        if known.is_some() {
+            return known;
+        }
+        let alias = room.alias()?;
+        match self.client.request(get_alias::Request::new(alias)).await {
+            Ok(v) => {
+                if self.update(alias.clone(), v.room_id.clone()) {
+                    self.lock_storage().save_storage();
+                }
+                Some(v.room_id)
+            }
+            Err(e) => {
+                error!(
+                    "Unable to resolve room alias {} due to error {:?}",
+                    alias, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Records the room an alias points to. Returns `true` if it changed.
+    fn update(&self, alias: RoomAliasId, room_id: RoomId) -> bool {
+        let mut storage = self.lock_storage();
+        match storage.aliases.get(&alias) {
+            Some(v) if v == &room_id => {
+                trace!("Room alias {} still points to {}", alias, room_id);
+                return false;
+            }
+            Some(v) => info!("Room alias {} moved from {} to {}", alias, v, room_id),
+            None => info!("Room alias {} points to {}", alias, room_id),
+        }
+        storage.aliases.insert(alias, room_id);
+        true
+    }
+
+    /// Locks the saved aliases, ignoring poisoning since they are always left consistent
+    fn lock_storage(&self) -> MutexGuard<'_, RoomAliasStorage> {
+        self.storage.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Returns the id of a room given by id or alias
+pub async fn resolve_room(client: &Client, room: &str) -> Result<RoomId, String> {
+    if let Ok(v) = RoomId::try_from(room) {
+        return Ok(v);
+    }
+    let alias = match RoomAliasId::try_from(room) {
+        Ok(v) => v,
+        Err(_) => return Err(format!("{} is not a room id or alias", room)),
+    };
+    match client.request(get_alias::Request::new(&alias)).await {
+        Ok(v) => Ok(v.room_id),
+        Err(e) => {
+            error!("Unable to resolve alias {} due to error {:?}", alias, e);
+            Err(format!("Unable to find room {}", alias))
+        }
+    }
+}
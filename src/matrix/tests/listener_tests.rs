@@ -0,0 +1,96 @@
+use crate::config::ListenerStorage;
+use crate::matrix::dispatcher::{EventDispatcher, TextHandler};
+use crate::matrix::listener::finish_batch;
+use async_trait::async_trait;
+use ruma::{events::room::message::TextMessageEventContent, RoomId, UserId};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+/// Records every message it handles and panics on messages saying "fail"
+#[derive(Default)]
+struct FailingHandler {
+    handled: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl TextHandler for FailingHandler {
+    async fn handle_text(
+        &self,
+        _room_id: &RoomId,
+        content: &TextMessageEventContent,
+        _sender: &UserId,
+    ) {
+        if content.body == "fail" {
+            panic!("Handler failed");
+        }
+        self.handled.lock().unwrap().push(content.body.clone());
+    }
+}
+
+fn storage() -> Mutex<ListenerStorage> {
+    Mutex::new(ListenerStorage {
+        last_sync: Some("old".to_string()),
+        ..ListenerStorage::default()
+    })
+}
+
+async fn dispatch(dispatcher: &mut EventDispatcher<FailingHandler>, room: &str, bodies: &[&str]) {
+    let room_id = RoomId::try_from(room).unwrap();
+    let sender = UserId::try_from("@user:homeserver.com").unwrap();
+    for body in bodies {
+        dispatcher
+            .dispatch_text(
+                &room_id,
+                TextMessageEventContent::plain(*body),
+                sender.clone(),
+            )
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn token_advances_once_batch_is_handled() {
+    let handler = Arc::new(FailingHandler::default());
+    let mut dispatcher = EventDispatcher::with_handler(handler.clone());
+    let storage = storage();
+    dispatch(&mut dispatcher, "!a:homeserver.com", &["one", "two"]).await;
+    dispatch(&mut dispatcher, "!b:homeserver.com", &["three"]).await;
+
+    assert!(finish_batch(&mut dispatcher, &storage, "new").await);
+    let mut handled = handler.handled.lock().unwrap().clone();
+    handled.sort();
+    assert_eq!(vec!["one", "three", "two"], handled);
+    assert_eq!(Some("new"), storage.lock().unwrap().last_sync.as_deref());
+}
+
+#[tokio::test]
+async fn token_kept_when_worker_fails_mid_batch() {
+    let handler = Arc::new(FailingHandler::default());
+    let mut dispatcher = EventDispatcher::with_handler(handler.clone());
+    let storage = storage();
+    dispatch(
+        &mut dispatcher,
+        "!a:homeserver.com",
+        &["one", "fail", "three"],
+    )
+    .await;
+    dispatch(&mut dispatcher, "!b:homeserver.com", &["four"]).await;
+
+    assert!(!finish_batch(&mut dispatcher, &storage, "new").await);
+    assert!(!handler
+        .handled
+        .lock()
+        .unwrap()
+        .contains(&"three".to_string()));
+    assert_eq!(Some("old"), storage.lock().unwrap().last_sync.as_deref());
+
+    // The failed worker is replaced when the batch is synced again
+    dispatch(&mut dispatcher, "!a:homeserver.com", &["three"]).await;
+    assert!(finish_batch(&mut dispatcher, &storage, "new").await);
+    assert!(handler
+        .handled
+        .lock()
+        .unwrap()
+        .contains(&"three".to_string()));
+    assert_eq!(Some("new"), storage.lock().unwrap().last_sync.as_deref());
+}
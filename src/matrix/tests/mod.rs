@@ -0,0 +1 @@
+mod listener_tests;
@@ -1,41 +1,138 @@
 //! Performs search of issues and pulls in message text and builds proper response
+//!
+//! Every issue or pull in a message is looked up with a single query, whatever repos they are in.
+//!
+//! The GitHub API budget reported by every query is stored so no query is made once it could
+//! exceed what is left. A single query made while the budget is exhausted restarts GitHub's hour
+//! long cooldown.
+//!
+//! Results are cached for `github_cache_seconds` so only issues and pulls that haven't been
+//! mentioned recently cost a query.
 
-use crate::config::MatrixListenerConfig;
-use crate::helpers::{clean_text, MatrixNoticeResponse};
-use crate::queries::issue_or_pull::IssueOrPullRepositoryIssueOrPullRequest::{Issue, PullRequest};
-use crate::queries::*;
+use super::{Trigger, TriggerContext, TriggerResponse};
+use crate::config::{GithubRateLimit, MatrixListenerConfig};
+use crate::helpers::{GithubResult, GithubResultKind, GithubResultState, MatrixNoticeResponse};
+use crate::queries::{
+    IssueOrPull, IssueOrPullRef, IssueState, IssuesOrPulls, IssuesOrPullsData, LabelConnection,
+    PullRequestState, RateLimit,
+};
 use crate::regex::GITHUB_SEARCH;
-use graphql_client::GraphQLQuery;
-use reqwest::{header, Url};
-use ruma::events::room::message::TextMessageEventContent;
-use tracing::{debug, error, trace};
-
-/// Searches and links found issues or pulls requested and builds response text
-pub async fn github_search(
-    text: &TextMessageEventContent,
-    config: &MatrixListenerConfig,
-    api_client: &reqwest::Client,
-    notice_response: &mut MatrixNoticeResponse,
-) {
-    let mut repos_to_search = Vec::new();
-    match &text.formatted {
-        Some(v) => {
-            let clean_text = clean_text(&v.body);
-            if GITHUB_SEARCH.is_match(&clean_text) {
-                for cap in GITHUB_SEARCH.captures_iter(&clean_text.to_lowercase()) {
-                    trace!("{:?}", cap);
-                    repos_to_search.push((cap[1].to_string(), cap[2].to_string()))
-                }
-            } else {
-                debug!("There are no remaining matches after cleaning tags. Doing nothing.");
-                return;
-            }
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use graphql_client::Response;
+use regex::Regex;
+use reqwest::{
+    header::{self, HeaderMap},
+    Url,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, trace, warn};
+
+/// Searches and links issues or pulls requested in configured repos
+pub struct GithubSearchTrigger;
+
+#[async_trait]
+impl Trigger for GithubSearchTrigger {
+    fn name(&self) -> &'static str {
+        "github-search"
+    }
+
+    fn regex(&self) -> &'static Regex {
+        &GITHUB_SEARCH
+    }
+
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool {
+        !config.repos.is_empty()
+    }
+
+    fn summary(&self) -> &'static str {
+        "Search github by project and issue/PR number"
+    }
+
+    fn help(&self, _prefix: &str, config: &MatrixListenerConfig) -> String {
+        github_search_help_message(config)
+    }
+
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse) {
+        let config = ctx.config;
+        let searches = github_references(ctx.text, config);
+        debug!("Queued searches: {:?}", searches);
+        if searches.is_empty() {
+            debug!("No searches found after parsing numbers. No searches will be built.");
+            return;
         }
-        None => {
-            for cap in GITHUB_SEARCH.captures_iter(&text.body.to_lowercase()) {
-                repos_to_search.push((cap[1].to_string(), cap[2].to_string()))
-            }
+        let results = search_issues_or_pulls(ctx, &searches, response).await;
+        if results.is_empty() {
+            // Searches skipped due to the rate limit have already been logged and reported
+            debug!("No search results returned as nothing was found or searches are rate limited. Doing nothing");
+        } else {
+            let mut notice_response = MatrixNoticeResponse::default();
+            notice_response.set_gh_results(results);
+            response.add_notice(notice_response);
+        }
+    }
+}
+
+/// Issues and pulls looked up recently.
+///
+/// Popular issues are linked many times a day, and reusing their results keeps each mention from
+/// costing a query. Kept in memory only, so a restart always asks GitHub again.
+#[derive(Default)]
+pub struct GithubCache {
+    /// Each result along with when it was looked up
+    results: Mutex<HashMap<IssueOrPullRef, (Instant, GithubResult)>>,
+}
+
+impl GithubCache {
+    /// Returns the result for an issue or pull if it was looked up less than `ttl` ago
+    pub fn get(&self, reference: &IssueOrPullRef, ttl: Duration) -> Option<GithubResult> {
+        match self.lock_results().get(reference) {
+            Some((time, result)) if time.elapsed() < ttl => Some(result.clone()),
+            _ => None,
+        }
+    }
+
+    /// Stores a newly looked up result, dropping every result older than `ttl`
+    pub fn insert(&self, reference: IssueOrPullRef, result: GithubResult, ttl: Duration) {
+        let mut results = self.lock_results();
+        results.retain(|_, (time, _)| time.elapsed() < ttl);
+        if ttl > Duration::from_secs(0) {
+            results.insert(reference, (Instant::now(), result));
+        }
+    }
+
+    /// Forgets the given issues and pulls, or every one if none are given.
+    ///
+    /// Returns the number of results forgotten.
+    pub fn remove(&self, references: &[IssueOrPullRef]) -> usize {
+        let mut results = self.lock_results();
+        if references.is_empty() {
+            let count = results.len();
+            results.clear();
+            return count;
         }
+        references
+            .iter()
+            .filter(|r| results.remove(*r).is_some())
+            .count()
+    }
+
+    /// Locks the results, ignoring poisoning since they are always left consistent
+    fn lock_results(&self) -> MutexGuard<'_, HashMap<IssueOrPullRef, (Instant, GithubResult)>> {
+        self.results.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Returns every issue or pull in a configured repo mentioned in lowercase text
+pub fn github_references(text: &str, config: &MatrixListenerConfig) -> Vec<IssueOrPullRef> {
+    let mut repos_to_search = Vec::new();
+    for cap in GITHUB_SEARCH.captures_iter(text) {
+        trace!("{:?}", cap);
+        repos_to_search.push((cap[1].to_string(), cap[2].to_string()))
     }
     let repos_to_search = repos_to_search;
     let mut searches = Vec::new();
@@ -52,7 +149,11 @@ pub async fn github_search(
                     };
                     let (owner, repo) = r.split_at(index);
                     let repo = repo.replace('/', "");
-                    searches.push((owner.to_string(), repo.to_string(), n))
+                    searches.push(IssueOrPullRef {
+                        owner: owner.to_string(),
+                        name: repo.to_string(),
+                        number: n,
+                    })
                 }
                 None => {
                     debug!("Repo {:?} not found", repo);
@@ -67,89 +168,316 @@ pub async fn github_search(
             }
         }
     }
-    let searches = searches;
-    debug!("Queued searches: {:?}", searches);
-    if searches.is_empty() {
-        debug!("No searches found after parsing numbers. No searches will be built.");
-        return;
+    searches
+}
+
+/// Looks up issues or pulls, returning the ones found in the order given without duplicates.
+///
+/// Cached results are used where possible and the rest are looked up with a single query.
+pub(super) async fn search_issues_or_pulls(
+    ctx: &TriggerContext<'_>,
+    searches: &[IssueOrPullRef],
+    response: &mut TriggerResponse,
+) -> Vec<GithubResult> {
+    let ttl = ctx.config.github_cache_ttl;
+    let cache = &ctx.listener.github_cache;
+    let mut found = HashMap::new();
+    let mut missing = Vec::new();
+    for reference in searches {
+        match cache.get(reference, ttl) {
+            Some(v) => {
+                trace!("Using cached result for {:?}", reference);
+                found.insert(reference.clone(), v);
+            }
+            None => missing.push(reference.clone()),
+        }
     }
+    if !missing.is_empty() && !rate_limited(ctx, response) {
+        for (reference, result) in lookup(ctx, &missing, response).await {
+            cache.insert(reference.clone(), result.clone(), ttl);
+            found.insert(reference, result);
+        }
+    }
+    searches.iter().filter_map(|r| found.remove(r)).collect()
+}
+
+/// Looks up issues or pulls with a single query, returning the ones that were found
+async fn lookup(
+    ctx: &TriggerContext<'_>,
+    searches: &[IssueOrPullRef],
+    response: &mut TriggerResponse,
+) -> Vec<(IssueOrPullRef, GithubResult)> {
+    let query = IssuesOrPulls::new(searches);
+    let response_body = match run_query(
+        ctx,
+        response,
+        &query.build_query(),
+        |d: &IssuesOrPullsData| d.rate_limit.as_ref(),
+    )
+    .await
+    {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+
     let mut results = Vec::new();
-    for (owner, name, number) in searches {
-        let query = IssueOrPull::build_query(issue_or_pull::Variables {
-            name,
-            owner,
-            number,
-        });
-        let response_body = match api_client
-            .post("https://api.github.com/graphql")
-            .bearer_auth(config.gh_access_token.clone())
-            .header(header::USER_AGENT, config.user_agent.clone())
-            .json(&query)
-            .send()
-            .await
-        {
-            Ok(r) => {
-                let response_body: graphql_client::Response<issue_or_pull::ResponseData> =
-                    match r.json().await {
-                        Ok(b) => b,
-                        Err(e) => {
-                            error!("No response body found. Error is {:?}", e);
-                            continue;
-                        }
-                    };
-                response_body
-            }
-            Err(e) => {
-                error!("Query failed, Error is {:?}", e);
-                continue;
-            }
-        };
-        let response_data = match response_body.data {
-            Some(d) => match d.repository {
-                Some(r) => match r.issue_or_pull_request {
+    for (reference, result) in query.results(response_body) {
+        match result {
+            Ok(IssueOrPull::Issue(v)) => {
+                let url = match github_url(&v.resource_path) {
                     Some(v) => v,
-                    None => {
-                        error!("Missing issue or pull request data");
-                        continue;
-                    }
-                },
-                None => {
-                    error!("Missing repository data");
-                    continue;
-                }
-            },
-            None => {
-                error!("Missing response data");
-                continue;
+                    None => continue,
+                };
+                let result = GithubResult {
+                    kind: GithubResultKind::Issue,
+                    id: format!("#{}", v.number),
+                    title: v.title,
+                    state: Some(match v.state {
+                        IssueState::Open => GithubResultState::Open,
+                        _ => GithubResultState::Closed,
+                    }),
+                    author: v.author.map(|a| a.login),
+                    labels: label_names(v.labels),
+                    comments: Some(v.comments.total_count),
+                    url,
+                };
+                results.push((reference, result))
             }
-        };
-
-        match response_data {
-            Issue(v) => {
-                let result = "https://github.com".to_string() + &v.resource_path + "\n";
-                match Url::parse(&result) {
-                    Ok(v) => results.push(v),
-                    Err(e) => error!(
-                        "Unable to parse result {:?} to Url due to error {:?}",
-                        result, e
-                    ),
-                }
+            Ok(IssueOrPull::PullRequest(v)) => {
+                let url = match github_url(&v.resource_path) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let result = GithubResult {
+                    kind: GithubResultKind::PullRequest,
+                    id: format!("#{}", v.number),
+                    title: v.title,
+                    state: Some(match v.state {
+                        PullRequestState::Merged => GithubResultState::Merged,
+                        PullRequestState::Open if v.is_draft => GithubResultState::Draft,
+                        PullRequestState::Open => GithubResultState::Open,
+                        _ => GithubResultState::Closed,
+                    }),
+                    author: v.author.map(|a| a.login),
+                    labels: label_names(v.labels),
+                    comments: Some(v.comments.total_count),
+                    url,
+                };
+                results.push((reference, result))
             }
-            PullRequest(v) => {
-                let result = "https://github.com".to_string() + &v.resource_path + "\n";
-                match Url::parse(&result) {
-                    Ok(v) => results.push(v),
-                    Err(e) => error!(
-                        "Unable to parse result {:?} to Url due to error {:?}",
-                        result, e
-                    ),
+            Err(e) => error!(
+                "Unable to find {}/{}#{}: {}",
+                reference.owner, reference.name, reference.number, e
+            ),
+        }
+    }
+    results
+}
+
+/// Returns the names of the labels on an issue or pull request
+fn label_names(labels: Option<LabelConnection>) -> Vec<String> {
+    labels
+        .and_then(|l| l.nodes)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|l| l.name)
+        .collect()
+}
+
+/// Returns the full link to an issue or pull request from its path on GitHub
+fn github_url(resource_path: &str) -> Option<Url> {
+    parse_url(&("https://github.com".to_string() + resource_path))
+}
+
+/// Parses a link returned by GitHub, logging it if it is invalid
+pub(super) fn parse_url(result: &str) -> Option<Url> {
+    match Url::parse(result) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error!(
+                "Unable to parse result {:?} to Url due to error {:?}",
+                result, e
+            );
+            None
+        }
+    }
+}
+
+/// Reserves the GitHub budget for a query, or adds an error saying when searches resume if the
+/// query could exceed it.
+///
+/// Returns `true` if no query should be made.
+pub(super) fn rate_limited(ctx: &TriggerContext<'_>, response: &mut TriggerResponse) -> bool {
+    let reserved = ctx
+        .listener
+        .storage
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .reserve_github_query();
+    match reserved {
+        Ok(_) => false,
+        Err(v) => {
+            add_rate_limit_error(v, response);
+            true
+        }
+    }
+}
+
+/// Adds an error saying when searches resume
+fn add_rate_limit_error(resume: SystemTime, response: &mut TriggerResponse) {
+    let resume = DateTime::<Utc>::from(resume).format("%Y-%m-%d %H:%M:%S UTC");
+    warn!(
+        "GitHub rate limit reached. Skipping searches until {}",
+        resume
+    );
+    response.add_errors(vec![format!(
+        "GitHub searches are rate limited. Try again at {}",
+        resume
+    )]);
+}
+
+/// Posts a query to GitHub and stores the budget it reports.
+///
+/// Returns the response if it has data. `rate_limit` returns the budget requested in the data.
+pub(super) async fn run_query<T, F>(
+    ctx: &TriggerContext<'_>,
+    response: &mut TriggerResponse,
+    query: &Value,
+    rate_limit: F,
+) -> Option<Response<T>>
+where
+    T: DeserializeOwned,
+    F: Fn(&T) -> Option<&RateLimit>,
+{
+    let config = ctx.config;
+    let response_body = match ctx
+        .listener
+        .api_client
+        .post("https://api.github.com/graphql")
+        .bearer_auth(config.gh_access_token.clone())
+        .header(header::USER_AGENT, config.user_agent.clone())
+        .json(query)
+        .send()
+        .await
+    {
+        Ok(r) => {
+            let header_limit = header_rate_limit(ctx, r.headers());
+            let response_body: Response<T> = match r.json().await {
+                Ok(b) => b,
+                Err(e) => {
+                    error!("No response body found. Error is {:?}", e);
+                    update_rate_limit(ctx, header_limit);
+                    return None;
                 }
-            }
+            };
+            let body_limit = response_body
+                .data
+                .as_ref()
+                .and_then(rate_limit)
+                .and_then(body_rate_limit);
+            update_rate_limit(ctx, body_limit.or(header_limit));
+            response_body
+        }
+        Err(e) => {
+            error!("Query failed, Error is {:?}", e);
+            return None;
         }
+    };
+    if response_body.data.is_none() {
+        error!(
+            "Missing response data. Errors are {:?}",
+            response_body.errors
+        );
+        let resume = ctx
+            .listener
+            .storage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .github_rate_limited_until();
+        if let Some(v) = resume {
+            add_rate_limit_error(v, response);
+        }
+        return None;
     }
-    if results.is_empty() {
-        error!("No search resulted returned. Doing nothing");
-    } else {
-        notice_response.set_gh_results(results)
+    Some(response_body)
+}
+
+/// Stores the GitHub budget reported by a query if there is one
+fn update_rate_limit(ctx: &TriggerContext<'_>, limit: Option<GithubRateLimit>) {
+    if let Some(v) = limit {
+        trace!("GitHub rate limit is now {:?}", v);
+        ctx.listener
+            .storage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .github_rate_limit = Some(v);
     }
 }
+
+/// Returns the GitHub budget requested in the body of a query
+fn body_rate_limit(limit: &RateLimit) -> Option<GithubRateLimit> {
+    match DateTime::parse_from_rfc3339(&limit.reset_at) {
+        Ok(v) => Some(GithubRateLimit {
+            cost: limit.cost,
+            remaining: limit.remaining,
+            reset_at: v.into(),
+        }),
+        Err(e) => {
+            error!(
+                "Unable to parse rate limit reset time {:?} due to error {:?}",
+                limit.reset_at, e
+            );
+            None
+        }
+    }
+}
+
+/// Returns the GitHub budget sent in the headers of a response.
+///
+/// GitHub leaves the requested `rateLimit` out of the body once the budget is exhausted, so the
+/// cost of the last successful query is kept.
+fn header_rate_limit(ctx: &TriggerContext<'_>, headers: &HeaderMap) -> Option<GithubRateLimit> {
+    let value = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+    let cost = ctx
+        .listener
+        .storage
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .github_rate_limit
+        .as_ref()
+        .map_or(1, |l| l.cost);
+    Some(GithubRateLimit {
+        cost,
+        remaining: value("x-ratelimit-remaining")?,
+        reset_at: UNIX_EPOCH + Duration::from_secs(value("x-ratelimit-reset")?.max(0) as u64),
+    })
+}
+
+/// Help for github searches, listing the configured repos
+fn github_search_help_message(config: &MatrixListenerConfig) -> String {
+    let mut repos = Vec::new();
+    for repo in config.repos.keys() {
+        repos.push(repo);
+    }
+    repos.sort();
+    let mut available_repos = String::new();
+    for repo in repos {
+        available_repos.push_str(repo);
+        available_repos.push('|');
+    }
+    available_repos.pop();
+    let available_repos = available_repos.replace('|', " | ");
+    format!("Github Search
+
+This action is only available as commandless. It will trigger on anything that matches \"jf#1234\" where \"jf\" is the repo you want to search and \"1234\" is the issue or PR you want to link.
+
+If the repo and the number exist, it will provide a link to the issue or pull in a bot message.
+
+USAGE:
+\tI could use a review on jf#1234
+\tjf#1234
+
+AVAILABLE REPOS:
+{}", available_repos)
+}
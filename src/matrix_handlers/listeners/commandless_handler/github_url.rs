@@ -0,0 +1,232 @@
+//! Unfurls links to GitHub issues, pulls, commits, and releases into the same summary a search
+//! would reply with
+//!
+//! Only links to repos in `searchable_repos` are unfurled unless `github_urls_any_repo` is set.
+//! Issues and pulls share the cache and query used by searches, while commits and releases are
+//! looked up together with a query of their own.
+
+use super::github_search::{parse_url, rate_limited, run_query, search_issues_or_pulls};
+use super::{Trigger, TriggerContext, TriggerResponse};
+use crate::config::MatrixListenerConfig;
+use crate::helpers::{GithubResult, GithubResultKind, GithubResultState, MatrixNoticeResponse};
+use crate::queries::{
+    CommitOrRelease, CommitOrReleaseKind, CommitOrReleaseRef, CommitsOrReleases,
+    CommitsOrReleasesData, IssueOrPullRef,
+};
+use crate::regex::GITHUB_URL;
+use async_trait::async_trait;
+use regex::Regex;
+use tracing::{debug, error, trace};
+
+/// Summarizes GitHub links pasted in messages
+pub struct GithubUrlTrigger;
+
+#[async_trait]
+impl Trigger for GithubUrlTrigger {
+    fn name(&self) -> &'static str {
+        "github-url"
+    }
+
+    fn regex(&self) -> &'static Regex {
+        &GITHUB_URL
+    }
+
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool {
+        config.github_urls_any_repo || !config.repos.is_empty()
+    }
+
+    fn summary(&self) -> &'static str {
+        "Summarize pasted github links"
+    }
+
+    fn help(&self, _prefix: &str, config: &MatrixListenerConfig) -> String {
+        github_url_help_message(config)
+    }
+
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse) {
+        let config = ctx.config;
+        let mut issues = Vec::new();
+        let mut others = Vec::new();
+        // Release tags are case sensitive so the original text is searched
+        for cap in GITHUB_URL.captures_iter(ctx.cased_text) {
+            trace!("{:?}", cap);
+            let (owner, name) = match repo(config, &cap[1], &cap[2]) {
+                Some(v) => v,
+                None => {
+                    debug!("Repo {}/{} is not searchable", &cap[1], &cap[2]);
+                    continue;
+                }
+            };
+            if let Some(number) = cap.get(3) {
+                match number.as_str().parse::<i64>() {
+                    Ok(number) => issues.push(IssueOrPullRef {
+                        owner,
+                        name,
+                        number,
+                    }),
+                    Err(e) => error!(
+                        "Issue or pull number unable to be parsed. Error is {:?}, quantity is {:?}",
+                        e,
+                        number.as_str()
+                    ),
+                }
+                continue;
+            }
+            let (kind, id) = match (cap.get(4), cap.get(5)) {
+                (Some(hash), _) => (CommitOrReleaseKind::Commit, hash.as_str().to_lowercase()),
+                (None, Some(tag)) => (
+                    CommitOrReleaseKind::Release,
+                    // Links at the end of a sentence are followed by punctuation
+                    tag.as_str()
+                        .trim_end_matches(&['.', ',', ')', '!'][..])
+                        .to_string(),
+                ),
+                (None, None) => continue,
+            };
+            let reference = CommitOrReleaseRef {
+                owner,
+                name,
+                kind,
+                id,
+            };
+            if !others.contains(&reference) {
+                others.push(reference);
+            }
+        }
+        debug!("Queued unfurls: {:?} {:?}", issues, others);
+
+        let mut results = Vec::new();
+        if !issues.is_empty() {
+            results.extend(search_issues_or_pulls(ctx, &issues, response).await);
+        }
+        if !others.is_empty() && !rate_limited(ctx, response) {
+            results.extend(lookup(ctx, &others, response).await);
+        }
+        if results.is_empty() {
+            debug!("No links were found on GitHub. Doing nothing");
+        } else {
+            let mut notice_response = MatrixNoticeResponse::default();
+            notice_response.set_gh_results(results);
+            response.add_notice(notice_response);
+        }
+    }
+}
+
+/// Returns the owner and name to look a linked repo up by, or `None` if it can't be searched.
+///
+/// Configured repos keep the case they were configured with so they share cached results with
+/// searches.
+fn repo(config: &MatrixListenerConfig, owner: &str, name: &str) -> Option<(String, String)> {
+    let linked = format!("{}/{}", owner, name);
+    match config
+        .repos
+        .values()
+        .find(|r| r.eq_ignore_ascii_case(&linked))
+    {
+        Some(r) => {
+            let (owner, name) = r.split_at(r.find('/')?);
+            Some((owner.to_string(), name[1..].to_string()))
+        }
+        None if config.github_urls_any_repo => Some((owner.to_string(), name.to_string())),
+        None => None,
+    }
+}
+
+/// Looks up commits or releases with a single query, returning the ones that were found
+async fn lookup(
+    ctx: &TriggerContext<'_>,
+    searches: &[CommitOrReleaseRef],
+    response: &mut TriggerResponse,
+) -> Vec<GithubResult> {
+    let query = CommitsOrReleases::new(searches);
+    let response_body = match run_query(
+        ctx,
+        response,
+        &query.build_query(),
+        |d: &CommitsOrReleasesData| d.rate_limit.as_ref(),
+    )
+    .await
+    {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+
+    let mut results = Vec::new();
+    for (reference, result) in query.results(response_body) {
+        match result {
+            Ok(CommitOrRelease::Commit(v)) => {
+                let url = match parse_url(&v.url) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                results.push(GithubResult {
+                    kind: GithubResultKind::Commit,
+                    id: v.abbreviated_oid,
+                    title: v.message_headline,
+                    state: None,
+                    author: v.author.and_then(|a| a.user.map(|u| u.login).or(a.name)),
+                    labels: Vec::new(),
+                    comments: Some(v.comments.total_count),
+                    url,
+                })
+            }
+            Ok(CommitOrRelease::Release(v)) => {
+                let url = match parse_url(&v.url) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let title = match v.name {
+                    Some(name) if !name.is_empty() => name,
+                    _ => v.tag_name.clone(),
+                };
+                results.push(GithubResult {
+                    kind: GithubResultKind::Release,
+                    id: v.tag_name,
+                    title,
+                    state: if v.is_prerelease {
+                        Some(GithubResultState::Prerelease)
+                    } else {
+                        None
+                    },
+                    author: v.author.map(|a| a.login),
+                    labels: Vec::new(),
+                    comments: None,
+                    url,
+                })
+            }
+            Ok(CommitOrRelease::Other) => error!(
+                "{}/{}@{} is not a commit",
+                reference.owner, reference.name, reference.id
+            ),
+            Err(e) => error!(
+                "Unable to find {}/{}@{}: {}",
+                reference.owner, reference.name, reference.id, e
+            ),
+        }
+    }
+    results
+}
+
+/// Help for github links, listing the repos links are summarized for
+fn github_url_help_message(config: &MatrixListenerConfig) -> String {
+    let repos = if config.github_urls_any_repo {
+        "Links to any repo are summarized".to_string()
+    } else {
+        let mut repos: Vec<&str> = config.repos.values().map(String::as_str).collect();
+        repos.sort_unstable();
+        repos.dedup();
+        repos.join(" | ")
+    };
+    format!("Github Url
+
+This action is only available as commandless. It will trigger on links to github issues, pulls, commits, and releases.
+
+If the link is found, it will provide the same summary as github-search in a bot message.
+
+USAGE:
+\tThis was fixed in https://github.com/jellyfin/jellyfin/pull/1234
+\thttps://github.com/jellyfin/jellyfin/releases/tag/v10.6.0
+
+AVAILABLE REPOS:
+{}", repos)
+}
@@ -1,65 +1,94 @@
 //! Performs group pings based on message text and builds proper response
 
+use super::{Trigger, TriggerContext, TriggerResponse};
 use crate::config::MatrixListenerConfig;
-use crate::helpers::{clean_text, MatrixFormattedTextResponse};
 use crate::regex::GROUP_PING;
-use ruma::{events::room::message::TextMessageEventContent, UserId};
+use async_trait::async_trait;
+use regex::Regex;
+use ruma::UserId;
 use std::collections::HashSet;
 use tracing::{debug, error, trace};
 
-/// Finds requested users to ping and builds response text
-pub fn group_ping(
-    text: &TextMessageEventContent,
-    sender: &UserId,
-    config: &MatrixListenerConfig,
-    text_response: &mut MatrixFormattedTextResponse,
-) {
-    let mut users: HashSet<UserId> = HashSet::new();
-    if !config.group_ping_users.contains(sender) {
-        debug!("User not authorized for group pings. Ignoring...");
-        return;
+/// Pings every user in the groups requested by an authorized user
+pub struct GroupPingTrigger;
+
+#[async_trait]
+impl Trigger for GroupPingTrigger {
+    fn name(&self) -> &'static str {
+        "ping"
+    }
+
+    fn regex(&self) -> &'static Regex {
+        &GROUP_PING
     }
-    match &text.formatted {
-        Some(v) => {
-            let clean_text = clean_text(&v.body);
-            if GROUP_PING.is_match(&clean_text) {
-                for cap in GROUP_PING.captures_iter(&clean_text.to_lowercase()) {
-                    trace!("{:?}", cap);
-                    match config.group_pings.get(&cap[1]) {
-                        Some(v) => {
-                            for user in v {
-                                users.insert(user.clone());
-                            }
-                        }
-                        None => error!("Somehow lost group between regex match and insertion!"),
+
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool {
+        !config.group_pings.is_empty()
+    }
+
+    fn summary(&self) -> &'static str {
+        "Ping a group of people"
+    }
+
+    fn help(&self, _prefix: &str, config: &MatrixListenerConfig) -> String {
+        group_ping_help_message(config)
+    }
+
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse) {
+        let config = ctx.config;
+        let mut users: HashSet<UserId> = HashSet::new();
+        if !config.group_ping_users.contains(ctx.sender) {
+            debug!("User not authorized for group pings. Ignoring...");
+            return;
+        }
+        for cap in GROUP_PING.captures_iter(ctx.text) {
+            trace!("{:?}", cap);
+            match config.group_pings.get(&cap[1]) {
+                Some(v) => {
+                    for user in v {
+                        users.insert(user.clone());
                     }
                 }
-            } else {
-                debug!("There are no remaining matches after cleaning tags. Doing nothing.");
-                return;
+                None => error!("Somehow lost group between regex match and insertion!"),
             }
         }
-        None => {
-            for cap in GROUP_PING.captures_iter(&text.body.to_lowercase()) {
-                trace!("{:?}", cap);
-                match config.group_pings.get(&cap[1]) {
-                    Some(v) => {
-                        for user in v {
-                            users.insert(user.clone());
-                        }
-                    }
-                    None => error!("Somehow lost group between regex match and insertion!"),
-                }
+        if users.is_empty() {
+            debug!("No users to ping after processing.");
+        } else {
+            // Remove user that requested ping if they exist in the list AND arent the only one in the list
+            if users.len() != 1 {
+                users.remove(ctx.sender);
             }
+            response.pings.set_users(users);
         }
     }
-    if users.is_empty() {
-        debug!("No users to ping after processing.");
-    } else {
-        // Remove user that requested ping if they exist in the list AND arent the only one in the list
-        if users.len() != 1 {
-            users.remove(&sender);
-        }
-        text_response.set_users(users);
+}
+
+/// Help for group pings, listing the configured groups
+fn group_ping_help_message(config: &MatrixListenerConfig) -> String {
+    let mut groups = Vec::new();
+    for group in config.group_pings.keys() {
+        groups.push(group);
     }
+    groups.sort();
+    let mut available_groups = String::new();
+    for group in groups {
+        available_groups.push_str(group);
+        available_groups.push('|');
+    }
+    available_groups.pop();
+    let available_groups = available_groups.replace('|', " | ");
+    format!("Group Ping
+
+This action is only available as commandless. It will trigger on anything that matches \"%group\" where \"group\" is the group you want to ping.
+
+If the group exists and you are authorized to make a group ping, a message pinging everyone in the group will be made in a bot message.
+
+USAGE:
+\tHey there %server can you look at this for me?
+\t%server
+
+AVAILABLE GROUPS:
+{}", available_groups
+    )
 }
@@ -1,71 +1,116 @@
 //! Performs lookup of URLs in message text and builds proper response
 
+use super::{Trigger, TriggerContext, TriggerResponse};
 use crate::config::MatrixListenerConfig;
-use crate::helpers::{clean_text, MatrixNoticeResponse};
+use crate::helpers::MatrixNoticeResponse;
 use crate::regex::LINK_URL;
+use async_trait::async_trait;
+use regex::Regex;
 use reqwest::Url;
-use ruma::events::room::message::TextMessageEventContent;
 use tracing::{debug, error, trace};
 
-/// Finds and links URLs requested and builds response text
-pub fn link_url(
-    text: &TextMessageEventContent,
-    config: &MatrixListenerConfig,
-    notice_response: &mut MatrixNoticeResponse,
-) {
-    let mut links: Vec<String> = Vec::new();
-    match &text.formatted {
-        Some(v) => {
-            let clean_text = clean_text(&v.body);
-            if LINK_URL.is_match(&clean_text) {
-                for cap in LINK_URL.captures_iter(&clean_text.to_lowercase()) {
-                    trace!("{:?}", cap);
-                    if config.linkers.contains(&cap[1].to_lowercase()) {
-                        match config.links.get(&cap[2].to_string()) {
-                            Some(v) => {
-                                links.push(v.to_string())
-                            }
-                            None => error!("Somehow lost link between matching it and inserting it into reply list!")
-                        }
-                    } else {
-                        debug!("No link found for {}", cap[2].to_string())
-                    }
+/// Finds and links URLs requested by a configured keyword
+pub struct LinkTrigger;
+
+#[async_trait]
+impl Trigger for LinkTrigger {
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    fn regex(&self) -> &'static Regex {
+        &LINK_URL
+    }
+
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool {
+        !config.links.is_empty() && !config.linkers.is_empty()
+    }
+
+    fn summary(&self) -> &'static str {
+        "Shortcuts for linking helpful URLs"
+    }
+
+    fn help(&self, _prefix: &str, config: &MatrixListenerConfig) -> String {
+        link_help_message(config)
+    }
+
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse) {
+        let config = ctx.config;
+        let mut links: Vec<String> = Vec::new();
+        for cap in LINK_URL.captures_iter(ctx.text) {
+            trace!("{:?}", cap);
+            if config.linkers.contains(&cap[1].to_lowercase()) {
+                match config.links.get(&cap[2].to_string()) {
+                    Some(v) => links.push(v.to_string()),
+                    None => error!(
+                        "Somehow lost link between matching it and inserting it into reply list!"
+                    ),
                 }
             } else {
-                debug!("There are no remaining matches after cleaning tags. Doing nothing.");
-                return;
+                debug!("No link found for {}", cap[2].to_string())
             }
         }
-        None => {
-            for cap in LINK_URL.captures_iter(&text.body.to_lowercase()) {
-                trace!("{:?}", cap);
-                if config.linkers.contains(&cap[1].to_lowercase()) {
-                    match config.links.get(&cap[2].to_string()) {
-                        Some(v) => {
-                            links.push(v.to_string())
-                        }
-                        None => error!("Somehow lost link between matching it and inserting it into reply list!")
-                    }
-                } else {
-                    debug!("No link found for {}", cap[2].to_string())
+
+        if links.is_empty() {
+            debug!("No links to build response with after processing");
+        } else {
+            let mut results = Vec::new();
+            for result in links {
+                match Url::parse(&result) {
+                    Ok(v) => results.push(v),
+                    Err(e) => error!(
+                        "Unable to parse result {:?} to Url due to error {:?}",
+                        result, e
+                    ),
                 }
             }
+            let mut notice_response = MatrixNoticeResponse::default();
+            notice_response.set_links(results);
+            response.add_notice(notice_response);
         }
     }
+}
 
-    if links.is_empty() {
-        debug!("No links to build response with after processing");
-    } else {
-        let mut results = Vec::new();
-        for result in links {
-            match Url::parse(&result) {
-                Ok(v) => results.push(v),
-                Err(e) => error!(
-                    "Unable to parse result {:?} to Url due to error {:?}",
-                    result, e
-                ),
-            }
-        }
-        notice_response.set_links(results);
+/// Help for links, listing the configured keywords and links
+fn link_help_message(config: &MatrixListenerConfig) -> String {
+    let mut keywords = Vec::new();
+    for keyword in &config.linkers {
+        keywords.push(keyword);
+    }
+    keywords.sort();
+    let mut available_keywords = String::new();
+    for keyword in keywords {
+        available_keywords.push_str(&keyword);
+        available_keywords.push('|');
+    }
+    available_keywords.pop();
+    let available_keywords = available_keywords.replace('|', " | ");
+    let mut links = Vec::new();
+    for link in config.links.keys() {
+        links.push(link);
+    }
+    links.sort();
+    let mut available_links = String::new();
+    for link in links {
+        available_links.push_str(&link);
+        available_links.push('|');
     }
+    available_links.pop();
+    let available_links = available_links.replace('|', " | ");
+    format!("Link
+
+This action is only available as commandless. It will trigger on anything that matches \"link@hwa\" where \"link\" is a configured keyword and \"hwa\" is a linkable item.
+
+if the keyword and item exist, there will be a link provided in a bot message.
+
+USAGE:
+\tI think you might want to look at link@hwa
+\tlink@hwa
+
+AVAILABLE KEYWORDS:
+{}
+
+AVAILABLE LINKS:
+{}
+    ", available_keywords, available_links)
 }
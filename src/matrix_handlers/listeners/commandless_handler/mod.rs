@@ -1,122 +1,279 @@
 //! Contains handlers and response functions for text based non-command events
+//!
+//! Every commandless action except spellcheck implements `Trigger`. Triggers run in the order
+//! configured for the room over a single copy of the message text that has been cleaned of
+//! replies and code.
 
 mod github_search;
+mod github_url;
 mod group_ping;
 mod link_url;
 mod spellcheck;
 mod unit_conversion;
 
-use crate::config::{ListenerStorage, MatrixListenerConfig};
-use crate::helpers::{check_format, MatrixFormattedTextResponse, MatrixNoticeResponse};
+use super::ListenerContext;
+use crate::config::MatrixListenerConfig;
+use crate::helpers::{
+    check_format, clean_text, MatrixFormattedNoticeResponse, MatrixFormattedTextResponse,
+    MatrixNoticeResponse,
+};
 use crate::messages::{MatrixFormattedMessage, MatrixMessage, MatrixMessageType};
-use crate::regex::{GITHUB_SEARCH, GROUP_PING, LINK_URL, UNIT_CONVERSION};
-use github_search::github_search;
-use group_ping::group_ping;
-use link_url::link_url;
+use async_trait::async_trait;
+use github_search::GithubSearchTrigger;
+use github_url::GithubUrlTrigger;
+use group_ping::GroupPingTrigger;
+use link_url::LinkTrigger;
+use regex::Regex;
 use ruma::{events::room::message::TextMessageEventContent, RoomId, UserId};
 use spellcheck::spellcheck;
+use std::collections::HashMap;
 use std::time::SystemTime;
 use tokio::sync::mpsc::Sender;
 use tracing::{debug, error, trace};
-use unit_conversion::unit_conversion;
+use unit_conversion::UnitConversionTrigger;
+
+pub use github_search::{github_references, GithubCache};
+pub use unit_conversion::unit_conversion_help_message;
+
+/// A commandless action that runs on any message matching its regex
+#[async_trait]
+pub trait Trigger: Send + Sync {
+    /// Name used to enable and order the trigger in the config
+    fn name(&self) -> &'static str;
+    /// Regex the cleaned message text must match for the trigger to run
+    fn regex(&self) -> &'static Regex;
+    /// Returns `true` if the trigger has everything it needs in the config to run
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool;
+    /// Single line description shown in the list of actions
+    fn summary(&self) -> &'static str;
+    /// Detailed help shown by the help command
+    fn help(&self, prefix: &str, config: &MatrixListenerConfig) -> String;
+    /// Adds the trigger's contribution for the matched message to the response
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse);
+}
+
+/// Data available to a trigger while it runs
+pub struct TriggerContext<'a> {
+    /// Lowercase message text with replies and code removed
+    pub text: &'a str,
+    /// Message text with replies and code removed, in its original case
+    pub cased_text: &'a str,
+    /// User that sent the message
+    pub sender: &'a UserId,
+    /// Configuration for the room the message was sent in
+    pub config: &'a MatrixListenerConfig,
+    /// Configuration, storage, and clients shared by all handlers
+    pub listener: &'a ListenerContext,
+}
+
+#[derive(Debug, Default)]
+/// Responses built by all triggers that ran for a message
+pub struct TriggerResponse {
+    /// Notice lines in the order the triggers that added them ran
+    notices: Vec<String>,
+    /// Html version of each notice in `notices`
+    formatted_notices: Vec<String>,
+    /// Users to ping in a formatted text message
+    pings: MatrixFormattedTextResponse,
+    /// Errors users should know about in a formatted notice
+    errors: MatrixFormattedNoticeResponse,
+}
+
+impl TriggerResponse {
+    /// Adds the contents of a notice response if it has any
+    pub fn add_notice(&mut self, notice: MatrixNoticeResponse) {
+        if let Some(v) = notice.format_text() {
+            self.notices.push(notice.to_string());
+            self.formatted_notices.push(v);
+        }
+    }
+
+    /// Adds errors to reply with
+    pub fn add_errors(&mut self, errors: Vec<String>) {
+        self.errors.add_errrors(errors);
+    }
+
+    /// Returns `true` if no trigger added a response
+    pub fn is_empty(&self) -> bool {
+        self.notices.is_empty() && !self.pings.is_some() && !self.errors.is_some()
+    }
+}
+
+/// Set of triggers and the order they run in for each room
+pub struct TriggerRegistry {
+    /// All known triggers in the order they were registered
+    triggers: Vec<Box<dyn Trigger>>,
+    /// Index into `triggers` for every name
+    names: HashMap<&'static str, usize>,
+    /// Names of triggers to run in rooms without their own list
+    order: Vec<String>,
+    /// Names of triggers to run in rooms with their own list
+    room_order: HashMap<RoomId, Vec<String>>,
+}
+
+impl TriggerRegistry {
+    /// Creates a registry of every trigger built into the bot, ordered as configured
+    pub fn new(config: &MatrixListenerConfig) -> Self {
+        let mut registry = Self {
+            triggers: Vec::new(),
+            names: HashMap::new(),
+            order: config.trigger_order.clone(),
+            room_order: config.room_triggers.clone(),
+        };
+        registry.register(Box::new(UnitConversionTrigger));
+        registry.register(Box::new(GithubSearchTrigger));
+        registry.register(Box::new(GithubUrlTrigger));
+        registry.register(Box::new(LinkTrigger));
+        registry.register(Box::new(GroupPingTrigger));
+        registry
+    }
+
+    /// Adds a trigger to the registry. It only runs if its name is in the configured order.
+    pub fn register(&mut self, trigger: Box<dyn Trigger>) {
+        self.names.insert(trigger.name(), self.triggers.len());
+        self.triggers.push(trigger);
+    }
+
+    /// Finds a trigger by its name, ignoring case
+    pub fn find(&self, name: &str) -> Option<&dyn Trigger> {
+        self.names
+            .get(name.to_lowercase().as_str())
+            .map(|v| self.triggers[*v].as_ref())
+    }
+
+    /// Returns every registered trigger in the order they were registered
+    pub fn triggers(&self) -> impl Iterator<Item = &dyn Trigger> {
+        self.triggers.iter().map(|t| t.as_ref())
+    }
+
+    /// Returns the triggers to run in a room in the order they should run
+    pub fn for_room<'a>(&'a self, room_id: &RoomId) -> impl Iterator<Item = &'a dyn Trigger> {
+        let order = self.room_order.get(room_id).unwrap_or(&self.order);
+        order
+            .iter()
+            .filter_map(move |name| self.names.get(name.as_str()))
+            .map(move |v| self.triggers[*v].as_ref())
+    }
+}
 
 /// Handler for all text based non-command events
 pub(super) async fn commandless_handler(
     text: &TextMessageEventContent,
     sender: &UserId,
     room_id: &RoomId,
-    storage: &mut ListenerStorage,
-    config: &MatrixListenerConfig,
-    api_client: &reqwest::Client,
+    listener: &ListenerContext,
     send: &mut Sender<MatrixMessage>,
 ) {
+    let config = listener.config.for_room(room_id);
     if sender == &config.mx_uname {
         // do nothing if message is from self
         trace!("Message is from self, doing nothing");
-    } else {
-        match check_format(text.formatted.as_ref().map(|f| &f.format)) {
-            Ok(_) => {
-                let mut notice_response = MatrixNoticeResponse::default();
-                let mut text_response = MatrixFormattedTextResponse::default();
-                if UNIT_CONVERSION.is_match(&text.body) && config.enable_unit_conversions {
-                    debug!("Entering commandless unit conversion path");
-                    unit_conversion(&text, &config, &mut notice_response);
-                }
-                if GITHUB_SEARCH.is_match(&text.body) && !config.repos.is_empty() {
-                    debug!("Entering commandless github search path");
-                    github_search(&text, &config, &api_client, &mut notice_response).await;
-                }
-                if LINK_URL.is_match(&text.body)
-                    && !config.links.is_empty()
-                    && !config.linkers.is_empty()
-                {
-                    debug!("Entering commandless url linking path");
-                    link_url(&text, &config, &mut notice_response);
-                }
-                if GROUP_PING.is_match(&text.body) {
-                    debug!("Entering commandless group ping path");
-                    group_ping(&text, &sender, &config, &mut text_response);
-                }
+        return;
+    }
+    if let Err(e) = check_format(text.formatted.as_ref().map(|f| &f.format)) {
+        error!("{:?}", e);
+        return;
+    }
+    let cased_text = match &text.formatted {
+        Some(v) => clean_text(&v.body),
+        None => text.body.clone(),
+    };
+    let clean_text = cased_text.to_lowercase();
+    let ctx = TriggerContext {
+        text: &clean_text,
+        cased_text: &cased_text,
+        sender,
+        config,
+        listener,
+    };
+    let mut response = TriggerResponse::default();
+    for trigger in listener.triggers.for_room(room_id) {
+        if trigger.enabled(config) && trigger.regex().is_match(&clean_text) {
+            debug!("Entering commandless {} path", trigger.name());
+            trigger.respond(&ctx, &mut response).await;
+        }
+    }
 
-                let notice_response = notice_response;
-                let text_response = text_response;
-
-                if notice_response.is_some() {
-                    match send
-                        .send(MatrixMessage {
-                            room_id: room_id.clone(),
-                            message: MatrixMessageType::Notice(notice_response.to_string()),
-                        })
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(_) => error!("Channel closed. Unable to send message."),
-                    };
-                }
-                if text_response.is_some() {
-                    let message = MatrixFormattedMessage {
-                        plain_text: text_response.to_string(),
-                        formatted_text: text_response.format_text(),
-                    };
-                    match send
-                        .send(MatrixMessage {
-                            room_id: room_id.clone(),
-                            message: MatrixMessageType::FormattedText(message),
-                        })
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(_) => error!("Channel closed. Unable to send message."),
-                    };
-                }
-                if config.enable_corrections
-                    && text.relates_to.is_none()
-                    && storage.correction_time_cooldown(room_id)
-                    && !config.correction_exclusion.contains(room_id)
-                    && !notice_response.is_some()
-                    && !text_response.is_some()
-                {
-                    if let Some(v) = spellcheck(text, sender, config) {
-                        match send
-                            .send(MatrixMessage {
-                                room_id: room_id.clone(),
-                                message: MatrixMessageType::Text(v),
-                            })
-                            .await
-                        {
-                            Ok(_) => {
-                                storage
-                                    .last_correction_time
-                                    .insert(room_id.clone(), SystemTime::now());
-                            }
-                            Err(_) => error!("Channel closed. Unable to send message."),
-                        };
-                    }
+    if !response.notices.is_empty() {
+        let message = MatrixFormattedMessage {
+            plain_text: response.notices.join("\n"),
+            formatted_text: Some(response.formatted_notices.join("<br>\n")),
+        };
+        match send
+            .send(MatrixMessage {
+                room_id: room_id.clone(),
+                message: MatrixMessageType::FormattedNotice(message),
+                resp: None,
+            })
+            .await
+        {
+            Ok(_) => (),
+            Err(_) => error!("Channel closed. Unable to send message."),
+        };
+    }
+    if response.pings.is_some() {
+        let message = MatrixFormattedMessage {
+            plain_text: response.pings.to_string(),
+            formatted_text: response.pings.format_text(),
+        };
+        match send
+            .send(MatrixMessage {
+                room_id: room_id.clone(),
+                message: MatrixMessageType::FormattedText(message),
+                resp: None,
+            })
+            .await
+        {
+            Ok(_) => (),
+            Err(_) => error!("Channel closed. Unable to send message."),
+        };
+    }
+    if response.errors.is_some() {
+        let message = MatrixFormattedMessage {
+            plain_text: response.errors.to_string(),
+            formatted_text: response.errors.format_text(),
+        };
+        match send
+            .send(MatrixMessage {
+                room_id: room_id.clone(),
+                message: MatrixMessageType::FormattedNotice(message),
+                resp: None,
+            })
+            .await
+        {
+            Ok(_) => (),
+            Err(_) => error!("Channel closed. Unable to send message."),
+        };
+    }
+    if config.enable_corrections
+        && text.relates_to.is_none()
+        && listener
+            .storage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .correction_time_cooldown(room_id)
+        && !config.correction_exclusion.contains(room_id)
+        && response.is_empty()
+    {
+        if let Some(v) = spellcheck(text, sender, config) {
+            match send
+                .send(MatrixMessage {
+                    room_id: room_id.clone(),
+                    message: MatrixMessageType::Text(v),
+                    resp: None,
+                })
+                .await
+            {
+                Ok(_) => {
+                    listener
+                        .storage
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .last_correction_time
+                        .insert(room_id.clone(), SystemTime::now());
                 }
-            }
-            Err(e) => {
-                error!("{:?}", e);
-            }
+                Err(_) => error!("Channel closed. Unable to send message."),
+            };
         }
     }
 }
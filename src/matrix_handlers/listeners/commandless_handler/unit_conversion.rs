@@ -1,41 +1,53 @@
 //! Performs unit conversions and adds them to response data
 
+use super::{Trigger, TriggerContext, TriggerResponse};
 use crate::config::MatrixListenerConfig;
-use crate::helpers::{clean_text, convert_unit, MatrixNoticeResponse};
+use crate::helpers::{convert_unit, MatrixNoticeResponse};
 use crate::regex::UNIT_CONVERSION;
-use ruma::events::room::message::TextMessageEventContent;
+use async_trait::async_trait;
+use regex::Regex;
 use tracing::{debug, trace};
 
-/// Adds unit conversions to the supplied BotResponseNotice
-pub fn unit_conversion(
-    text: &TextMessageEventContent,
-    config: &MatrixListenerConfig,
-    notice_response: &mut MatrixNoticeResponse,
-) {
-    let mut conversions = Vec::new();
-    match &text.formatted {
-        Some(v) => {
-            let clean_text = clean_text(&v.body);
-            if UNIT_CONVERSION.is_match(&clean_text) {
-                for cap in UNIT_CONVERSION.captures_iter(&clean_text.to_lowercase()) {
-                    process_capture(&cap, &config, &mut conversions)
-                }
-            } else {
-                debug!("There are no remaining matches after cleaning tags. Doing nothing.");
-                return;
-            }
+/// Adds unit conversions for any quantities with units in the message
+pub struct UnitConversionTrigger;
+
+#[async_trait]
+impl Trigger for UnitConversionTrigger {
+    fn name(&self) -> &'static str {
+        "unit-conversion"
+    }
+
+    fn regex(&self) -> &'static Regex {
+        &UNIT_CONVERSION
+    }
+
+    fn enabled(&self, config: &MatrixListenerConfig) -> bool {
+        config.enable_unit_conversions
+    }
+
+    fn summary(&self) -> &'static str {
+        "Convert common conversational units"
+    }
+
+    fn help(&self, prefix: &str, config: &MatrixListenerConfig) -> String {
+        unit_conversion_help_message(prefix, config)
+    }
+
+    async fn respond(&self, ctx: &TriggerContext<'_>, response: &mut TriggerResponse) {
+        let mut conversions = Vec::new();
+        for cap in UNIT_CONVERSION.captures_iter(ctx.text) {
+            process_capture(&cap, ctx.config, &mut conversions)
         }
-        None => {
-            for cap in UNIT_CONVERSION.captures_iter(&text.body.to_lowercase()) {
-                process_capture(&cap, &config, &mut conversions)
+        let conversions = conversions;
+        match convert_unit(conversions) {
+            Some(v) => {
+                let mut notice_response = MatrixNoticeResponse::default();
+                notice_response.set_unit_conversions(v);
+                response.add_notice(notice_response);
             }
+            None => debug!("No convertable units found. No unit conversions will be performed."),
         }
     }
-    let conversions = conversions;
-    match convert_unit(conversions) {
-        Some(v) => notice_response.set_unit_conversions(v),
-        None => debug!("No convertable units found. No unit conversions will be performed."),
-    }
 }
 
 /// Processes a unit conversion regex capture into a Vec
@@ -66,3 +78,44 @@ fn capture_not_excluded(capture: &regex::Captures, config: &MatrixListenerConfig
     }
     true
 }
+
+/// Help for unit conversion, both as a command and commandless action
+pub fn unit_conversion_help_message(prefix: &str, config: &MatrixListenerConfig) -> String {
+    let mut units = Vec::new();
+    for unit in &config.unit_conversion_exclusion {
+        units.push(unit);
+    }
+    units.sort();
+    let mut space_excluded_units = String::new();
+    for unit in units {
+        space_excluded_units.push_str(&unit);
+        space_excluded_units.push('|');
+    }
+    space_excluded_units.pop();
+    let space_excluded_units = space_excluded_units.replace('|', " | ");
+    format!("Unit Conversion
+
+This action is available as both a command and commanless. It will convert common converstation units Imperial <-> Metric to help ease international chat. There can be a space between the quantity and unit except for the units excluded by configuration (listed below).
+
+USAGE:
+\tCOMMAND:
+\t\t{}convert 20c
+
+\tCOMMANDLESS:
+\t\tIt's weird that the speed limit here is 45mph
+\t\t45 mph
+
+SUPPORTED UNITS:
+LENGTH:
+cm | m | km | in | ft | mi | mile | miles
+TEMPERATURE:
+c | °c | f | °f
+WEIGHT:
+kg | lbs
+SPEED:
+km/h | kmh | kph | kmph | mph
+
+SPACE EXCLUDED UNITS:
+{}
+    ", prefix, space_excluded_units)
+}
@@ -0,0 +1,360 @@
+//! Commands that let admins manage the rooms the bot is in
+
+use super::{Command, CommandContext, Permission};
+use crate::config::MatrixListenerConfig;
+use crate::matrix::rooms::resolve_room;
+use crate::messages::{MatrixMessage, MatrixMessageError, MatrixMessageType, DELIVERY_TIMEOUT};
+use async_trait::async_trait;
+use ruma::{
+    api::client::r0::{
+        membership::{join_room_by_id_or_alias, joined_members, joined_rooms, leave_room},
+        state::get_state_events_for_key,
+    },
+    events::{room::name::NameEventContent, EventType},
+    RoomId, RoomIdOrAliasId,
+};
+use ruma_client::Client;
+use std::convert::TryFrom;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use tracing::{debug, error, info};
+
+/// Joins a room by id or alias
+pub(super) struct JoinCommand;
+
+/// Leaves the current room or the room given
+pub(super) struct LeaveCommand;
+
+/// Lists the rooms the bot is in
+pub(super) struct RoomsCommand;
+
+/// Sends a message to a room as the bot
+pub(super) struct SayCommand;
+
+#[async_trait]
+impl Command for JoinCommand {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "Join a room"
+    }
+
+    fn usage(&self) -> &'static str {
+        "ROOM"
+    }
+
+    fn help(&self, prefix: &str, _config: &MatrixListenerConfig) -> String {
+        format!(
+            "Join
+
+Joins a room by its id or alias. Only available to authorized users.
+
+USAGE:
+\t{}join #room:example.com
+\t{}join !roomid:example.com
+",
+            prefix, prefix
+        )
+    }
+
+    fn parse_args(&self, args: &str) -> Result<Vec<String>, String> {
+        single_room_arg(args)?.ok_or_else(|| "No room given".to_string())
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        let room = match RoomIdOrAliasId::try_from(args[0].as_str()) {
+            Ok(v) => v,
+            Err(_) => {
+                ctx.reply_errors(vec![format!("{} is not a room id or alias", args[0])])
+                    .await;
+                return;
+            }
+        };
+        let req = join_room_by_id_or_alias::Request::new(&room);
+        match ctx.listener.matrix_client.request(req).await {
+            Ok(v) => {
+                info!("Joined room {} as requested by {}", v.room_id, ctx.sender);
+                ctx.reply(MatrixMessageType::Notice(format!("Joined {}", v.room_id)))
+                    .await;
+            }
+            Err(e) => {
+                error!("Unable to join room {} due to error {:?}", room, e);
+                ctx.reply_errors(vec![format!("Unable to join {}", room)])
+                    .await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Command for LeaveCommand {
+    fn name(&self) -> &'static str {
+        "leave"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "Leave this room or another room"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[ROOM]"
+    }
+
+    fn help(&self, prefix: &str, _config: &MatrixListenerConfig) -> String {
+        format!(
+            "Leave
+
+Leaves the room given by id or alias, or the current room if none is given. Only available to authorized users.
+
+USAGE:
+\t{}leave
+\t{}leave #room:example.com
+",
+            prefix, prefix
+        )
+    }
+
+    fn parse_args(&self, args: &str) -> Result<Vec<String>, String> {
+        Ok(single_room_arg(args)?.unwrap_or_default())
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        let room_id = match args.first() {
+            Some(v) => match resolve_room(&ctx.listener.matrix_client, v).await {
+                Ok(v) => v,
+                Err(e) => {
+                    ctx.reply_errors(vec![e]).await;
+                    return;
+                }
+            },
+            None => ctx.room_id.clone(),
+        };
+        let current_room = &room_id == ctx.room_id;
+        if current_room {
+            // The reply can no longer be sent once the room has been left, so wait until it is
+            let (resp, recv) = oneshot::channel();
+            let message = MatrixMessage {
+                room_id: room_id.clone(),
+                message: MatrixMessageType::Notice("Leaving room".to_string()),
+                resp: Some(resp),
+            };
+            if ctx.send.send(message).await.is_err() {
+                error!("Channel closed. Unable to send message.");
+            } else if timeout(DELIVERY_TIMEOUT, recv).await.is_err() {
+                debug!("Leaving room {} before the notice was sent", room_id);
+            }
+        }
+        let req = leave_room::Request::new(&room_id);
+        match ctx.listener.matrix_client.request(req).await {
+            Ok(_) => {
+                info!("Left room {} as requested by {}", room_id, ctx.sender);
+                if !current_room {
+                    ctx.reply(MatrixMessageType::Notice(format!("Left {}", room_id)))
+                        .await;
+                }
+            }
+            Err(e) => {
+                error!("Unable to leave room {} due to error {:?}", room_id, e);
+                ctx.reply_errors(vec![format!("Unable to leave {}", room_id)])
+                    .await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Command for RoomsCommand {
+    fn name(&self) -> &'static str {
+        "rooms"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "List the rooms the bot is in"
+    }
+
+    fn help(&self, prefix: &str, _config: &MatrixListenerConfig) -> String {
+        format!(
+            "Rooms
+
+Lists every room the bot has joined along with its name and number of members. Only available to authorized users.
+
+USAGE:
+\t{}rooms
+",
+            prefix
+        )
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, _args: Vec<String>) {
+        let client = &ctx.listener.matrix_client;
+        let rooms = match client.request(joined_rooms::Request::new()).await {
+            Ok(v) => v.joined_rooms,
+            Err(e) => {
+                error!("Unable to get joined rooms due to error {:?}", e);
+                ctx.reply_errors(vec!["Unable to get joined rooms".to_string()])
+                    .await;
+                return;
+            }
+        };
+        let mut lines = Vec::new();
+        for room_id in rooms {
+            let name = room_name(client, &room_id).await;
+            let members = match client.request(joined_members::Request::new(&room_id)).await {
+                Ok(v) => v.joined.len().to_string(),
+                Err(e) => {
+                    error!(
+                        "Unable to get members of room {} due to error {:?}",
+                        room_id, e
+                    );
+                    "unknown".to_string()
+                }
+            };
+            lines.push(match name {
+                Some(v) => format!("{} ({}) - {} members", v, room_id, members),
+                None => format!("{} - {} members", room_id, members),
+            });
+        }
+        lines.sort_by_key(|l| l.to_lowercase());
+        let message = if lines.is_empty() {
+            "Not in any rooms".to_string()
+        } else {
+            format!("Joined {} rooms:\n{}", lines.len(), lines.join("\n"))
+        };
+        ctx.reply(MatrixMessageType::Notice(message)).await;
+    }
+}
+
+#[async_trait]
+impl Command for SayCommand {
+    fn name(&self) -> &'static str {
+        "say"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "Send a message to a room as the bot"
+    }
+
+    fn usage(&self) -> &'static str {
+        "ROOM TEXT"
+    }
+
+    fn help(&self, prefix: &str, _config: &MatrixListenerConfig) -> String {
+        format!(
+            "Say
+
+Sends text to a room given by id or alias as the bot. Only available to authorized users.
+
+USAGE:
+\t{}say #room:example.com Hello everyone!
+",
+            prefix
+        )
+    }
+
+    fn parse_args(&self, args: &str) -> Result<Vec<String>, String> {
+        let mut parts = args.trim().splitn(2, char::is_whitespace);
+        let room = parts.next().unwrap_or_default();
+        let text = parts.next().unwrap_or_default().trim();
+        if room.is_empty() {
+            Err("No room given".to_string())
+        } else if text.is_empty() {
+            Err("Nothing to say".to_string())
+        } else {
+            Ok(vec![room.to_string(), text.to_string()])
+        }
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        let room_id = match resolve_room(&ctx.listener.matrix_client, &args[0]).await {
+            Ok(v) => v,
+            Err(e) => {
+                ctx.reply_errors(vec![e]).await;
+                return;
+            }
+        };
+        let (resp, recv) = oneshot::channel();
+        let message = MatrixMessage {
+            room_id: room_id.clone(),
+            message: MatrixMessageType::Text(args[1].clone()),
+            resp: Some(resp),
+        };
+        if ctx.send.send(message).await.is_err() {
+            error!("Channel closed. Unable to send message.");
+            return;
+        }
+        let error = match timeout(DELIVERY_TIMEOUT, recv).await {
+            Ok(Ok(Ok(v))) => {
+                debug!("Sent event {} to room {}", v, room_id);
+                if &room_id != ctx.room_id {
+                    ctx.reply(MatrixMessageType::Notice(format!("Sent to {}", room_id)))
+                        .await;
+                }
+                return;
+            }
+            Ok(Ok(Err(MatrixMessageError::Queued))) | Err(_) => {
+                ctx.reply(MatrixMessageType::Notice(format!(
+                    "Not sent to {} yet. It will be sent once the homeserver accepts it",
+                    room_id
+                )))
+                .await;
+                return;
+            }
+            Ok(Ok(Err(MatrixMessageError::Rejected(e)))) => e,
+            Ok(Ok(Err(MatrixMessageError::NotAMessage))) | Ok(Err(_)) => {
+                "Unknown error".to_string()
+            }
+        };
+        error!("Unable to say message in room {}: {}", room_id, error);
+        ctx.reply_errors(vec![format!("Unable to send to {}: {}", room_id, error)])
+            .await;
+    }
+}
+
+/// Parses arguments that are either empty or a single room id or alias
+fn single_room_arg(args: &str) -> Result<Option<Vec<String>>, String> {
+    let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+    match args.len() {
+        0 => Ok(None),
+        1 => Ok(Some(args)),
+        _ => Err("Only a single room can be given".to_string()),
+    }
+}
+
+/// Returns the name of a room if it has one
+async fn room_name(client: &Client, room_id: &RoomId) -> Option<String> {
+    let req = get_state_events_for_key::Request::new(room_id, EventType::RoomName, "");
+    match client.request(req).await {
+        Ok(v) => match serde_json::from_str::<NameEventContent>(v.content.get()) {
+            Ok(v) => v.name().map(String::from),
+            Err(e) => {
+                error!(
+                    "Unable to parse name of room {} due to error {:?}",
+                    room_id, e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            debug!("Room {} has no name: {:?}", room_id, e);
+            None
+        }
+    }
+}
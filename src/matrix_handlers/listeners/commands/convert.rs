@@ -0,0 +1,65 @@
+//! Command that converts units found in its arguments
+
+use super::{Command, CommandContext};
+use crate::config::MatrixListenerConfig;
+use crate::helpers::convert_unit;
+use crate::helpers::MatrixNoticeResponse;
+use crate::matrix_handlers::listeners::unit_conversion_help_message;
+use crate::messages::MatrixMessageType;
+use crate::regex::UNIT_CONVERSION;
+use async_trait::async_trait;
+use tracing::debug;
+
+/// Converts common conversational units Imperial <-> Metric
+pub(super) struct ConvertCommand;
+
+#[async_trait]
+impl Command for ConvertCommand {
+    fn name(&self) -> &'static str {
+        "convert"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Convert common conversational units"
+    }
+
+    fn usage(&self) -> &'static str {
+        "QUANTITY[UNIT]..."
+    }
+
+    fn help(&self, prefix: &str, config: &MatrixListenerConfig) -> String {
+        unit_conversion_help_message(prefix, config)
+    }
+
+    fn parse_args(&self, args: &str) -> Result<Vec<String>, String> {
+        if args.trim().is_empty() {
+            Err("Nothing to convert".to_string())
+        } else {
+            Ok(vec![args.to_lowercase()])
+        }
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        if ctx.text.relates_to.is_some() || ctx.text.formatted.is_some() {
+            debug!("Message is a reply or formatted, skipping conversion");
+            return;
+        }
+        let mut conversions = Vec::new();
+        for arg in &args {
+            for cap in UNIT_CONVERSION.captures_iter(arg) {
+                conversions.push((cap[1].to_string(), cap[2].to_string()));
+            }
+        }
+        let result = match convert_unit(conversions) {
+            Some(v) => v,
+            None => {
+                debug!("No convertable units found. No reply will be constructed.");
+                return;
+            }
+        };
+        let mut response = MatrixNoticeResponse::default();
+        response.set_unit_conversions(result);
+        ctx.reply(MatrixMessageType::Notice(response.to_string()))
+            .await;
+    }
+}
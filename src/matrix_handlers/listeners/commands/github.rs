@@ -0,0 +1,65 @@
+//! Command that makes the next search for GitHub issues and pulls ask GitHub again
+
+use super::{Command, CommandContext, Permission};
+use crate::config::MatrixListenerConfig;
+use crate::matrix_handlers::listeners::github_references;
+use crate::messages::MatrixMessageType;
+use async_trait::async_trait;
+use tracing::info;
+
+/// Forgets cached GitHub search results
+pub(super) struct GithubRefreshCommand;
+
+#[async_trait]
+impl Command for GithubRefreshCommand {
+    fn name(&self) -> &'static str {
+        "github-refresh"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "Forget cached GitHub search results"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[REPO#NUMBER]..."
+    }
+
+    fn help(&self, prefix: &str, config: &MatrixListenerConfig) -> String {
+        format!(
+            "Github-refresh
+
+Forgets the cached results of the given issues and pulls, or of every issue and pull if none are given, so the next time they are mentioned GitHub is asked again. Results are otherwise reused for {} seconds. Only available to authorized users.
+
+USAGE:
+\t{}github-refresh
+\t{}github-refresh repo#123 repo#456
+",
+            config.github_cache_ttl.as_secs(),
+            prefix,
+            prefix
+        )
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        let references = github_references(&args.join(" ").to_lowercase(), ctx.config);
+        if !args.is_empty() && references.is_empty() {
+            ctx.reply_errors(vec![format!(
+                "No issues or pulls in configured repos found in {}",
+                args.join(" ")
+            )])
+            .await;
+            return;
+        }
+        let count = ctx.listener.github_cache.remove(&references);
+        info!("{} forgot {} cached GitHub results", ctx.sender, count);
+        ctx.reply(MatrixMessageType::Notice(format!(
+            "Forgot {} cached GitHub results",
+            count
+        )))
+        .await;
+    }
+}
@@ -0,0 +1,182 @@
+//! Command that shows help generated from the registered commands and commandless actions
+
+use super::{Command, CommandContext};
+use crate::config::MatrixListenerConfig;
+use crate::messages::MatrixMessageType;
+use async_trait::async_trait;
+use tracing::{debug, trace};
+
+/// Shows a list of commands and actions or detailed help for one of them
+pub(super) struct HelpCommand;
+
+#[async_trait]
+impl Command for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show this message or detailed help for a command or action"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[COMMAND|ACTION]"
+    }
+
+    fn help(&self, prefix: &str, _: &MatrixListenerConfig) -> String {
+        format!(
+            "Help
+
+Shows a list of commands and actions, or detailed help for one of them.
+
+USAGE:
+\t{0}help
+\t{0}help [COMMAND|ACTION]",
+            prefix
+        )
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>) {
+        let config = ctx.config;
+        if !config.help_rooms.is_empty() && !config.help_rooms.contains(ctx.room_id) {
+            trace!(
+                "Rooms are limited and room {} is not in the allowed list of help command rooms",
+                ctx.room_id
+            );
+            return;
+        }
+        trace!("Room is allowed, building help message");
+        let registry = &ctx.listener.commands;
+        let prefix = registry.prefix();
+        let message = match args.first() {
+            Some(v) => {
+                let name = v.strip_prefix(prefix).unwrap_or(v.as_str());
+                match registry.find(name) {
+                    Some(command) => Some(command_help_message(command, prefix, config)),
+                    None => action_help_message(ctx, name, prefix),
+                }
+            }
+            None => {
+                trace!("Printing help message for program");
+                Some(generic_help_message(ctx))
+            }
+        };
+        match message {
+            Some(v) => ctx.reply(MatrixMessageType::Notice(v)).await,
+            None => {
+                debug!("Unknown action");
+                let action = args.first().cloned().unwrap_or_default();
+                ctx.reply_errors(vec![format!("Unknown action {}", action)])
+                    .await;
+            }
+        }
+    }
+}
+
+/// Lists all registered commands and commandless actions
+fn generic_help_message(ctx: &CommandContext<'_>) -> String {
+    let registry = &ctx.listener.commands;
+    let prefix = registry.prefix();
+    let mut commands = String::new();
+    for command in registry.commands() {
+        if !command_allowed(command, ctx) {
+            continue;
+        }
+        commands.push_str(&format!(
+            "\t{}{}\t{}\n",
+            prefix,
+            command.name(),
+            command.summary()
+        ));
+    }
+    let mut actions = String::new();
+    for trigger in ctx.listener.triggers.triggers() {
+        actions.push_str(&format!("\t{}\t{}\n", trigger.name(), trigger.summary()));
+    }
+    format!("Matrix Bot v{}
+Repository: {}
+
+This bot has two types of actions it can perform: command and commandless
+Use the {2}help command to learn more about their characteristics
+
+USAGE:
+\t{2}help command|commandless
+\t{2}help [COMMAND|ACTION]
+
+ACTION TYPES:
+\tcommand\t\tCommand actions are a message that starts with {2}
+\tcommandless\tCommandless actions are any message that meets the critera to trigger an action and do not start with an {2}
+
+COMMANDS:
+{3}
+ACTIONS:
+{4}",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_REPOSITORY"),
+        prefix,
+        commands,
+        actions.trim_end()
+    )
+}
+
+/// Returns `true` if the user running help is allowed to run `command`
+fn command_allowed(command: &dyn Command, ctx: &CommandContext<'_>) -> bool {
+    match command.permission() {
+        super::Permission::Anyone => true,
+        super::Permission::Admin => ctx.listener.config.admins.contains(ctx.sender),
+    }
+}
+
+/// Detailed help for a command, including its usage and aliases
+fn command_help_message(
+    command: &dyn Command,
+    prefix: &str,
+    config: &MatrixListenerConfig,
+) -> String {
+    let mut message = command.help(prefix, config);
+    if !command.aliases().is_empty() {
+        let aliases: Vec<String> = command
+            .aliases()
+            .iter()
+            .map(|a| format!("{}{}", prefix, a))
+            .collect();
+        message.push_str(&format!("\n\nALIASES:\n\t{}", aliases.join(" | ")));
+    }
+    message
+}
+
+/// Detailed help for an action type or commandless action
+fn action_help_message(ctx: &CommandContext<'_>, name: &str, prefix: &str) -> Option<String> {
+    match name.to_ascii_lowercase().as_ref() {
+        "command" => Some(action_command_help_message(prefix)),
+        "commandless" => Some(action_commandless_help_message()),
+        _ => ctx
+            .listener
+            .triggers
+            .find(name)
+            .map(|t| t.help(prefix, ctx.config)),
+    }
+}
+
+fn action_command_help_message(prefix: &str) -> String {
+    format!("Command Action
+
+Command actions are defined as message that have no formatting (like no italics, no inline code, not a reply, etc) that start with a {0}. These can only perform one action per message.
+
+EXAMPLES:
+\t{0}help
+\t{0}convert 22mi", prefix)
+}
+
+fn action_commandless_help_message() -> String {
+    "Commandless Action
+
+Commandles actions can happen in any plain text message but certain text formatting will be ignored. Currently ignored formatting is inline code, code blocks, and the text in a reply (but not the reply itself)
+
+The exact rules for triggering a commandless action vary by action (so check action help pages for info on how to trigger them), but their defining features are that they can be in any part of a message and mutiple can be triggered per message.
+
+EXAMPLES:
+\tHey there, i think you want to read docs@troubleshooting
+\tIts not like 32f is that cold. not sure what you are complaining about
+".to_string()
+}
@@ -0,0 +1,243 @@
+//! Commands triggered by messages starting with the configured command prefix
+//!
+//! Every command implements `Command` and is registered in a `CommandRegistry` which is
+//! responsible for finding the command, checking permissions, and parsing arguments before
+//! executing it. Help for commands is generated from the registry.
+
+mod admin;
+mod convert;
+mod github;
+mod help;
+mod reload;
+
+use super::ListenerContext;
+use crate::config::MatrixListenerConfig;
+use crate::helpers::MatrixFormattedNoticeResponse;
+use crate::messages::{MatrixFormattedMessage, MatrixMessage, MatrixMessageType};
+use admin::{JoinCommand, LeaveCommand, RoomsCommand, SayCommand};
+use async_trait::async_trait;
+use convert::ConvertCommand;
+use github::GithubRefreshCommand;
+use help::HelpCommand;
+use reload::ReloadCommand;
+use ruma::{events::room::message::TextMessageEventContent, RoomId, UserId};
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, trace};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Users allowed to run a command
+pub enum Permission {
+    /// Any user in a room with the bot
+    Anyone,
+    /// Only users listed in `authorized_users`
+    Admin,
+}
+
+/// A command that can be run by sending the command prefix followed by its name or an alias
+#[async_trait]
+pub trait Command: Send + Sync {
+    /// Name used to run the command, without the prefix
+    fn name(&self) -> &'static str;
+    /// Other names that can be used to run the command
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Users allowed to run the command
+    fn permission(&self) -> Permission {
+        Permission::Anyone
+    }
+    /// Single line description shown in the list of commands
+    fn summary(&self) -> &'static str;
+    /// Arguments accepted by the command, shown in help and errors
+    fn usage(&self) -> &'static str {
+        ""
+    }
+    /// Detailed help shown by the help command
+    fn help(&self, prefix: &str, config: &MatrixListenerConfig) -> String;
+    /// Parses the text following the command name into arguments.
+    ///
+    /// Splits on whitespace unless overridden. An error is shown to the user along with usage.
+    fn parse_args(&self, args: &str) -> Result<Vec<String>, String> {
+        Ok(args.split_whitespace().map(String::from).collect())
+    }
+    /// Runs the command with the parsed arguments
+    async fn execute(&self, ctx: &mut CommandContext<'_>, args: Vec<String>);
+}
+
+/// Data available to a command while it runs
+pub struct CommandContext<'a> {
+    /// Message that triggered the command
+    pub text: &'a TextMessageEventContent,
+    /// User that sent the message
+    pub sender: &'a UserId,
+    /// Room the message was sent in
+    pub room_id: &'a RoomId,
+    /// Configuration for the room the message was sent in
+    pub config: &'a MatrixListenerConfig,
+    /// Configuration, storage, and clients shared by all handlers
+    pub listener: &'a ListenerContext,
+    /// Channel used to send messages to the responder
+    pub send: &'a mut Sender<MatrixMessage>,
+}
+
+impl CommandContext<'_> {
+    /// Sends a message to the room the command was run in
+    pub async fn reply(&mut self, message: MatrixMessageType) {
+        match self
+            .send
+            .send(MatrixMessage {
+                room_id: self.room_id.clone(),
+                message,
+                resp: None,
+            })
+            .await
+        {
+            Ok(_) => (),
+            Err(_) => error!("Channel closed. Unable to send message."),
+        };
+    }
+
+    /// Sends a list of errors to the room the command was run in
+    pub async fn reply_errors(&mut self, errors: Vec<String>) {
+        let mut response = MatrixFormattedNoticeResponse::default();
+        response.add_errrors(errors);
+        let message = MatrixMessageType::FormattedNotice(MatrixFormattedMessage {
+            plain_text: response.to_string(),
+            formatted_text: response.format_text(),
+        });
+        self.reply(message).await;
+    }
+}
+
+/// Set of commands that can be run and the prefix used to run them
+pub struct CommandRegistry {
+    /// Text a message must start with to be treated as a command
+    prefix: String,
+    /// All registered commands in the order they were registered
+    commands: Vec<Box<dyn Command>>,
+    /// Index into `commands` for every lowercase name and alias
+    names: HashMap<String, usize>,
+}
+
+impl CommandRegistry {
+    /// Creates a registry without any commands
+    pub fn new(prefix: String) -> Self {
+        Self {
+            prefix,
+            commands: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Creates a registry containing every command built into the bot
+    pub fn with_default_commands(prefix: String) -> Self {
+        let mut registry = Self::new(prefix);
+        registry.register(Box::new(HelpCommand));
+        registry.register(Box::new(ConvertCommand));
+        registry.register(Box::new(JoinCommand));
+        registry.register(Box::new(LeaveCommand));
+        registry.register(Box::new(RoomsCommand));
+        registry.register(Box::new(SayCommand));
+        registry.register(Box::new(ReloadCommand));
+        registry.register(Box::new(GithubRefreshCommand));
+        registry
+    }
+
+    /// Adds a command to the registry.
+    ///
+    /// Names and aliases already used by another command are skipped.
+    pub fn register(&mut self, command: Box<dyn Command>) {
+        let index = self.commands.len();
+        for name in std::iter::once(command.name()).chain(command.aliases().iter().copied()) {
+            let name = name.to_lowercase();
+            match self.names.get(&name) {
+                Some(v) => error!(
+                    "Command name {} is already used by command {}. Skipping it",
+                    name,
+                    self.commands[*v].name()
+                ),
+                None => {
+                    self.names.insert(name, index);
+                }
+            }
+        }
+        self.commands.push(command);
+    }
+
+    /// Text a message must start with to be treated as a command
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns `true` if the message should be handled as a command
+    pub fn is_command(&self, body: &str) -> bool {
+        body.starts_with(&self.prefix)
+    }
+
+    /// Finds a command by its name or one of its aliases, ignoring case
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        self.names
+            .get(&name.to_lowercase())
+            .map(|v| self.commands[*v].as_ref())
+    }
+
+    /// Returns every registered command in the order they were registered
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(|c| c.as_ref())
+    }
+
+    /// Finds and runs the command in a message.
+    ///
+    /// Unknown commands are ignored so other bots using the same prefix are not answered.
+    pub async fn dispatch(&self, mut ctx: CommandContext<'_>) {
+        let body = match ctx.text.body.strip_prefix(self.prefix.as_str()) {
+            Some(v) => v,
+            None => return,
+        };
+        let mut parts = body.splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or_default();
+        let args = parts.next().unwrap_or_default();
+        let command = match self.find(name) {
+            Some(v) => v,
+            None => {
+                debug!("Unknown command {}. Doing nothing...", name);
+                return;
+            }
+        };
+        if command.permission() == Permission::Admin
+            && !ctx.listener.config.admins.contains(ctx.sender)
+        {
+            debug!(
+                "User {} is not allowed to run command {}",
+                ctx.sender,
+                command.name()
+            );
+            ctx.reply_errors(vec![format!(
+                "You are not allowed to use {}{}",
+                self.prefix,
+                command.name()
+            )])
+            .await;
+            return;
+        }
+        match command.parse_args(args) {
+            Ok(args) => {
+                trace!("Running command {} with args {:?}", command.name(), args);
+                command.execute(&mut ctx, args).await
+            }
+            Err(e) => {
+                ctx.reply_errors(vec![
+                    e,
+                    format!(
+                        "Usage: {}{} {}",
+                        self.prefix,
+                        command.name(),
+                        command.usage()
+                    ),
+                ])
+                .await
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Command that reloads the config without restarting the bot
+
+use super::{Command, CommandContext, Permission};
+use crate::config::MatrixListenerConfig;
+use crate::messages::MatrixMessageType;
+use async_trait::async_trait;
+use tracing::{error, info};
+
+/// Loads config.toml again and reports what changed
+pub(super) struct ReloadCommand;
+
+#[async_trait]
+impl Command for ReloadCommand {
+    fn name(&self) -> &'static str {
+        "reload"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Admin
+    }
+
+    fn summary(&self) -> &'static str {
+        "Reload the config file"
+    }
+
+    fn help(&self, prefix: &str, _config: &MatrixListenerConfig) -> String {
+        format!(
+            "Reload
+
+Loads config.toml again, resolves its room aliases, and starts using it without restarting the bot. The current config is kept if the new one is invalid. Replies with the repos, links, and groups that were added or removed along with any other settings that changed. Changes to the matrix account, max_concurrent_handlers, and crash_report_room require a restart. Only available to authorized users.
+
+USAGE:
+\t{}reload
+",
+            prefix
+        )
+    }
+
+    async fn execute(&self, ctx: &mut CommandContext<'_>, _args: Vec<String>) {
+        match ctx.listener.reloader.reload().await {
+            Ok(diff) => {
+                info!("Config reloaded by {}", ctx.sender);
+                ctx.reply(MatrixMessageType::Notice(format!(
+                    "Reloaded config\n{}",
+                    diff
+                )))
+                .await;
+            }
+            Err(e) => {
+                error!("Unable to reload config, keeping current config: {}", e);
+                let mut errors =
+                    vec!["Unable to reload config, keeping current config".to_string()];
+                errors.extend(e.problems.iter().map(|p| p.to_string()));
+                ctx.reply_errors(errors).await;
+            }
+        }
+    }
+}
@@ -4,42 +4,118 @@
 //! and searching github
 
 mod commandless_handler;
-mod help_handler;
-mod unit_conversion_handler;
+mod commands;
 
 use self::commandless_handler::commandless_handler;
-use self::help_handler::help_handler;
-use self::unit_conversion_handler::unit_conversion_handler;
-use crate::config::{ListenerStorage, MatrixListenerConfig};
-use crate::messages::{MatrixInviteMessage, MatrixInviteType, MatrixMessage, MatrixMessageType};
-use ruma::{events::room::message::TextMessageEventContent, RoomId, UserId};
+use self::commands::CommandContext;
+use crate::config::{Config, ListenerStorage, MatrixListenerConfig};
+use crate::helpers::MatrixFormattedTextResponse;
+use crate::messages::{
+    MatrixFormattedMessage, MatrixInviteMessage, MatrixInviteType, MatrixMessage, MatrixMessageType,
+};
+use crate::reload::ConfigReloader;
+use ruma::{
+    api::client::r0::membership::{join_room_by_id, leave_room},
+    events::room::message::TextMessageEventContent,
+    RoomId, UserId,
+};
+use ruma_client::Client;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, trace};
+use tracing::{debug, error, info, trace, warn};
 
-/// Dispatches incoming text events to a number of different handlers depending on various conditions
+pub use self::commandless_handler::{
+    github_references, unit_conversion_help_message, GithubCache, TriggerRegistry,
+};
+pub use self::commands::CommandRegistry;
+
+/// Configuration, storage, and clients shared by every handler
+pub struct ListenerContext {
+    /// Configuration data.
+    pub config: Arc<MatrixListenerConfig>,
+    /// Storage data shared with the sync loop.
+    pub storage: Arc<Mutex<ListenerStorage>>,
+    /// Reqwest client used for external API calls.
+    pub api_client: reqwest::Client,
+    /// Matrix client used for requests that are not messages.
+    pub matrix_client: Client,
+    /// Commands that can be run in rooms.
+    pub commands: CommandRegistry,
+    /// Commandless actions that can run in rooms.
+    pub triggers: TriggerRegistry,
+    /// Reloads the config on request.
+    pub reloader: Arc<ConfigReloader>,
+    /// Recent GitHub search results, kept across config reloads.
+    pub github_cache: Arc<GithubCache>,
+}
+
+impl ListenerContext {
+    /// Creates handler data from a config
+    pub fn new(
+        config: &Config,
+        storage: Arc<Mutex<ListenerStorage>>,
+        matrix_client: Client,
+        reloader: Arc<ConfigReloader>,
+    ) -> Self {
+        let config = Arc::new(MatrixListenerConfig::new(
+            config,
+            &reloader.aliases().known(),
+        ));
+        Self {
+            commands: CommandRegistry::with_default_commands(config.command_prefix.clone()),
+            triggers: TriggerRegistry::new(&config),
+            config,
+            storage,
+            api_client: reqwest::Client::new(),
+            matrix_client,
+            reloader,
+            github_cache: Arc::new(GithubCache::default()),
+        }
+    }
+
+    /// Creates handler data from a reloaded config, keeping the same storage and clients
+    pub fn with_config(&self, config: &Config) -> Self {
+        let config = Arc::new(MatrixListenerConfig::new(
+            config,
+            &self.reloader.aliases().known(),
+        ));
+        Self {
+            commands: CommandRegistry::with_default_commands(config.command_prefix.clone()),
+            triggers: TriggerRegistry::new(&config),
+            config,
+            storage: self.storage.clone(),
+            api_client: self.api_client.clone(),
+            matrix_client: self.matrix_client.clone(),
+            reloader: self.reloader.clone(),
+            github_cache: self.github_cache.clone(),
+        }
+    }
+}
+
+/// Dispatches incoming text events to commands or commandless handlers depending on the command prefix
 pub async fn handle_text_event(
     text: &TextMessageEventContent,
     sender: &UserId,
     room_id: &RoomId,
-    storage: &mut ListenerStorage,
-    config: &MatrixListenerConfig,
-    api_client: &reqwest::Client,
-    mut send: &mut Sender<MatrixMessage>,
+    listener: &ListenerContext,
+    send: &mut Sender<MatrixMessage>,
 ) {
-    if !&text.body.starts_with('!') {
+    if !listener.commands.is_command(&text.body) {
         debug!("Entering no command path...");
-        commandless_handler(
-            text, sender, room_id, storage, config, api_client, &mut send,
-        )
-        .await
-    } else if text.body.to_lowercase().starts_with("!convert ") {
-        debug!("Entering unit conversion path...");
-        unit_conversion_handler(text, room_id, &mut send).await
-    } else if text.body.to_lowercase().starts_with("!help") {
-        debug!("Entering help path...");
-        help_handler(text, room_id, config, &mut send).await
+        commandless_handler(text, sender, room_id, listener, send).await
     } else {
-        debug!("Doing nothing...");
+        debug!("Entering command path...");
+        listener
+            .commands
+            .dispatch(CommandContext {
+                text,
+                sender,
+                room_id,
+                config: listener.config.for_room(room_id),
+                listener,
+                send,
+            })
+            .await
     }
 }
 
@@ -60,6 +136,7 @@ pub async fn handle_invite_event(
             .send(MatrixMessage {
                 room_id: room_id.clone(),
                 message: MatrixMessageType::Invite(message),
+                resp: None,
             })
             .await
         {
@@ -75,6 +152,7 @@ pub async fn handle_invite_event(
             .send(MatrixMessage {
                 room_id: room_id.clone(),
                 message: MatrixMessageType::Invite(message),
+                resp: None,
             })
             .await
         {
@@ -83,3 +161,90 @@ pub async fn handle_invite_event(
         };
     }
 }
+
+/// Follows a room that was upgraded to a replacement room.
+///
+/// Joins the replacement room, moves stored data over to it, leaves the old room, and tells the
+/// admins there about any config that still refers to the old room by id.
+pub async fn handle_tombstone_event(
+    old_room: &RoomId,
+    new_room: &RoomId,
+    sender: &UserId,
+    listener: &ListenerContext,
+    send: &mut Sender<MatrixMessage>,
+) {
+    info!(
+        "Room {} was upgraded to {} by {}",
+        old_room, new_room, sender
+    );
+    let req = join_room_by_id::Request::new(new_room);
+    if let Err(e) = listener.matrix_client.request(req).await {
+        error!(
+            "Unable to join room {} replacing {} due to error {:?}",
+            new_room, old_room, e
+        );
+        return;
+    }
+    info!("Joined room {} replacing {}", new_room, old_room);
+    listener
+        .storage
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace_room(old_room, new_room);
+
+    let req = leave_room::Request::new(old_room);
+    let mut notice = match listener.matrix_client.request(req).await {
+        Ok(_) => {
+            info!("Left upgraded room {}", old_room);
+            format!(
+                "Followed the upgrade of {} to this room and left the old room",
+                old_room
+            )
+        }
+        Err(e) => {
+            error!(
+                "Unable to leave upgraded room {} due to error {:?}",
+                old_room, e
+            );
+            format!(
+                "Followed the upgrade of {} to this room. The old room can be left with {}leave {}",
+                old_room, listener.config.command_prefix, old_room
+            )
+        }
+    };
+
+    let references = listener.reloader.current().room_references(old_room);
+    if !references.is_empty() {
+        warn!(
+            "Config still refers to upgraded room {} in {}. Replace it with {}",
+            old_room,
+            references.join(", "),
+            new_room
+        );
+        notice.push_str(&format!(
+            "\nThe config still refers to the old room in:\n{}\nReplace it with {} and reload the config",
+            references.join("\n"),
+            new_room
+        ));
+    }
+    let mut pings = MatrixFormattedTextResponse::default();
+    pings.set_users(listener.config.admins.clone());
+    let messages = vec![
+        MatrixMessageType::Notice(notice),
+        MatrixMessageType::FormattedText(MatrixFormattedMessage {
+            plain_text: pings.to_string(),
+            formatted_text: pings.format_text(),
+        }),
+    ];
+    for message in messages {
+        let message = MatrixMessage {
+            room_id: new_room.clone(),
+            message,
+            resp: None,
+        };
+        if send.send(message).await.is_err() {
+            error!("Channel closed. Unable to send message.");
+            return;
+        }
+    }
+}
@@ -1,84 +1,110 @@
-use crate::config::ResponderStorage;
+use crate::messages::{MatrixFormattedMessage, MatrixMessageType};
 use ruma::events::AnyMessageEventContent;
 use ruma::{
-    api::client::r0::{
-        membership::{join_room_by_id, leave_room},
-        message::send_message_event,
+    api::{
+        client::{
+            error::ErrorKind,
+            r0::{
+                membership::{join_room_by_id, leave_room},
+                message::send_message_event,
+            },
+        },
+        error::{FromHttpResponseError, ServerError},
     },
     events::room::message::{
         MessageEventContent, NoticeMessageEventContent, TextMessageEventContent,
     },
-    RoomId, UserId,
+    EventId, RoomId, UserId,
 };
 use ruma_client::Client;
-use tracing::{debug, error, info};
+use std::time::Duration;
+use tracing::{debug, info};
 
-pub async fn send_notice(
-    client: &Client,
-    room_id: &RoomId,
-    storage: &mut ResponderStorage,
-    message: String,
-) {
-    let content = AnyMessageEventContent::RoomMessage(MessageEventContent::Notice(
-        NoticeMessageEventContent::plain(message),
-    ));
-    let next_txn_id = storage.next_txn_id();
-    let req = send_message_event::Request::new(room_id, &next_txn_id.as_str(), &content);
-    match client.request(req).await {
-        Ok(_) => (),
-        Err(e) => error!("{:?}", e),
-    }
+/// Type used to represent a failed attempt at sending a message
+#[derive(Debug)]
+pub enum SendError {
+    /// Sending may succeed later. Contains the delay requested by the homeserver if there was one.
+    Retry(Option<Duration>),
+    /// Sending will never succeed and the message should be dropped
+    Fatal(String),
 }
-pub async fn send_plain_text(
-    room_id: &RoomId,
-    storage: &mut ResponderStorage,
-    message: String,
+
+/// Sends a message to a room using the supplied transaction id and returns the id of the event.
+///
+/// Sending the same transaction id more than once will only ever result in a single event.
+pub async fn send_message(
     client: &Client,
-) {
-    let content = AnyMessageEventContent::RoomMessage(MessageEventContent::Text(
-        TextMessageEventContent::plain(message),
-    ));
-    let next_txn_id = storage.next_txn_id();
-    let req = send_message_event::Request::new(room_id, &next_txn_id.as_str(), &content);
+    room_id: &RoomId,
+    txn_id: &str,
+    message: &MatrixMessageType,
+) -> Result<EventId, SendError> {
+    let content = match message {
+        MatrixMessageType::Text(m) => notice_or_text(false, m, None),
+        MatrixMessageType::Notice(m) => notice_or_text(true, m, None),
+        MatrixMessageType::FormattedText(MatrixFormattedMessage {
+            plain_text,
+            formatted_text,
+        }) => notice_or_text(false, plain_text, Some(formatted_text)),
+        MatrixMessageType::FormattedNotice(MatrixFormattedMessage {
+            plain_text,
+            formatted_text,
+        }) => notice_or_text(true, plain_text, Some(formatted_text)),
+        MatrixMessageType::Invite(_) => {
+            return Err(SendError::Fatal(
+                "Invites cannot be sent as messages".to_string(),
+            ))
+        }
+    };
+    let req = send_message_event::Request::new(room_id, txn_id, &content);
     match client.request(req).await {
-        Ok(_) => (),
-        Err(e) => error!("Unable to send response due to error {:?}", e),
+        Ok(v) => Ok(v.event_id),
+        Err(e) => Err(classify_error(e)),
     }
 }
 
-pub async fn send_formatted_text(
-    room_id: &RoomId,
-    storage: &mut ResponderStorage,
-    message: String,
-    formatted_message: Option<String>,
-    client: &Client,
-) {
-    let content = AnyMessageEventContent::RoomMessage(MessageEventContent::Text(
-        TextMessageEventContent::html(message, formatted_message.unwrap_or_default()),
-    ));
-    let next_txn_id = storage.next_txn_id();
-    let req = send_message_event::Request::new(room_id, &next_txn_id.as_str(), &content);
-    match client.request(req).await {
-        Ok(_) => (),
-        Err(e) => error!("Unable to send response due to error {:?}", e),
-    }
+/// Builds message content for a notice or text message with optional html formatting
+fn notice_or_text(
+    notice: bool,
+    message: &str,
+    formatted_message: Option<&Option<String>>,
+) -> AnyMessageEventContent {
+    let content = match (notice, formatted_message) {
+        (true, None) => MessageEventContent::Notice(NoticeMessageEventContent::plain(message)),
+        (true, Some(f)) => MessageEventContent::Notice(NoticeMessageEventContent::html(
+            message,
+            f.clone().unwrap_or_default(),
+        )),
+        (false, None) => MessageEventContent::Text(TextMessageEventContent::plain(message)),
+        (false, Some(f)) => MessageEventContent::Text(TextMessageEventContent::html(
+            message,
+            f.clone().unwrap_or_default(),
+        )),
+    };
+    AnyMessageEventContent::RoomMessage(content)
 }
 
-pub async fn send_formatted_notice(
-    room_id: &RoomId,
-    storage: &mut ResponderStorage,
-    message: String,
-    formatted_message: Option<String>,
-    client: &Client,
-) {
-    let content = AnyMessageEventContent::RoomMessage(MessageEventContent::Notice(
-        NoticeMessageEventContent::html(message, formatted_message.unwrap_or_default()),
-    ));
-    let next_txn_id = storage.next_txn_id();
-    let req = send_message_event::Request::new(room_id, &next_txn_id.as_str(), &content);
-    match client.request(req).await {
-        Ok(_) => (),
-        Err(e) => error!("{:?}", e),
+/// Determines if a failed send is worth retrying
+fn classify_error(e: ruma_client::Error<ruma::api::client::Error>) -> SendError {
+    match e {
+        ruma_client::Error::FromHttpResponse(FromHttpResponseError::Http(ServerError::Known(
+            e,
+        ))) => match e.kind {
+            ErrorKind::LimitExceeded { retry_after_ms } => SendError::Retry(retry_after_ms),
+            _ if e.status_code.is_server_error() => SendError::Retry(None),
+            _ => SendError::Fatal(format!("{:?}", e)),
+        },
+        // Usually a reverse proxy reporting that the homeserver is unavailable
+        ruma_client::Error::FromHttpResponse(FromHttpResponseError::Http(
+            ServerError::Unknown(e),
+        )) => {
+            debug!("Unknown error response {:?}", e);
+            SendError::Retry(None)
+        }
+        ruma_client::Error::Response(e) => {
+            debug!("Unable to reach homeserver due to error {:?}", e);
+            SendError::Retry(None)
+        }
+        e => SendError::Fatal(format!("{:?}", e)),
     }
 }
 
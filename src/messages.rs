@@ -1,13 +1,22 @@
-use ruma::{RoomId, UserId};
+use ruma::{EventId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Longest time to wait for the outcome of sending a message before giving up on it.
+///
+/// The message is not cancelled and may still be sent afterwards.
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub struct MatrixMessage {
     pub room_id: RoomId,
     pub message: MatrixMessageType,
-    // pub resp: Responder<MatrixMessageResult>,
+    /// Optional channel that receives the outcome of sending the message
+    pub resp: Option<Responder<MatrixMessageResult>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum MatrixMessageType {
     Invite(MatrixInviteMessage),
     Text(String),
@@ -16,28 +25,46 @@ pub enum MatrixMessageType {
     FormattedNotice(MatrixFormattedMessage),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum MatrixInviteType {
     Accept,
     Reject,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MatrixFormattedMessage {
     pub plain_text: String,
     pub formatted_text: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MatrixInviteMessage {
     pub kind: MatrixInviteType,
     pub sender: UserId,
 }
 
-// #[derive(Debug)]
-// pub enum MatrixMessageResult {
-//     Sent,
-//     FailedToSend,
-// }
+/// Message waiting in the outbox to be acknowledged by the homeserver
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OutboxMessage {
+    /// Transaction id assigned when the message was queued. Reused for every retry.
+    pub txn_id: String,
+    pub room_id: RoomId,
+    pub message: MatrixMessageType,
+}
+
+/// Outcome of sending a message. Contains the event id of the sent message on success.
+pub type MatrixMessageResult = Result<EventId, MatrixMessageError>;
+
+#[derive(Debug)]
+/// Reasons a message was not sent
+pub enum MatrixMessageError {
+    /// The homeserver refused the message and it will not be retried
+    Rejected(String),
+    /// The message could not be sent yet and remains in the outbox. It is retried in the background,
+    /// or on next start if a shutdown was requested
+    Queued,
+    /// Invites do not produce an event and cannot be acknowledged
+    NotAMessage,
+}
 
-// pub type Responder<T> = oneshot::Sender<T>;
+pub type Responder<T> = oneshot::Sender<T>;
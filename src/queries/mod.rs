@@ -4,23 +4,525 @@
 //!
 //! Tests cover all known cases of a query
 //! but will not cover unexpected responses from Reqwest
+//!
+//! Built queries are checked against github_schema.graphql, GitHub's public schema, by
+//! tests/schema.rs. Queries are built from aliases and fragments at runtime, so nothing checks
+//! them at compile time. Whenever an alias or fragment builder changes, update
+//! `queries_match_schema` so it validates every shape of query the builder can produce, and
+//! update tests/schema.rs if the query uses GraphQL it does not check yet.
 
 #[cfg(test)]
 mod tests;
 
-use graphql_client::*;
+use graphql_client::{PathFragment, Response};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Fragment selecting the fields of an issue or pull request used in replies
+const ISSUE_OR_PULL_FIELDS: &str = include_str!("github_issueorpull.graphql");
+/// Fragment selecting the fields of a commit used in replies
+const COMMIT_FIELDS: &str = include_str!("github_commit.graphql");
+/// Fragment selecting the fields of a release used in replies
+const RELEASE_FIELDS: &str = include_str!("github_release.graphql");
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// An issue or pull request to look up by its repo and number
+pub struct IssueOrPullRef {
+    /// Owner of the repo
+    pub owner: String,
+    /// Name of the repo
+    pub name: String,
+    /// Number of the issue or pull request
+    pub number: i64,
+}
 
-/// Type that represents URI results from query
-/// Cannot be `Url` as the returned URI is not a complete URL
-type URI = String;
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// A commit or release to look up by its repo and hash or tag
+pub struct CommitOrReleaseRef {
+    /// Owner of the repo
+    pub owner: String,
+    /// Name of the repo
+    pub name: String,
+    /// Whether to look up a commit or a release
+    pub kind: CommitOrReleaseKind,
+    /// Full or abbreviated hash of the commit, or tag of the release
+    pub id: String,
+}
 
-#[derive(GraphQLQuery)]
-#[graphql(
-    schema_path = "src/queries/github_schema.graphql",
-    query_path = "src/queries/github_issueorpull.graphql",
-    response_derives = "Debug"
-)]
-/// Query struct derived from file github_issueorpull.graphql
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Kinds of things a `CommitOrReleaseRef` can refer to
+pub enum CommitOrReleaseKind {
+    /// A commit given by its hash
+    Commit,
+    /// A release given by its tag
+    Release,
+}
+
+#[derive(Debug)]
+/// Query looking up any number of issues or pulls across repos at once
 ///
-/// Reference that file for further details on structure composition
-pub struct IssueOrPull;
+/// Every repo is an aliased `repository` field containing an aliased `issueOrPullRequest` field
+/// for each number, so the whole lookup costs a single request.
+pub struct IssuesOrPulls {
+    /// Repos to look in along with the numbers to look up in each, in the order first seen
+    repos: Vec<((String, String), Vec<i64>)>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Data returned by an `IssuesOrPulls` query
+pub struct IssuesOrPullsData {
+    /// API budget left after the query
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: Option<RateLimit>,
+    /// Issues or pulls found in each aliased repo. `None` if the repo was not found
+    #[serde(flatten)]
+    repos: HashMap<String, Option<HashMap<String, Option<IssueOrPull>>>>,
+}
+
+#[derive(Debug)]
+/// Query looking up any number of commits or releases across repos at once
+///
+/// Built the same way as `IssuesOrPulls`, with an aliased `object` or `release` field for each
+/// hash or tag. Hashes and tags are passed as variables.
+pub struct CommitsOrReleases {
+    /// Repos to look in along with what to look up in each, in the order first seen
+    repos: Vec<((String, String), Vec<CommitOrReleaseRef>)>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Data returned by a `CommitsOrReleases` query
+pub struct CommitsOrReleasesData {
+    /// API budget left after the query
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: Option<RateLimit>,
+    /// Commits or releases found in each aliased repo. `None` if the repo was not found
+    #[serde(flatten)]
+    repos: HashMap<String, Option<HashMap<String, Option<CommitOrRelease>>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// API budget reported by GitHub
+pub struct RateLimit {
+    /// Points the query cost
+    pub cost: i64,
+    /// Points left until the budget resets
+    pub remaining: i64,
+    /// ISO 8601 time the budget resets
+    pub reset_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "__typename")]
+/// An issue or pull request returned by a query
+pub enum IssueOrPull {
+    /// An issue
+    Issue(Issue),
+    /// A pull request
+    PullRequest(PullRequest),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Fields of an issue selected by github_issueorpull.graphql
+pub struct Issue {
+    /// Number of the issue
+    pub number: i64,
+    /// Title of the issue
+    pub title: String,
+    /// Path of the issue on GitHub. Not a complete URL
+    pub resource_path: String,
+    /// Whether the issue is open or closed
+    pub state: IssueState,
+    /// Author of the issue. `None` if the account has been deleted
+    pub author: Option<Actor>,
+    /// First labels applied to the issue
+    pub labels: Option<LabelConnection>,
+    /// Comments on the issue
+    pub comments: CommentConnection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Fields of a pull request selected by github_issueorpull.graphql
+pub struct PullRequest {
+    /// Number of the pull request
+    pub number: i64,
+    /// Title of the pull request
+    pub title: String,
+    /// Path of the pull request on GitHub. Not a complete URL
+    pub resource_path: String,
+    /// Whether the pull request is open, closed, or merged
+    pub state: PullRequestState,
+    /// Whether the pull request is not ready for review
+    pub is_draft: bool,
+    /// Author of the pull request. `None` if the account has been deleted
+    pub author: Option<Actor>,
+    /// First labels applied to the pull request
+    pub labels: Option<LabelConnection>,
+    /// Comments on the pull request
+    pub comments: CommentConnection,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+/// States of an issue
+pub enum IssueState {
+    /// Open issue
+    Open,
+    /// Closed issue
+    Closed,
+    /// State added to the API after this was written
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+/// States of a pull request
+pub enum PullRequestState {
+    /// Open pull request
+    Open,
+    /// Closed pull request that was not merged
+    Closed,
+    /// Merged pull request
+    Merged,
+    /// State added to the API after this was written
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "__typename")]
+/// A commit or release returned by a query
+pub enum CommitOrRelease {
+    /// A commit
+    Commit(Commit),
+    /// A release
+    Release(Release),
+    /// A tree, blob, or tag found instead of a commit
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Fields of a commit selected by github_commit.graphql
+pub struct Commit {
+    /// Shortest unambiguous hash of the commit
+    pub abbreviated_oid: String,
+    /// First line of the commit message
+    pub message_headline: String,
+    /// Link to the commit
+    pub url: String,
+    /// Git author of the commit
+    pub author: Option<GitActor>,
+    /// Comments on the commit
+    pub comments: CommentConnection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Fields of a release selected by github_release.graphql
+pub struct Release {
+    /// Title of the release. `None` if it has none
+    pub name: Option<String>,
+    /// Tag the release was made from
+    pub tag_name: String,
+    /// Link to the release
+    pub url: String,
+    /// Whether the release is marked as not ready for production
+    pub is_prerelease: bool,
+    /// Account that published the release. `None` if the account has been deleted
+    pub author: Option<Actor>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Author recorded in a commit
+pub struct GitActor {
+    /// Name recorded in the commit
+    pub name: Option<String>,
+    /// GitHub account the author's email belongs to
+    pub user: Option<Actor>,
+}
+
+#[derive(Debug, Deserialize)]
+/// A GitHub user or bot
+pub struct Actor {
+    /// Username of the account
+    pub login: String,
+}
+
+#[derive(Debug, Deserialize)]
+/// Labels applied to an issue or pull request
+pub struct LabelConnection {
+    /// Labels in the page requested
+    pub nodes: Option<Vec<Option<Label>>>,
+}
+
+#[derive(Debug, Deserialize)]
+/// A label applied to an issue or pull request
+pub struct Label {
+    /// Name of the label
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Comments on an issue or pull request
+pub struct CommentConnection {
+    /// Number of comments
+    pub total_count: i64,
+}
+
+impl IssuesOrPulls {
+    /// Creates a query looking up every supplied issue or pull, ignoring duplicates
+    pub fn new(refs: &[IssueOrPullRef]) -> Self {
+        let mut repos: Vec<((String, String), Vec<i64>)> = Vec::new();
+        for r in refs {
+            let repo = (r.owner.clone(), r.name.clone());
+            match repos.iter_mut().find(|(k, _)| k == &repo) {
+                Some((_, numbers)) => {
+                    if !numbers.contains(&r.number) {
+                        numbers.push(r.number)
+                    }
+                }
+                None => repos.push((repo, vec![r.number])),
+            }
+        }
+        Self { repos }
+    }
+
+    /// Returns the query document. Repo owners and names are passed as variables.
+    pub fn document(&self) -> String {
+        let mut variables = Vec::new();
+        let mut fields = String::new();
+        for (i, (_, numbers)) in self.repos.iter().enumerate() {
+            variables.push(format!("$owner{}: String!, $name{}: String!", i, i));
+            fields.push_str(&format!(
+                "  {}: repository(owner: $owner{}, name: $name{}) {{\n",
+                repo_alias(i),
+                i,
+                i
+            ));
+            for (j, number) in numbers.iter().enumerate() {
+                fields.push_str(&format!(
+                    "    {}: issueOrPullRequest(number: {}) {{\n      ...IssueOrPullFields\n    }}\n",
+                    issue_alias(j),
+                    number
+                ));
+            }
+            fields.push_str("  }\n");
+        }
+        format!(
+            "query IssuesOrPulls({}) {{\n  rateLimit {{\n    cost\n    remaining\n    resetAt\n  }}\n{}}}\n\n{}",
+            variables.join(", "),
+            fields,
+            ISSUE_OR_PULL_FIELDS
+        )
+    }
+
+    /// Returns the body to post to the GraphQL endpoint
+    pub fn build_query(&self) -> Value {
+        let mut variables = Map::new();
+        for (i, ((owner, name), _)) in self.repos.iter().enumerate() {
+            variables.insert(format!("owner{}", i), Value::String(owner.clone()));
+            variables.insert(format!("name{}", i), Value::String(name.clone()));
+        }
+        json!({
+            "query": self.document(),
+            "variables": variables,
+            "operationName": "IssuesOrPulls",
+        })
+    }
+
+    /// Matches the response of the query back to the issue or pull each reference is for.
+    ///
+    /// Every looked up issue or pull is returned once, in the order first given, along with the
+    /// error GitHub returned for it if it was not found.
+    pub fn results(
+        &self,
+        response: Response<IssuesOrPullsData>,
+    ) -> Vec<(IssueOrPullRef, Result<IssueOrPull, String>)> {
+        let errors = error_messages(response.errors);
+        let mut repos = response.data.map(|d| d.repos).unwrap_or_default();
+        let mut results = Vec::new();
+        for (i, ((owner, name), numbers)) in self.repos.iter().enumerate() {
+            let repo_alias = repo_alias(i);
+            let mut found = repos.remove(&repo_alias).flatten();
+            for (j, number) in numbers.iter().enumerate() {
+                let issue_alias = issue_alias(j);
+                let result = match found
+                    .as_mut()
+                    .and_then(|f| f.remove(&issue_alias))
+                    .flatten()
+                {
+                    Some(v) => Ok(v),
+                    None => Err(errors
+                        .get(&format!("{}.{}", repo_alias, issue_alias))
+                        .or_else(|| errors.get(&repo_alias))
+                        .cloned()
+                        .unwrap_or_else(|| "Not found".to_string())),
+                };
+                let reference = IssueOrPullRef {
+                    owner: owner.clone(),
+                    name: name.clone(),
+                    number: *number,
+                };
+                results.push((reference, result));
+            }
+        }
+        results
+    }
+}
+
+impl CommitsOrReleases {
+    /// Creates a query looking up every supplied commit or release, ignoring duplicates
+    pub fn new(refs: &[CommitOrReleaseRef]) -> Self {
+        let mut repos: Vec<((String, String), Vec<CommitOrReleaseRef>)> = Vec::new();
+        for r in refs {
+            let repo = (r.owner.clone(), r.name.clone());
+            match repos.iter_mut().find(|(k, _)| k == &repo) {
+                Some((_, items)) => {
+                    if !items.contains(r) {
+                        items.push(r.clone())
+                    }
+                }
+                None => repos.push((repo, vec![r.clone()])),
+            }
+        }
+        Self { repos }
+    }
+
+    /// Returns the query document. Repo owners and names, hashes, and tags are passed as variables.
+    ///
+    /// Only the fragments that are used are included as GitHub rejects documents with unused ones.
+    pub fn document(&self) -> String {
+        let mut variables = Vec::new();
+        let mut fields = String::new();
+        let mut fragments = Vec::new();
+        for (i, (_, items)) in self.repos.iter().enumerate() {
+            variables.push(format!("$owner{}: String!, $name{}: String!", i, i));
+            fields.push_str(&format!(
+                "  {}: repository(owner: $owner{}, name: $name{}) {{\n",
+                repo_alias(i),
+                i,
+                i
+            ));
+            for (j, item) in items.iter().enumerate() {
+                variables.push(format!("${}: String!", id_variable(i, j)));
+                let (field, fragment, fragment_fields) = match item.kind {
+                    CommitOrReleaseKind::Commit => {
+                        ("object(expression", "CommitFields", COMMIT_FIELDS)
+                    }
+                    CommitOrReleaseKind::Release => {
+                        ("release(tagName", "ReleaseFields", RELEASE_FIELDS)
+                    }
+                };
+                if !fragments.contains(&fragment_fields) {
+                    fragments.push(fragment_fields);
+                }
+                fields.push_str(&format!(
+                    "    {}: {}: ${}) {{\n      ...{}\n    }}\n",
+                    item_alias(j),
+                    field,
+                    id_variable(i, j),
+                    fragment
+                ));
+            }
+            fields.push_str("  }\n");
+        }
+        format!(
+            "query CommitsOrReleases({}) {{\n  rateLimit {{\n    cost\n    remaining\n    resetAt\n  }}\n{}}}\n\n{}",
+            variables.join(", "),
+            fields,
+            fragments.join("\n")
+        )
+    }
+
+    /// Returns the body to post to the GraphQL endpoint
+    pub fn build_query(&self) -> Value {
+        let mut variables = Map::new();
+        for (i, ((owner, name), items)) in self.repos.iter().enumerate() {
+            variables.insert(format!("owner{}", i), Value::String(owner.clone()));
+            variables.insert(format!("name{}", i), Value::String(name.clone()));
+            for (j, item) in items.iter().enumerate() {
+                variables.insert(id_variable(i, j), Value::String(item.id.clone()));
+            }
+        }
+        json!({
+            "query": self.document(),
+            "variables": variables,
+            "operationName": "CommitsOrReleases",
+        })
+    }
+
+    /// Matches the response of the query back to the commit or release each reference is for.
+    ///
+    /// Every looked up commit or release is returned once, in the order first given, along with
+    /// the error GitHub returned for it if it was not found.
+    pub fn results(
+        &self,
+        response: Response<CommitsOrReleasesData>,
+    ) -> Vec<(CommitOrReleaseRef, Result<CommitOrRelease, String>)> {
+        let errors = error_messages(response.errors);
+        let mut repos = response.data.map(|d| d.repos).unwrap_or_default();
+        let mut results = Vec::new();
+        for (i, (_, items)) in self.repos.iter().enumerate() {
+            let repo_alias = repo_alias(i);
+            let mut found = repos.remove(&repo_alias).flatten();
+            for (j, item) in items.iter().enumerate() {
+                let item_alias = item_alias(j);
+                let result = match found.as_mut().and_then(|f| f.remove(&item_alias)).flatten() {
+                    Some(v) => Ok(v),
+                    None => Err(errors
+                        .get(&format!("{}.{}", repo_alias, item_alias))
+                        .or_else(|| errors.get(&repo_alias))
+                        .cloned()
+                        .unwrap_or_else(|| "Not found".to_string())),
+                };
+                results.push((item.clone(), result));
+            }
+        }
+        results
+    }
+}
+
+/// Returns the message of every error GitHub returned, keyed by the aliases in its path joined by `.`
+fn error_messages(errors: Option<Vec<graphql_client::Error>>) -> HashMap<String, String> {
+    let mut messages = HashMap::new();
+    for error in errors.into_iter().flatten() {
+        let path: Vec<&str> = error
+            .path
+            .iter()
+            .flatten()
+            .filter_map(|p| match p {
+                PathFragment::Key(k) => Some(k.as_str()),
+                PathFragment::Index(_) => None,
+            })
+            .collect();
+        messages.insert(path.join("."), error.message.clone());
+    }
+    messages
+}
+
+/// Returns the alias of the `repository` field for the repo at `index`
+fn repo_alias(index: usize) -> String {
+    format!("repo{}", index)
+}
+
+/// Returns the alias of the `issueOrPullRequest` field for the number at `index` in its repo
+fn issue_alias(index: usize) -> String {
+    format!("issue{}", index)
+}
+
+/// Returns the alias of the `object` or `release` field for the hash or tag at `index` in its repo
+fn item_alias(index: usize) -> String {
+    format!("item{}", index)
+}
+
+/// Returns the variable holding the hash or tag at `index` in the repo at `repo_index`
+fn id_variable(repo_index: usize, index: usize) -> String {
+    format!("id{}_{}", repo_index, index)
+}
@@ -1,20 +1,31 @@
 mod common;
+mod schema;
 
-use super::issue_or_pull::IssueOrPullRepositoryIssueOrPullRequest::{Issue, PullRequest};
 use super::*;
 use common::load_access_token;
 use reqwest::header::{self, HeaderValue};
+use serde::de::DeserializeOwned;
 
-#[tokio::test]
-async fn issue() {
-    let access_token = load_access_token();
-    let client = reqwest::Client::new();
-    let query = IssueOrPull::build_query(issue_or_pull::Variables {
-        name: "jellyfin".to_string(),
+fn reference(name: &str, number: i64) -> IssueOrPullRef {
+    IssueOrPullRef {
         owner: "jellyfin".to_string(),
-        number: 1234,
-    });
+        name: name.to_string(),
+        number,
+    }
+}
+
+fn commit_or_release(kind: CommitOrReleaseKind, name: &str, id: &str) -> CommitOrReleaseRef {
+    CommitOrReleaseRef {
+        owner: "jellyfin".to_string(),
+        name: name.to_string(),
+        kind,
+        id: id.to_string(),
+    }
+}
 
+async fn run_query<T: DeserializeOwned>(body: &Value) -> Response<T> {
+    let access_token = load_access_token();
+    let client = reqwest::Client::new();
     let response = client
         .post("https://api.github.com/graphql")
         .bearer_auth(access_token)
@@ -22,93 +33,300 @@ async fn issue() {
             header::USER_AGENT,
             HeaderValue::from_static("jellyfin-matrix-bot/tester"),
         )
-        .json(&query)
+        .json(body)
         .send()
         .await
         .unwrap();
-    let response_body: Response<issue_or_pull::ResponseData> = response.json().await.unwrap();
-    let response_data = response_body
-        .data
-        .expect("missing response data")
-        .repository
-        .expect("missing repository")
-        .issue_or_pull_request
-        .expect("missing issue or pull request");
-
-    match response_data {
-        Issue(v) => assert_eq!("/jellyfin/jellyfin/issues/1234", v.resource_path),
+    response.json().await.unwrap()
+}
+
+#[test]
+fn duplicates_are_queried_once() {
+    let query = IssuesOrPulls::new(&[
+        reference("jellyfin", 1),
+        reference("jellyfin-web", 3),
+        reference("jellyfin", 2),
+        reference("jellyfin", 1),
+    ]);
+    let document = query.document();
+    assert_eq!(document.matches("repository(").count(), 2);
+    assert_eq!(document.matches("issueOrPullRequest(").count(), 3);
+    assert!(document.contains("fragment IssueOrPullFields on IssueOrPullRequest"));
+    let body = query.build_query();
+    assert_eq!(body["variables"]["name1"], "jellyfin-web");
+}
+
+#[test]
+fn issues_or_pulls_query_text() {
+    let query = IssuesOrPulls::new(&[
+        reference("jellyfin", 1),
+        reference("jellyfin-web", 2),
+        reference("jellyfin", 3),
+    ]);
+    assert_eq!(
+        query.document(),
+        format!(
+            "query IssuesOrPulls($owner0: String!, $name0: String!, $owner1: String!, $name1: String!) {{
+  rateLimit {{
+    cost
+    remaining
+    resetAt
+  }}
+  repo0: repository(owner: $owner0, name: $name0) {{
+    issue0: issueOrPullRequest(number: 1) {{
+      ...IssueOrPullFields
+    }}
+    issue1: issueOrPullRequest(number: 3) {{
+      ...IssueOrPullFields
+    }}
+  }}
+  repo1: repository(owner: $owner1, name: $name1) {{
+    issue0: issueOrPullRequest(number: 2) {{
+      ...IssueOrPullFields
+    }}
+  }}
+}}
+
+{}",
+            ISSUE_OR_PULL_FIELDS
+        )
+    );
+    assert_eq!(
+        query.build_query()["variables"],
+        json!({
+            "owner0": "jellyfin",
+            "name0": "jellyfin",
+            "owner1": "jellyfin",
+            "name1": "jellyfin-web",
+        })
+    );
+}
+
+#[test]
+fn commits_or_releases_query_text() {
+    let query = CommitsOrReleases::new(&[
+        commit_or_release(CommitOrReleaseKind::Release, "jellyfin", "v10.6.0"),
+        commit_or_release(CommitOrReleaseKind::Commit, "jellyfin-web", "abc1234"),
+        commit_or_release(CommitOrReleaseKind::Commit, "jellyfin", "def5678"),
+    ]);
+    assert_eq!(
+        query.document(),
+        format!(
+            "query CommitsOrReleases($owner0: String!, $name0: String!, $id0_0: String!, $id0_1: String!, $owner1: String!, $name1: String!, $id1_0: String!) {{
+  rateLimit {{
+    cost
+    remaining
+    resetAt
+  }}
+  repo0: repository(owner: $owner0, name: $name0) {{
+    item0: release(tagName: $id0_0) {{
+      ...ReleaseFields
+    }}
+    item1: object(expression: $id0_1) {{
+      ...CommitFields
+    }}
+  }}
+  repo1: repository(owner: $owner1, name: $name1) {{
+    item0: object(expression: $id1_0) {{
+      ...CommitFields
+    }}
+  }}
+}}
+
+{}
+{}",
+            RELEASE_FIELDS, COMMIT_FIELDS
+        )
+    );
+    assert_eq!(
+        query.build_query()["variables"],
+        json!({
+            "owner0": "jellyfin",
+            "name0": "jellyfin",
+            "id0_0": "v10.6.0",
+            "id0_1": "def5678",
+            "owner1": "jellyfin",
+            "name1": "jellyfin-web",
+            "id1_0": "abc1234",
+        })
+    );
+}
+
+#[test]
+fn queries_match_schema() {
+    schema::validate(
+        &IssuesOrPulls::new(&[
+            reference("jellyfin", 1),
+            reference("jellyfin-web", 2),
+            reference("jellyfin", 3),
+        ])
+        .document(),
+    );
+    schema::validate(
+        &CommitsOrReleases::new(&[
+            commit_or_release(CommitOrReleaseKind::Release, "jellyfin", "v10.6.0"),
+            commit_or_release(CommitOrReleaseKind::Commit, "jellyfin-web", "abc1234"),
+        ])
+        .document(),
+    );
+    schema::validate(
+        &CommitsOrReleases::new(&[commit_or_release(
+            CommitOrReleaseKind::Commit,
+            "jellyfin",
+            "abc1234",
+        )])
+        .document(),
+    );
+}
+
+#[test]
+fn results_match_references() {
+    let query = IssuesOrPulls::new(&[
+        reference("jellyfin", 1),
+        reference("jellyfin", 2),
+        reference("missing", 3),
+    ]);
+    let response: Response<IssuesOrPullsData> = serde_json::from_str(
+        r#"{
+            "data": {
+                "rateLimit": { "cost": 1, "remaining": 4990, "resetAt": "2020-11-02T18:00:00Z" },
+                "repo0": {
+                    "issue0": {
+                        "__typename": "PullRequest",
+                        "number": 1,
+                        "title": "Merged",
+                        "resourcePath": "/jellyfin/jellyfin/pull/1",
+                        "state": "MERGED",
+                        "isDraft": false,
+                        "author": null,
+                        "labels": { "nodes": [{ "name": "bug" }] },
+                        "comments": { "totalCount": 2 }
+                    },
+                    "issue1": null
+                },
+                "repo1": null
+            },
+            "errors": [
+                { "path": ["repo0", "issue1"], "message": "No issue 2" },
+                { "path": ["repo1"], "message": "No repo" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let results = query.results(response);
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].0, reference("jellyfin", 1));
+    match &results[0].1 {
+        Ok(IssueOrPull::PullRequest(v)) => assert_eq!(PullRequestState::Merged, v.state),
+        _ => panic!("Did not get a pull back like expected"),
+    }
+    assert_eq!(results[1].1.as_ref().unwrap_err(), "No issue 2");
+    assert_eq!(results[2].1.as_ref().unwrap_err(), "No repo");
+}
+
+#[tokio::test]
+async fn issue() {
+    let query = IssuesOrPulls::new(&[reference("jellyfin", 1234)]);
+    let mut results = query.results(run_query(&query.build_query()).await);
+
+    match results.remove(0).1 {
+        Ok(IssueOrPull::Issue(v)) => assert_eq!("/jellyfin/jellyfin/issues/1234", v.resource_path),
         _ => panic!("Did not get an issue back like expected"),
     }
 }
 
 #[tokio::test]
 async fn pull() {
-    let access_token = load_access_token();
-    let client = reqwest::Client::new();
-    let query = IssueOrPull::build_query(issue_or_pull::Variables {
-        name: "jellyfin".to_string(),
-        owner: "jellyfin".to_string(),
-        number: 123,
-    });
+    let query = IssuesOrPulls::new(&[reference("jellyfin", 123)]);
+    let mut results = query.results(run_query(&query.build_query()).await);
 
-    let response = client
-        .post("https://api.github.com/graphql")
-        .bearer_auth(access_token)
-        .header(
-            header::USER_AGENT,
-            HeaderValue::from_static("jellyfin-matrix-bot/tester"),
-        )
-        .json(&query)
-        .send()
-        .await
-        .unwrap();
-    let response_body: Response<issue_or_pull::ResponseData> = response.json().await.unwrap();
-    let response_data = response_body
-        .data
-        .expect("missing response data")
-        .repository
-        .expect("missing repository")
-        .issue_or_pull_request
-        .expect("missing issue or pull request");
-
-    match response_data {
-        PullRequest(v) => assert_eq!("/jellyfin/jellyfin/pull/123", v.resource_path),
+    match results.remove(0).1 {
+        Ok(IssueOrPull::PullRequest(v)) => {
+            assert_eq!("/jellyfin/jellyfin/pull/123", v.resource_path)
+        }
         _ => panic!("Did not get a pull back like expected"),
     }
 }
 
 #[tokio::test]
 async fn not_found() {
-    let access_token = load_access_token();
-    let client = reqwest::Client::new();
-    let query = IssueOrPull::build_query(issue_or_pull::Variables {
-        name: "jellyfin".to_string(),
-        owner: "jellyfin".to_string(),
-        number: 123456,
-    });
+    let query = IssuesOrPulls::new(&[reference("jellyfin", 123456), reference("jellyfin", 123)]);
+    let results = query.results(run_query(&query.build_query()).await);
 
-    let response = client
-        .post("https://api.github.com/graphql")
-        .bearer_auth(access_token)
-        .header(
-            header::USER_AGENT,
-            HeaderValue::from_static("jellyfin-matrix-bot/tester"),
-        )
-        .json(&query)
-        .send()
-        .await
-        .unwrap();
-    let response_body: Response<issue_or_pull::ResponseData> = response.json().await.unwrap();
-    let response_data = response_body.errors.expect("no errors found");
-
-    if response_data.len() != 1 {
-        panic!("Expected 1 error and got {}", response_data.len())
-    } else {
-        for error in response_data {
-            assert_eq!(
-                "Could not resolve to an issue or pull request with the number of 123456.",
-                error.message
-            )
-        }
+    assert_eq!(
+        Err("Could not resolve to an issue or pull request with the number of 123456.".to_string()),
+        results[0].1.as_ref().map(|_| ()).map_err(|e| e.clone())
+    );
+    assert!(results[1].1.is_ok());
+}
+
+#[test]
+fn commits_and_releases_match_references() {
+    let query = CommitsOrReleases::new(&[
+        commit_or_release(CommitOrReleaseKind::Commit, "jellyfin", "abc1234"),
+        commit_or_release(CommitOrReleaseKind::Release, "jellyfin", "v10.6.0"),
+        commit_or_release(CommitOrReleaseKind::Commit, "jellyfin", "abc1234"),
+    ]);
+    let document = query.document();
+    assert!(document.contains("item0: object(expression: $id0_0)"));
+    assert!(document.contains("item1: release(tagName: $id0_1)"));
+    assert!(document.contains("fragment CommitFields on GitObject"));
+    assert!(document.contains("fragment ReleaseFields on Release"));
+    assert_eq!(query.build_query()["variables"]["id0_1"], "v10.6.0");
+    let response: Response<CommitsOrReleasesData> = serde_json::from_str(
+        r#"{
+            "data": {
+                "rateLimit": { "cost": 1, "remaining": 4990, "resetAt": "2020-11-02T18:00:00Z" },
+                "repo0": {
+                    "item0": {
+                        "__typename": "Commit",
+                        "abbreviatedOid": "abc1234",
+                        "messageHeadline": "Fix playback",
+                        "url": "https://github.com/jellyfin/jellyfin/commit/abc1234",
+                        "author": { "name": "Someone", "user": null },
+                        "comments": { "totalCount": 0 }
+                    },
+                    "item1": null
+                }
+            },
+            "errors": [
+                { "path": ["repo0", "item1"], "message": "No release" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let results = query.results(response);
+    assert_eq!(results.len(), 2);
+    match &results[0].1 {
+        Ok(CommitOrRelease::Commit(v)) => assert_eq!("Fix playback", v.message_headline),
+        _ => panic!("Did not get a commit back like expected"),
+    }
+    assert_eq!(results[1].1.as_ref().unwrap_err(), "No release");
+}
+
+#[test]
+fn unused_fragments_are_left_out() {
+    let query = CommitsOrReleases::new(&[commit_or_release(
+        CommitOrReleaseKind::Commit,
+        "jellyfin",
+        "abc1234",
+    )]);
+    let document = query.document();
+    assert!(document.contains("fragment CommitFields on GitObject"));
+    assert!(!document.contains("ReleaseFields"));
+}
+
+#[tokio::test]
+async fn release() {
+    let query = CommitsOrReleases::new(&[commit_or_release(
+        CommitOrReleaseKind::Release,
+        "jellyfin",
+        "v10.6.0",
+    )]);
+    let mut results = query.results(run_query(&query.build_query()).await);
+
+    match results.remove(0).1 {
+        Ok(CommitOrRelease::Release(v)) => assert_eq!("v10.6.0", v.tag_name),
+        _ => panic!("Did not get a release back like expected"),
     }
 }
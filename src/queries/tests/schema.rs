@@ -0,0 +1,198 @@
+use graphql_parser::query::{
+    self, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition,
+};
+use graphql_parser::schema::{self, TypeDefinition};
+use std::collections::{HashMap, HashSet};
+
+const SCHEMA: &str = include_str!("../github_schema.graphql");
+
+/// Types from the schema needed to check a query
+struct Schema {
+    /// Fields of every object and interface
+    fields: HashMap<String, Vec<schema::Field>>,
+    /// Interfaces implemented by every object
+    interfaces: HashMap<String, Vec<String>>,
+    /// Members of every union
+    unions: HashMap<String, Vec<String>>,
+}
+
+impl Schema {
+    fn load() -> Self {
+        let document = graphql_parser::parse_schema(SCHEMA).unwrap();
+        let mut schema = Self {
+            fields: HashMap::new(),
+            interfaces: HashMap::new(),
+            unions: HashMap::new(),
+        };
+        for definition in document.definitions {
+            match definition {
+                schema::Definition::TypeDefinition(TypeDefinition::Object(v)) => {
+                    schema
+                        .interfaces
+                        .insert(v.name.clone(), v.implements_interfaces);
+                    schema.fields.insert(v.name, v.fields);
+                }
+                schema::Definition::TypeDefinition(TypeDefinition::Interface(v)) => {
+                    schema.fields.insert(v.name, v.fields);
+                }
+                schema::Definition::TypeDefinition(TypeDefinition::Union(v)) => {
+                    schema.unions.insert(v.name, v.types);
+                }
+                _ => (),
+            }
+        }
+        schema
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.fields.contains_key(name) || self.unions.contains_key(name)
+    }
+
+    /// Returns `true` if a value of type `parent` can be of type `condition`
+    fn overlaps(&self, parent: &str, condition: &str) -> bool {
+        let implements = |object: &str, interface: &str| {
+            self.interfaces
+                .get(object)
+                .into_iter()
+                .flatten()
+                .any(|i| i == interface)
+        };
+        let member = |union: &str, object: &str| {
+            self.unions
+                .get(union)
+                .into_iter()
+                .flatten()
+                .any(|m| m == object)
+        };
+        parent == condition
+            || implements(parent, condition)
+            || implements(condition, parent)
+            || member(parent, condition)
+            || member(condition, parent)
+    }
+}
+
+/// Walks a query, recording what it uses
+struct Checker<'a> {
+    schema: &'a Schema,
+    fragments: HashMap<String, &'a FragmentDefinition>,
+    used_fragments: HashSet<String>,
+    used_variables: HashSet<String>,
+}
+
+impl<'a> Checker<'a> {
+    fn check(&mut self, selection_set: &'a SelectionSet, parent: &str) {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    if field.name == "__typename" {
+                        continue;
+                    }
+                    let definition = self
+                        .schema
+                        .fields
+                        .get(parent)
+                        .and_then(|f| f.iter().find(|f| f.name == field.name))
+                        .unwrap_or_else(|| panic!("{} has no field {}", parent, field.name));
+                    for (name, value) in &field.arguments {
+                        assert!(
+                            definition.arguments.iter().any(|a| &a.name == name),
+                            "{}.{} has no argument {}",
+                            parent,
+                            field.name,
+                            name
+                        );
+                        if let query::Value::Variable(v) = value {
+                            self.used_variables.insert(v.clone());
+                        }
+                    }
+                    let field_type = named_type(&definition.field_type);
+                    assert_eq!(
+                        self.schema.exists(field_type),
+                        !field.selection_set.items.is_empty(),
+                        "{}.{} must select fields if and only if it is an object",
+                        parent,
+                        field.name
+                    );
+                    self.check(&field.selection_set, field_type);
+                }
+                Selection::FragmentSpread(spread) => {
+                    let fragment = *self
+                        .fragments
+                        .get(&spread.fragment_name)
+                        .unwrap_or_else(|| panic!("No fragment {}", spread.fragment_name));
+                    let TypeCondition::On(condition) = &fragment.type_condition;
+                    assert!(
+                        self.schema.overlaps(parent, condition),
+                        "{} can't be spread on {}",
+                        fragment.name,
+                        parent
+                    );
+                    if self.used_fragments.insert(fragment.name.clone()) {
+                        self.check(&fragment.selection_set, condition);
+                    }
+                }
+                Selection::InlineFragment(inline) => {
+                    let condition = match &inline.type_condition {
+                        Some(TypeCondition::On(v)) => v.as_str(),
+                        None => parent,
+                    };
+                    assert!(self.schema.exists(condition), "No type {}", condition);
+                    assert!(
+                        self.schema.overlaps(parent, condition),
+                        "{} can't be used in {}",
+                        condition,
+                        parent
+                    );
+                    self.check(&inline.selection_set, condition);
+                }
+            }
+        }
+    }
+}
+
+fn named_type(field_type: &query::Type) -> &str {
+    match field_type {
+        query::Type::NamedType(v) => v,
+        query::Type::ListType(v) | query::Type::NonNullType(v) => named_type(v),
+    }
+}
+
+/// Panics if the query does not match the schema the way GitHub requires.
+///
+/// Checks fields, arguments, and fragment types, and that every variable and fragment is used.
+pub(super) fn validate(document: &str) {
+    let schema = Schema::load();
+    let document = graphql_parser::parse_query(document).unwrap();
+    let mut operations = Vec::new();
+    let mut fragments = HashMap::new();
+    for definition in &document.definitions {
+        match definition {
+            Definition::Operation(OperationDefinition::Query(v)) => operations.push(v),
+            Definition::Fragment(v) => {
+                let TypeCondition::On(condition) = &v.type_condition;
+                assert!(schema.exists(condition), "No type {}", condition);
+                fragments.insert(v.name.clone(), v);
+            }
+            Definition::Operation(v) => panic!("Unexpected operation {:?}", v),
+        }
+    }
+    assert_eq!(operations.len(), 1, "Document must have a single query");
+    let mut checker = Checker {
+        schema: &schema,
+        fragments,
+        used_fragments: HashSet::new(),
+        used_variables: HashSet::new(),
+    };
+    checker.check(&operations[0].selection_set, "Query");
+
+    let defined: HashSet<String> = operations[0]
+        .variable_definitions
+        .iter()
+        .map(|v| v.name.clone())
+        .collect();
+    assert_eq!(defined, checker.used_variables);
+    let defined: HashSet<String> = checker.fragments.keys().cloned().collect();
+    assert_eq!(defined, checker.used_fragments);
+}
@@ -28,6 +28,23 @@ lazy_static! {
         ([[:digit:]]+)                  # The number to search issues and pulls for (captured)
     ").unwrap();
 }
+lazy_static! {
+    pub static ref GITHUB_URL: Regex = Regex::new(
+        r#"(?xi)
+        https?://(?:www\.)?github\.com/
+        ([a-z0-9-]+)                    # The owner of the repo (captured)
+        /
+        ([a-z0-9._-]+)                  # The name of the repo (captured)
+        /
+        (?:
+            (?:issues|pull)/([0-9]+)    # The number of an issue or pull (captured)
+            | commit/([0-9a-f]{7,40})   # The hash of a commit (captured)
+            | releases/tag/([^\s?\#"<>]+) # The tag of a release (captured)
+        )
+    "#
+    )
+    .unwrap();
+}
 lazy_static! {
     pub static ref LINK_URL: Regex = Regex::new(
         r"(?x)
@@ -0,0 +1,55 @@
+mod no_capture {
+    use crate::regex::*;
+
+    #[test]
+    fn issue() {
+        assert!(GITHUB_URL.is_match("see https://github.com/jellyfin/jellyfin/issues/1234"))
+    }
+    #[test]
+    fn pull_files() {
+        assert!(GITHUB_URL.is_match("https://github.com/jellyfin/jellyfin-web/pull/42/files"))
+    }
+    #[test]
+    fn repo_only() {
+        assert!(!GITHUB_URL.is_match("https://github.com/jellyfin/jellyfin"))
+    }
+    #[test]
+    fn short_commit() {
+        assert!(!GITHUB_URL.is_match("https://github.com/jellyfin/jellyfin/commit/abc12"))
+    }
+    #[test]
+    fn other_site() {
+        assert!(!GITHUB_URL.is_match("https://gitlab.com/jellyfin/jellyfin/issues/1234"))
+    }
+}
+
+mod capture {
+    use crate::regex::*;
+
+    #[test]
+    fn issue() {
+        let cap = GITHUB_URL
+            .captures("https://www.github.com/jellyfin/jellyfin/issues/1234#issuecomment-1")
+            .unwrap();
+        assert_eq!(&cap[1], "jellyfin");
+        assert_eq!(&cap[2], "jellyfin");
+        assert_eq!(&cap[3], "1234");
+        assert!(cap.get(4).is_none());
+        assert!(cap.get(5).is_none());
+    }
+    #[test]
+    fn commit() {
+        let cap = GITHUB_URL
+            .captures("https://github.com/jellyfin/jellyfin.org/commit/0123456789abcdef")
+            .unwrap();
+        assert_eq!(&cap[2], "jellyfin.org");
+        assert_eq!(&cap[4], "0123456789abcdef");
+    }
+    #[test]
+    fn release_in_link() {
+        let cap = GITHUB_URL
+            .captures(r#"<a href="https://github.com/jellyfin/jellyfin/releases/tag/v10.7.0-RC1">here</a>"#)
+            .unwrap();
+        assert_eq!(&cap[5], "v10.7.0-RC1");
+    }
+}
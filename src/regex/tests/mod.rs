@@ -1 +1,2 @@
 mod conversion_tests;
+mod github_url_tests;
@@ -0,0 +1,103 @@
+//! Reloading of config.toml while the bot is running
+//!
+//! The most recently loaded config is broadcast to every task that uses it. Tasks read the
+//! latest value whenever they need it so a reload takes effect without restarting or logging in
+//! again. Room aliases in a new config are resolved before it is broadcast.
+
+use crate::config::{Config, ConfigDiff, ConfigError};
+use crate::matrix::rooms::RoomAliases;
+use futures::future::pending;
+use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch::{self, Receiver, Sender};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{error, info};
+
+/// Receiving half of the config channel. Always holds the most recently loaded config.
+pub type ConfigReceiver = Receiver<Arc<Config>>;
+
+/// Loads config.toml again on request and hands the result to every task using it
+pub struct ConfigReloader {
+    /// Config currently in use.
+    current: Mutex<Arc<Config>>,
+    /// Held for the duration of a reload so reloads never overlap.
+    reloading: AsyncMutex<()>,
+    /// Rooms of the aliases used in the config.
+    aliases: Arc<RoomAliases>,
+    /// Sends newly loaded configs to every task.
+    send: Sender<Arc<Config>>,
+    /// Kept so new receivers can be handed out and sending never fails.
+    recv: ConfigReceiver,
+}
+
+impl ConfigReloader {
+    /// Creates a reloader that starts out with the supplied config.
+    ///
+    /// The aliases in `config` must already have been refreshed.
+    pub fn new(config: Config, aliases: Arc<RoomAliases>) -> Self {
+        let config = Arc::new(config);
+        let (send, recv) = watch::channel(config.clone());
+        Self {
+            current: Mutex::new(config),
+            reloading: AsyncMutex::new(()),
+            aliases,
+            send,
+            recv,
+        }
+    }
+
+    /// Returns the config currently in use
+    pub fn current(&self) -> Arc<Config> {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Returns a receiver that always holds the most recently loaded config
+    pub fn subscribe(&self) -> ConfigReceiver {
+        self.recv.clone()
+    }
+
+    /// Returns the rooms of the aliases used in the config
+    pub fn aliases(&self) -> &Arc<RoomAliases> {
+        &self.aliases
+    }
+
+    /// Loads and validates config.toml, resolves its room aliases, then swaps it in if it is valid.
+    ///
+    /// Returns what changed. The config in use is left untouched if the new one is invalid.
+    pub async fn reload(&self) -> Result<ConfigDiff, ConfigError> {
+        let _reloading = self.reloading.lock().await;
+        let config = Config::try_load_config()?;
+        self.aliases.refresh(config.room_aliases()).await;
+        let config = Arc::new(config);
+        let diff = self.current().diff(&config);
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = config.clone();
+        if self.send.broadcast(config).is_err() {
+            error!("Unable to broadcast reloaded config. This should never happen");
+        }
+        Ok(diff)
+    }
+}
+
+/// Reloads the config every time the process receives SIGHUP
+///
+/// Never resolves
+pub async fn reload_on_sighup(reloader: Arc<ConfigReloader>) {
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to register SIGHUP handler due to error {:?}", e);
+            return pending().await;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        info!("Recieved SIGHUP, reloading config...");
+        match reloader.reload().await {
+            Ok(diff) => info!("Reloaded config. {}", diff.to_string().replace('\n', ". ")),
+            Err(e) => error!("Unable to reload config, keeping current config: {}", e),
+        }
+    }
+    pending().await
+}
@@ -0,0 +1,60 @@
+//! Helpers used to coordinate a graceful shutdown of all running tasks
+
+use futures::future::pending;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch::{self, Receiver, Sender};
+use tracing::{error, info};
+
+/// Receiving half of the shutdown channel handed to every long running task.
+///
+/// Holds `true` once a shutdown has been requested.
+pub type ShutdownReceiver = Receiver<bool>;
+
+/// Sending half of the shutdown channel. Broadcasting `true` requests a shutdown.
+pub type ShutdownSender = Sender<bool>;
+
+/// Creates a new shutdown channel with no shutdown requested
+pub fn channel() -> (ShutdownSender, ShutdownReceiver) {
+    watch::channel(false)
+}
+
+/// Waits until the process receives either SIGTERM or SIGINT
+///
+/// Never resolves if the signal handlers are unable to be registered
+pub async fn wait_for_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to register SIGTERM handler due to error {:?}", e);
+            return pending().await;
+        }
+    };
+    let mut sigint = match signal(SignalKind::interrupt()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to register SIGINT handler due to error {:?}", e);
+            return pending().await;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => info!("Recieved SIGTERM, shutting down..."),
+        _ = sigint.recv() => info!("Recieved SIGINT, shutting down..."),
+    }
+}
+
+/// Returns `true` if a shutdown has been requested
+pub fn is_shutdown(shutdown: &ShutdownReceiver) -> bool {
+    *shutdown.borrow()
+}
+
+/// Resolves once a shutdown has been requested or the sending half has been dropped
+pub async fn wait_for_shutdown(shutdown: &mut ShutdownReceiver) {
+    if is_shutdown(shutdown) {
+        return;
+    }
+    while let Some(v) = shutdown.recv().await {
+        if v {
+            return;
+        }
+    }
+}
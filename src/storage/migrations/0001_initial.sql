@@ -0,0 +1,24 @@
+-- Initial schema holding the state previously kept in session.ron,
+-- matrix_listener.ron and matrix_responder.ron
+
+CREATE TABLE session (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    -- Matrix session serialized as json
+    data TEXT NOT NULL
+);
+
+CREATE TABLE sync_state (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    next_batch TEXT
+);
+
+CREATE TABLE correction_cooldown (
+    room_id TEXT PRIMARY KEY NOT NULL,
+    -- Milliseconds since the unix epoch
+    last_correction INTEGER NOT NULL
+);
+
+CREATE TABLE txn_state (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    last_txn_id INTEGER NOT NULL
+);
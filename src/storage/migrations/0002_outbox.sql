@@ -0,0 +1,10 @@
+-- Outbound messages waiting to be acknowledged by the homeserver
+
+CREATE TABLE outbox (
+    txn_id TEXT PRIMARY KEY NOT NULL,
+    room_id TEXT NOT NULL,
+    -- Message serialized as json
+    message TEXT NOT NULL,
+    -- Milliseconds since the unix epoch
+    queued_at INTEGER NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Last known room of every room alias that has been resolved
+
+CREATE TABLE room_aliases (
+    alias TEXT PRIMARY KEY NOT NULL,
+    room_id TEXT NOT NULL
+);
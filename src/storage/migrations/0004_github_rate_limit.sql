@@ -0,0 +1,11 @@
+-- Budget of GitHub API points reported by the most recent query
+
+CREATE TABLE github_rate_limit (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    -- Points the most recent query cost
+    cost INTEGER NOT NULL,
+    -- Points left until the budget resets
+    remaining INTEGER NOT NULL,
+    -- Milliseconds since the unix epoch
+    reset_at INTEGER NOT NULL
+);
@@ -0,0 +1,241 @@
+//! Persistent storage used by the bot between restarts
+//!
+//! All state is kept in a single SQLite database inside of `MATRIX_BOT_DATA_DIR`. State from the
+//! ron files used by older versions is imported the first time the database is created.
+//!
+//! The store runs on a thread of its own so database access never blocks the async tasks using
+//! it. Jobs run one at a time in the order they were sent, so queued writes are always seen by
+//! later reads.
+//!
+//! Relevant tests are in a test submodule
+//!
+//! Tests cover atomic writes, recovery from missing or corrupted files, schema migrations,
+//! the outbox, importing from ron files, and the order jobs run in on the storage thread
+
+#[cfg(test)]
+mod tests;
+
+mod ron_file;
+mod sqlite;
+
+pub use ron_file::RonStore;
+pub use sqlite::SqliteStore;
+
+use crate::config::{ListenerStorage, ResponderStorage, RoomAliasStorage, SessionStorage};
+use crate::messages::OutboxMessage;
+use lazy_static::lazy_static;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::{debug, error};
+
+/// File name of the SQLite database inside of `MATRIX_BOT_DATA_DIR`
+pub const DATABASE_FILE: &str = "matrix_bot.sqlite";
+
+lazy_static! {
+    /// Store shared by every part of the bot that needs persistent state
+    static ref STORE: StoreThread = {
+        let store = match SqliteStore::open(&data_path(DATABASE_FILE)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to open storage database due to error {}", e);
+                process::exit(3)
+            }
+        };
+        let thread = StoreThread::spawn(Box::new(store));
+        STORE_OPENED.store(true, Ordering::SeqCst);
+        thread
+    };
+}
+
+/// Set once the shared store has been opened
+static STORE_OPENED: AtomicBool = AtomicBool::new(false);
+
+/// Work run against the store on its thread
+type Job = Box<dyn FnOnce(&mut dyn StateStore) + Send>;
+
+#[derive(Error, Debug)]
+/// Type used to represent failures while reading or writing persistent state
+pub enum StorageError {
+    #[error("Unable to access {0:?} due to error {1}")]
+    /// Returned if a file or its directory cannot be read from or written to
+    Io(PathBuf, io::Error),
+    #[error("Unable to parse {0:?} due to invalid ron: {1}")]
+    /// Returned if the file contents are not valid ron for the requested type
+    InvalidRon(PathBuf, ron::Error),
+    #[error("Unable to format data as ron, this should never occur. Error is {0}")]
+    /// Returned if the data cannot be serialized as ron
+    Serialize(ron::Error),
+    #[error("Database error: {0}")]
+    /// Returned if a query against the database fails
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Invalid data found in database: {0}")]
+    /// Returned if a stored value cannot be converted back into its runtime type
+    InvalidData(String),
+}
+
+/// Backend that all persistent bot state is loaded from and saved to
+pub trait StateStore {
+    /// Loads the saved matrix session
+    fn load_session(&mut self) -> Result<SessionStorage, StorageError>;
+    /// Replaces the saved matrix session
+    fn save_session(&mut self, storage: &SessionStorage) -> Result<(), StorageError>;
+    /// Loads the matrix listener state
+    fn load_listener(&mut self) -> Result<ListenerStorage, StorageError>;
+    /// Replaces the matrix listener state
+    fn save_listener(&mut self, storage: &ListenerStorage) -> Result<(), StorageError>;
+    /// Loads the matrix responder state
+    fn load_responder(&mut self) -> Result<ResponderStorage, StorageError>;
+    /// Replaces the matrix responder state
+    fn save_responder(&mut self, storage: &ResponderStorage) -> Result<(), StorageError>;
+    /// Loads the last known room of every resolved room alias
+    fn load_room_aliases(&mut self) -> Result<RoomAliasStorage, StorageError>;
+    /// Replaces the last known room of every resolved room alias
+    fn save_room_aliases(&mut self, storage: &RoomAliasStorage) -> Result<(), StorageError>;
+    /// Loads all messages waiting in the outbox in the order they were queued
+    fn load_outbox(&mut self) -> Result<Vec<OutboxMessage>, StorageError>;
+    /// Adds a message to the end of the outbox
+    fn push_outbox(&mut self, message: &OutboxMessage) -> Result<(), StorageError>;
+    /// Removes the message with the supplied transaction id from the outbox
+    fn remove_outbox(&mut self, txn_id: &str) -> Result<(), StorageError>;
+    /// Replaces all state, including the outbox, with the state in `from`
+    fn replace_state(&mut self, from: &mut dyn StateStore) -> Result<(), StorageError>;
+}
+
+/// Returns `MATRIX_BOT_DATA_DIR` or the current directory if it is unset
+pub fn data_dir() -> PathBuf {
+    match env::var("MATRIX_BOT_DATA_DIR") {
+        Ok(v) => PathBuf::from(v),
+        Err(_) => PathBuf::new(),
+    }
+}
+
+/// Returns the full path of a storage file inside of `MATRIX_BOT_DATA_DIR`
+pub fn data_path(file_name: &str) -> PathBuf {
+    data_dir().join(file_name)
+}
+
+/// Returns the shared store, opening it on first use.
+///
+/// Exits the program if the database cannot be opened or migrated.
+pub fn store() -> &'static StoreThread {
+    &STORE
+}
+
+/// Waits for every queued write to the shared store to finish.
+///
+/// Does nothing if the store was never opened.
+pub fn flush() {
+    if STORE_OPENED.load(Ordering::SeqCst) {
+        store().flush();
+    }
+}
+
+/// Store running on a thread of its own
+pub struct StoreThread {
+    /// Sends jobs to the store's thread. Locked only while sending.
+    jobs: Mutex<Sender<Job>>,
+}
+
+impl StoreThread {
+    /// Moves a store to a new thread that runs jobs until every handle has been dropped.
+    ///
+    /// Exits the program if the thread cannot be started.
+    pub fn spawn(mut store: Box<dyn StateStore + Send>) -> Self {
+        let (jobs, recv) = mpsc::channel::<Job>();
+        let result = thread::Builder::new()
+            .name("storage".to_string())
+            .spawn(move || {
+                for job in recv {
+                    job(store.as_mut());
+                }
+                debug!("Storage thread stopped");
+            });
+        if let Err(e) = result {
+            error!("Unable to start storage thread due to error {}", e);
+            process::exit(3)
+        }
+        Self {
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    /// Queues a job without waiting for it to run
+    pub fn queue<F>(&self, job: F)
+    where
+        F: FnOnce(&mut dyn StateStore) + Send + 'static,
+    {
+        let jobs = match self.jobs.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner(),
+        };
+        if jobs.send(Box::new(job)).is_err() {
+            error!("Storage thread has stopped. Unable to access storage");
+            process::exit(10)
+        }
+    }
+
+    /// Runs a job and blocks the current thread until it returns.
+    ///
+    /// Async code should use `run_async` instead.
+    pub fn run<T, F>(&self, job: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn StateStore) -> T + Send + 'static,
+    {
+        let (resp, recv) = mpsc::sync_channel(1);
+        self.queue(move |store| {
+            let _ = resp.send(job(store));
+        });
+        match recv.recv() {
+            Ok(v) => v,
+            Err(_) => {
+                error!("Storage thread stopped while running a job");
+                process::exit(10)
+            }
+        }
+    }
+
+    /// Runs a job and waits for it to return without blocking the async runtime
+    pub async fn run_async<T, F>(&self, job: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn StateStore) -> T + Send + 'static,
+    {
+        let (resp, recv) = oneshot::channel();
+        self.queue(move |store| {
+            let _ = resp.send(job(store));
+        });
+        match recv.await {
+            Ok(v) => v,
+            Err(_) => {
+                error!("Storage thread stopped while running a job");
+                process::exit(10)
+            }
+        }
+    }
+
+    /// Blocks until every job queued so far has run
+    pub fn flush(&self) {
+        self.run(|_| ())
+    }
+}
+
+/// Copies all state from one store into another
+pub fn copy_state(from: &mut dyn StateStore, to: &mut dyn StateStore) -> Result<(), StorageError> {
+    to.save_session(&from.load_session()?)?;
+    to.save_listener(&from.load_listener()?)?;
+    to.save_responder(&from.load_responder()?)?;
+    to.save_room_aliases(&from.load_room_aliases()?)?;
+    for message in from.load_outbox()? {
+        to.push_outbox(&message)?;
+    }
+    Ok(())
+}
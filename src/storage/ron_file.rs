@@ -0,0 +1,203 @@
+//! Crash safe loading and saving of the ron storage files used by older versions of the bot
+//!
+//! Data is written to a temporary file, synced to disk, and then renamed over the original so
+//! a crash mid-write can never leave a partially written file behind. The previous file is kept
+//! as a `.bak` copy and is used when the main file is missing or unable to be parsed.
+
+use super::{copy_state, StateStore, StorageError};
+use crate::config::{ListenerStorage, ResponderStorage, RoomAliasStorage, SessionStorage};
+use crate::messages::OutboxMessage;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, trace, warn};
+
+/// File name of the saved matrix session
+pub const SESSION_FILE: &str = "session.ron";
+/// File name of the saved matrix listener state
+pub const LISTENER_FILE: &str = "matrix_listener.ron";
+/// File name of the saved matrix responder state
+pub const RESPONDER_FILE: &str = "matrix_responder.ron";
+/// File name of the saved outbox
+pub const OUTBOX_FILE: &str = "outbox.ron";
+/// File name of the saved room aliases
+pub const ROOM_ALIASES_FILE: &str = "room_aliases.ron";
+
+/// Store backed by one ron file per kind of state, as used by older versions of the bot
+pub struct RonStore {
+    /// Directory containing the ron files
+    dir: PathBuf,
+}
+
+impl RonStore {
+    /// Creates a store that reads and writes ron files inside of `dir`
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Returns `true` if any of the ron files or their backups exist
+    pub fn exists(&self) -> bool {
+        [SESSION_FILE, LISTENER_FILE, RESPONDER_FILE]
+            .iter()
+            .any(|f| self.dir.join(f).exists() || with_extension(&self.dir.join(f), "bak").exists())
+    }
+
+    /// Loads `file_name` or returns a default value if there is no usable data
+    fn load<T: Default + DeserializeOwned>(&self, file_name: &str) -> Result<T, StorageError> {
+        Ok(load_or_recover(&self.dir.join(file_name))?.unwrap_or_default())
+    }
+}
+
+impl StateStore for RonStore {
+    fn load_session(&mut self) -> Result<SessionStorage, StorageError> {
+        self.load(SESSION_FILE)
+    }
+    fn save_session(&mut self, storage: &SessionStorage) -> Result<(), StorageError> {
+        write_atomic(&self.dir.join(SESSION_FILE), storage)
+    }
+    fn load_listener(&mut self) -> Result<ListenerStorage, StorageError> {
+        self.load(LISTENER_FILE)
+    }
+    fn save_listener(&mut self, storage: &ListenerStorage) -> Result<(), StorageError> {
+        write_atomic(&self.dir.join(LISTENER_FILE), storage)
+    }
+    fn load_responder(&mut self) -> Result<ResponderStorage, StorageError> {
+        self.load(RESPONDER_FILE)
+    }
+    fn save_responder(&mut self, storage: &ResponderStorage) -> Result<(), StorageError> {
+        write_atomic(&self.dir.join(RESPONDER_FILE), storage)
+    }
+    fn load_room_aliases(&mut self) -> Result<RoomAliasStorage, StorageError> {
+        self.load(ROOM_ALIASES_FILE)
+    }
+    fn save_room_aliases(&mut self, storage: &RoomAliasStorage) -> Result<(), StorageError> {
+        write_atomic(&self.dir.join(ROOM_ALIASES_FILE), storage)
+    }
+    fn load_outbox(&mut self) -> Result<Vec<OutboxMessage>, StorageError> {
+        self.load(OUTBOX_FILE)
+    }
+    fn push_outbox(&mut self, message: &OutboxMessage) -> Result<(), StorageError> {
+        let mut outbox = self.load_outbox()?;
+        outbox.retain(|m| m.txn_id != message.txn_id);
+        outbox.push(message.clone());
+        write_atomic(&self.dir.join(OUTBOX_FILE), &outbox)
+    }
+    fn remove_outbox(&mut self, txn_id: &str) -> Result<(), StorageError> {
+        let mut outbox = self.load_outbox()?;
+        outbox.retain(|m| m.txn_id != txn_id);
+        write_atomic(&self.dir.join(OUTBOX_FILE), &outbox)
+    }
+    /// Replaces all state one file at a time. Files already replaced are kept if a later one fails
+    fn replace_state(&mut self, from: &mut dyn StateStore) -> Result<(), StorageError> {
+        write_atomic(&self.dir.join(OUTBOX_FILE), &Vec::<OutboxMessage>::new())?;
+        copy_state(from, self)
+    }
+}
+
+/// Loads the file at `path`, falling back to its backup if required.
+///
+/// Returns `Ok(None)` if no usable data was found. Corrupted files are moved aside with a
+/// `.corrupt` extension so they can be inspected later.
+pub(crate) fn load_or_recover<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, StorageError> {
+    let backup = with_extension(path, "bak");
+    match read(path) {
+        Ok(Some(v)) => return Ok(Some(v)),
+        Ok(None) => trace!("{:?} does not exist, checking for a backup", path),
+        Err(StorageError::InvalidRon(p, e)) => {
+            warn!(
+                "Unable to parse {:?} due to error {}. Attempting to recover from backup",
+                p, e
+            );
+            let corrupt = with_extension(path, "corrupt");
+            if let Err(e) = fs::rename(path, &corrupt) {
+                return Err(StorageError::Io(path.to_path_buf(), e));
+            }
+            warn!("Moved corrupted file to {:?}", corrupt);
+        }
+        Err(e) => return Err(e),
+    }
+    match read(&backup) {
+        Ok(Some(v)) => {
+            warn!("Recovered storage from backup {:?}", backup);
+            Ok(Some(v))
+        }
+        Ok(None) => Ok(None),
+        Err(StorageError::InvalidRon(p, e)) => {
+            error!(
+                "Backup {:?} is also corrupted due to error {}. Starting with default data",
+                p, e
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads and parses the file at `path`. Returns `Ok(None)` if it does not exist.
+fn read<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, StorageError> {
+    let mut file = match File::open(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(StorageError::Io(path.to_path_buf(), e)),
+    };
+    let mut contents = String::new();
+    if let Err(e) = file.read_to_string(&mut contents) {
+        return Err(StorageError::Io(path.to_path_buf(), e));
+    }
+    match ron::from_str(&contents) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) => Err(StorageError::InvalidRon(path.to_path_buf(), e)),
+    }
+}
+
+/// Writes `data` to `path` via a synced temporary file and rename.
+///
+/// The file being replaced is kept as a `.bak` copy.
+pub(crate) fn write_atomic<T: Serialize>(path: &Path, data: &T) -> Result<(), StorageError> {
+    let ron = ron::to_string(data).map_err(StorageError::Serialize)?;
+    let tmp = with_extension(path, "tmp");
+    let backup = with_extension(path, "bak");
+    let io_err = |p: &Path| {
+        let p = p.to_path_buf();
+        move |e: io::Error| StorageError::Io(p, e)
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp)
+        .map_err(io_err(&tmp))?;
+    file.write_all(ron.as_bytes()).map_err(io_err(&tmp))?;
+    file.sync_all().map_err(io_err(&tmp))?;
+    drop(file);
+
+    match fs::rename(path, &backup) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => return Err(StorageError::Io(backup, e)),
+    }
+    fs::rename(&tmp, path).map_err(io_err(path))?;
+    sync_parent(path).map_err(io_err(path))
+}
+
+/// Syncs the directory containing `path` so renames within it are durable
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Returns `path` with `extension` appended. `session.ron` becomes `session.ron.bak`.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path
+        .file_name()
+        .map(|v| v.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
@@ -0,0 +1,358 @@
+//! SQLite backed store holding all persistent bot state in a single database
+//!
+//! The schema is managed by the migrations embedded in `MIGRATIONS`. The schema version is kept
+//! in the `user_version` pragma so only migrations newer than the database are ever run.
+
+use super::{copy_state, RonStore, StateStore, StorageError};
+use crate::config::{
+    GithubRateLimit, ListenerStorage, ResponderStorage, RoomAliasStorage, SessionStorage,
+};
+use crate::messages::OutboxMessage;
+use ruma::{RoomAliasId, RoomId};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, trace};
+
+/// Schema migrations embedded in the binary.
+///
+/// The migration at index `n` upgrades the database to schema version `n + 1`. Never edit or
+/// reorder existing entries, only append new ones.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_initial.sql"),
+    include_str!("migrations/0002_outbox.sql"),
+    include_str!("migrations/0003_room_aliases.sql"),
+    include_str!("migrations/0004_github_rate_limit.sql"),
+];
+
+/// Store that keeps all state in a SQLite database
+pub struct SqliteStore {
+    /// Open connection to the database
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path` and migrates it to the latest schema.
+    ///
+    /// If the database is newly created, state is imported from any ron files found next to it.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")?;
+        let mut store = Self { conn };
+        let ron_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        store.initialize(Some(RonStore::new(ron_dir.to_path_buf())))?;
+        Ok(store)
+    }
+
+    /// Opens a database that only exists in memory. Used for testing.
+    #[cfg(test)]
+    pub fn open_in_memory(import: Option<RonStore>) -> Result<Self, StorageError> {
+        let mut store = Self {
+            conn: Connection::open_in_memory()?,
+        };
+        store.initialize(import)?;
+        Ok(store)
+    }
+
+    /// Returns the current schema version of the database
+    pub fn schema_version(&self) -> Result<usize, StorageError> {
+        let version: i64 = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        Ok(version as usize)
+    }
+
+    /// Runs pending migrations and the one-time ron import as a single transaction
+    fn initialize(&mut self, import: Option<RonStore>) -> Result<(), StorageError> {
+        self.transaction(|store| store.migrate_and_import(import))
+    }
+
+    /// Runs `f` as a single transaction that is rolled back if it fails
+    fn transaction(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        match f(self) {
+            Ok(_) => {
+                self.conn.execute_batch("COMMIT")?;
+                Ok(())
+            }
+            Err(e) => {
+                self.conn.execute_batch("ROLLBACK")?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Applies all migrations newer than the database, then imports ron files into a new database
+    fn migrate_and_import(&mut self, import: Option<RonStore>) -> Result<(), StorageError> {
+        let current = self.schema_version()?;
+        if current > MIGRATIONS.len() {
+            return Err(StorageError::InvalidData(format!(
+                "Database schema version {} is newer than the latest known version {}",
+                current,
+                MIGRATIONS.len()
+            )));
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            trace!("Migrating database to schema version {}", index + 1);
+            self.conn.execute_batch(migration)?;
+            self.conn
+                .pragma_update(None, "user_version", &((index + 1) as i64))?;
+        }
+        if current == 0 {
+            if let Some(mut ron) = import.filter(|r| r.exists()) {
+                info!("Importing existing state from ron files into new database");
+                copy_state(&mut ron, self)?;
+                info!(
+                    "Imported existing state. The ron files are no longer used and can be removed"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn load_session(&mut self) -> Result<SessionStorage, StorageError> {
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT data FROM session WHERE id = 0", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let session = match data {
+            Some(v) => Some(serde_json::from_str(&v).map_err(|e| {
+                StorageError::InvalidData(format!("Unable to parse session due to error {}", e))
+            })?),
+            None => None,
+        };
+        Ok(SessionStorage { session })
+    }
+
+    fn save_session(&mut self, storage: &SessionStorage) -> Result<(), StorageError> {
+        match &storage.session {
+            Some(v) => {
+                let data = serde_json::to_string(v).map_err(|e| {
+                    StorageError::InvalidData(format!(
+                        "Unable to format session due to error {}",
+                        e
+                    ))
+                })?;
+                self.conn.execute(
+                    "INSERT OR REPLACE INTO session (id, data) VALUES (0, ?1)",
+                    params![data],
+                )?;
+            }
+            None => {
+                self.conn.execute("DELETE FROM session", NO_PARAMS)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_listener(&mut self) -> Result<ListenerStorage, StorageError> {
+        let last_sync: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT next_batch FROM sync_state WHERE id = 0",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        let mut last_correction_time = HashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT room_id, last_correction FROM correction_cooldown")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        for row in rows {
+            let (room_id, millis) = row?;
+            let room_id = RoomId::try_from(room_id.as_str()).map_err(|e| {
+                StorageError::InvalidData(format!("Invalid room id {:?}: {}", room_id, e))
+            })?;
+            last_correction_time.insert(room_id, from_millis(millis));
+        }
+        let github_rate_limit = self
+            .conn
+            .query_row(
+                "SELECT cost, remaining, reset_at FROM github_rate_limit WHERE id = 0",
+                NO_PARAMS,
+                |row| {
+                    Ok(GithubRateLimit {
+                        cost: row.get(0)?,
+                        remaining: row.get(1)?,
+                        reset_at: from_millis(row.get(2)?),
+                    })
+                },
+            )
+            .optional()?;
+        Ok(ListenerStorage {
+            last_sync,
+            last_correction_time,
+            github_rate_limit,
+        })
+    }
+
+    fn save_listener(&mut self, storage: &ListenerStorage) -> Result<(), StorageError> {
+        let sp = self.conn.savepoint()?;
+        sp.execute(
+            "INSERT OR REPLACE INTO sync_state (id, next_batch) VALUES (0, ?1)",
+            params![storage.last_sync],
+        )?;
+        sp.execute("DELETE FROM correction_cooldown", NO_PARAMS)?;
+        for (room_id, time) in &storage.last_correction_time {
+            sp.execute(
+                "INSERT INTO correction_cooldown (room_id, last_correction) VALUES (?1, ?2)",
+                params![room_id.as_str(), to_millis(time)],
+            )?;
+        }
+        match &storage.github_rate_limit {
+            Some(v) => sp.execute(
+                "INSERT OR REPLACE INTO github_rate_limit (id, cost, remaining, reset_at) VALUES (0, ?1, ?2, ?3)",
+                params![v.cost, v.remaining, to_millis(&v.reset_at)],
+            )?,
+            None => sp.execute("DELETE FROM github_rate_limit", NO_PARAMS)?,
+        };
+        sp.commit()?;
+        Ok(())
+    }
+
+    fn load_responder(&mut self) -> Result<ResponderStorage, StorageError> {
+        let last_txn_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT last_txn_id FROM txn_state WHERE id = 0",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(ResponderStorage {
+            last_txn_id: last_txn_id.unwrap_or_default() as u64,
+        })
+    }
+
+    fn save_responder(&mut self, storage: &ResponderStorage) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO txn_state (id, last_txn_id) VALUES (0, ?1)",
+            params![storage.last_txn_id as i64],
+        )?;
+        Ok(())
+    }
+
+    fn load_room_aliases(&mut self) -> Result<RoomAliasStorage, StorageError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT alias, room_id FROM room_aliases")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut aliases = HashMap::new();
+        for row in rows {
+            let (alias, room_id) = row?;
+            let alias = RoomAliasId::try_from(alias.as_str()).map_err(|e| {
+                StorageError::InvalidData(format!("Invalid room alias {:?}: {}", alias, e))
+            })?;
+            let room_id = RoomId::try_from(room_id.as_str()).map_err(|e| {
+                StorageError::InvalidData(format!("Invalid room id {:?}: {}", room_id, e))
+            })?;
+            aliases.insert(alias, room_id);
+        }
+        Ok(RoomAliasStorage { aliases })
+    }
+
+    fn save_room_aliases(&mut self, storage: &RoomAliasStorage) -> Result<(), StorageError> {
+        let sp = self.conn.savepoint()?;
+        sp.execute("DELETE FROM room_aliases", NO_PARAMS)?;
+        for (alias, room_id) in &storage.aliases {
+            sp.execute(
+                "INSERT INTO room_aliases (alias, room_id) VALUES (?1, ?2)",
+                params![alias.as_str(), room_id.as_str()],
+            )?;
+        }
+        sp.commit()?;
+        Ok(())
+    }
+
+    fn load_outbox(&mut self) -> Result<Vec<OutboxMessage>, StorageError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT txn_id, room_id, message FROM outbox ORDER BY queued_at, rowid")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut outbox = Vec::new();
+        for row in rows {
+            let (txn_id, room_id, message) = row?;
+            let room_id = RoomId::try_from(room_id.as_str()).map_err(|e| {
+                StorageError::InvalidData(format!("Invalid room id {:?}: {}", room_id, e))
+            })?;
+            let message = serde_json::from_str(&message).map_err(|e| {
+                StorageError::InvalidData(format!(
+                    "Unable to parse outbox message {} due to error {}",
+                    txn_id, e
+                ))
+            })?;
+            outbox.push(OutboxMessage {
+                txn_id,
+                room_id,
+                message,
+            });
+        }
+        Ok(outbox)
+    }
+
+    fn push_outbox(&mut self, message: &OutboxMessage) -> Result<(), StorageError> {
+        let data = serde_json::to_string(&message.message).map_err(|e| {
+            StorageError::InvalidData(format!(
+                "Unable to format outbox message {} due to error {}",
+                message.txn_id, e
+            ))
+        })?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO outbox (txn_id, room_id, message, queued_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                message.txn_id,
+                message.room_id.as_str(),
+                data,
+                to_millis(&SystemTime::now())
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_outbox(&mut self, txn_id: &str) -> Result<(), StorageError> {
+        self.conn
+            .execute("DELETE FROM outbox WHERE txn_id = ?1", params![txn_id])?;
+        Ok(())
+    }
+
+    /// Replaces all state as a single transaction, so nothing is changed if any part fails
+    fn replace_state(&mut self, from: &mut dyn StateStore) -> Result<(), StorageError> {
+        self.transaction(|store| {
+            store.conn.execute("DELETE FROM outbox", NO_PARAMS)?;
+            copy_state(from, store)
+        })
+    }
+}
+
+/// Converts a time to milliseconds since the unix epoch, clamping times before it to 0
+fn to_millis(time: &SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Converts milliseconds since the unix epoch to a time
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
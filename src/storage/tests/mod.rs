@@ -0,0 +1,3 @@
+mod ron_tests;
+mod sqlite_tests;
+mod thread_tests;
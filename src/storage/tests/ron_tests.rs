@@ -0,0 +1,115 @@
+use crate::storage::ron_file::{load_or_recover, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+struct TestStorage {
+    last_sync: Option<String>,
+    count: u64,
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "matrix-bot-storage-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn round_trip() {
+    let path = test_dir("round_trip").join("storage.ron");
+    let storage = TestStorage {
+        last_sync: Some("s72594_4483_1934".to_string()),
+        count: 5,
+    };
+    write_atomic(&path, &storage).unwrap();
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(Some(storage), loaded)
+}
+
+#[test]
+fn missing_file() {
+    let path = test_dir("missing_file").join("storage.ron");
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(None, loaded)
+}
+
+#[test]
+fn shorter_payload_truncates() {
+    let path = test_dir("shorter_payload").join("storage.ron");
+    let long = TestStorage {
+        last_sync: Some("a_very_long_sync_token_that_takes_up_space".to_string()),
+        count: 1,
+    };
+    let short = TestStorage {
+        last_sync: None,
+        count: 2,
+    };
+    write_atomic(&path, &long).unwrap();
+    write_atomic(&path, &short).unwrap();
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(Some(short), loaded)
+}
+
+#[test]
+fn keeps_backup() {
+    let dir = test_dir("keeps_backup");
+    let path = dir.join("storage.ron");
+    let first = TestStorage {
+        last_sync: None,
+        count: 1,
+    };
+    let second = TestStorage {
+        last_sync: None,
+        count: 2,
+    };
+    write_atomic(&path, &first).unwrap();
+    write_atomic(&path, &second).unwrap();
+    let backup: Option<TestStorage> = load_or_recover(&dir.join("storage.ron.bak")).unwrap();
+    assert_eq!(Some(first), backup)
+}
+
+#[test]
+fn corrupted_recovers_from_backup() {
+    let dir = test_dir("corrupted_backup");
+    let path = dir.join("storage.ron");
+    let first = TestStorage {
+        last_sync: None,
+        count: 1,
+    };
+    write_atomic(&path, &first).unwrap();
+    write_atomic(&path, &first).unwrap();
+    fs::write(&path, "(last_sync: No").unwrap();
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(Some(first), loaded);
+    assert!(dir.join("storage.ron.corrupt").exists())
+}
+
+#[test]
+fn corrupted_without_backup() {
+    let dir = test_dir("corrupted_no_backup");
+    let path = dir.join("storage.ron");
+    fs::write(&path, "garbage").unwrap();
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(None, loaded);
+    assert!(dir.join("storage.ron.corrupt").exists())
+}
+
+#[test]
+fn missing_file_uses_backup() {
+    let dir = test_dir("missing_uses_backup");
+    let path = dir.join("storage.ron");
+    let first = TestStorage {
+        last_sync: None,
+        count: 1,
+    };
+    write_atomic(&path, &first).unwrap();
+    fs::rename(&path, dir.join("storage.ron.bak")).unwrap();
+    let loaded: Option<TestStorage> = load_or_recover(&path).unwrap();
+    assert_eq!(Some(first), loaded)
+}
@@ -0,0 +1,202 @@
+use crate::config::{GithubRateLimit, ListenerStorage, ResponderStorage, RoomAliasStorage};
+use crate::messages::{MatrixMessageType, OutboxMessage};
+use crate::storage::{RonStore, SqliteStore, StateStore};
+use ruma::{RoomAliasId, RoomId};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("matrix-bot-sqlite-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn listener_storage() -> ListenerStorage {
+    let mut last_correction_time = HashMap::new();
+    last_correction_time.insert(
+        RoomId::try_from("!randomalpha:homeserver.com").unwrap(),
+        UNIX_EPOCH + Duration::from_millis(1_600_000_000_000),
+    );
+    ListenerStorage {
+        last_sync: Some("s72594_4483_1934".to_string()),
+        last_correction_time,
+        github_rate_limit: Some(GithubRateLimit {
+            cost: 1,
+            remaining: 4999,
+            reset_at: UNIX_EPOCH + Duration::from_millis(1_600_003_600_000),
+        }),
+    }
+}
+
+#[test]
+fn migrates_to_latest() {
+    let store = SqliteStore::open_in_memory(None).unwrap();
+    assert_eq!(4, store.schema_version().unwrap())
+}
+
+#[test]
+fn empty_database() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    assert!(store.load_session().unwrap().session.is_none());
+    assert!(store.load_listener().unwrap().last_sync.is_none());
+    assert!(store.load_listener().unwrap().github_rate_limit.is_none());
+    assert_eq!(0, store.load_responder().unwrap().last_txn_id)
+}
+
+#[test]
+fn listener_round_trip() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    let storage = listener_storage();
+    store.save_listener(&storage).unwrap();
+    let loaded = store.load_listener().unwrap();
+    assert_eq!(storage.last_sync, loaded.last_sync);
+    assert_eq!(storage.last_correction_time, loaded.last_correction_time);
+    assert_eq!(storage.github_rate_limit, loaded.github_rate_limit)
+}
+
+#[test]
+fn responder_round_trip() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    store
+        .save_responder(&ResponderStorage { last_txn_id: 42 })
+        .unwrap();
+    assert_eq!(42, store.load_responder().unwrap().last_txn_id)
+}
+
+#[test]
+fn room_aliases_round_trip() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    let mut storage = RoomAliasStorage::default();
+    storage.aliases.insert(
+        RoomAliasId::try_from("#help:homeserver.com").unwrap(),
+        RoomId::try_from("!randomalpha:homeserver.com").unwrap(),
+    );
+    store.save_room_aliases(&storage).unwrap();
+    assert_eq!(storage.aliases, store.load_room_aliases().unwrap().aliases);
+    store
+        .save_room_aliases(&RoomAliasStorage::default())
+        .unwrap();
+    assert!(store.load_room_aliases().unwrap().aliases.is_empty())
+}
+
+fn outbox_message(txn_id: &str) -> OutboxMessage {
+    OutboxMessage {
+        txn_id: txn_id.to_string(),
+        room_id: RoomId::try_from("!randomalpha:homeserver.com").unwrap(),
+        message: MatrixMessageType::Notice(format!("message {}", txn_id)),
+    }
+}
+
+#[test]
+fn outbox_keeps_order() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    for txn_id in &["1", "2", "3"] {
+        store.push_outbox(&outbox_message(txn_id)).unwrap();
+    }
+    store.remove_outbox("2").unwrap();
+    let outbox: Vec<String> = store
+        .load_outbox()
+        .unwrap()
+        .into_iter()
+        .map(|m| m.txn_id)
+        .collect();
+    assert_eq!(vec!["1", "3"], outbox)
+}
+
+#[test]
+fn outbox_round_trip() {
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    store.push_outbox(&outbox_message("1")).unwrap();
+    let outbox = store.load_outbox().unwrap();
+    assert_eq!(1, outbox.len());
+    assert_eq!("!randomalpha:homeserver.com", outbox[0].room_id.as_str());
+    match &outbox[0].message {
+        MatrixMessageType::Notice(v) => assert_eq!("message 1", v),
+        v => panic!("Expected a notice and got {:?}", v),
+    }
+}
+
+#[test]
+fn replace_state_replaces_outbox() {
+    let dir = test_dir("replace");
+    let mut ron = RonStore::new(dir);
+    ron.save_listener(&listener_storage()).unwrap();
+    ron.push_outbox(&outbox_message("2")).unwrap();
+
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    store.push_outbox(&outbox_message("1")).unwrap();
+    store.replace_state(&mut ron).unwrap();
+    assert_eq!(
+        listener_storage().last_sync,
+        store.load_listener().unwrap().last_sync
+    );
+    let outbox: Vec<String> = store
+        .load_outbox()
+        .unwrap()
+        .into_iter()
+        .map(|m| m.txn_id)
+        .collect();
+    assert_eq!(vec!["2"], outbox)
+}
+
+#[test]
+fn failed_replace_keeps_state() {
+    let dir = test_dir("failed_replace");
+    let mut ron = RonStore::new(dir.clone());
+    ron.save_responder(&ResponderStorage { last_txn_id: 7 })
+        .unwrap();
+    // The outbox is copied last and is unable to be read
+    fs::create_dir(dir.join("outbox.ron")).unwrap();
+
+    let mut store = SqliteStore::open_in_memory(None).unwrap();
+    store.save_listener(&listener_storage()).unwrap();
+    store.push_outbox(&outbox_message("1")).unwrap();
+    assert!(store.replace_state(&mut ron).is_err());
+    assert_eq!(
+        listener_storage().last_sync,
+        store.load_listener().unwrap().last_sync
+    );
+    assert_eq!(0, store.load_responder().unwrap().last_txn_id);
+    assert_eq!(1, store.load_outbox().unwrap().len())
+}
+
+#[test]
+fn reopen_keeps_data() {
+    let path = test_dir("reopen").join("matrix_bot.sqlite");
+    let storage = listener_storage();
+    SqliteStore::open(&path)
+        .unwrap()
+        .save_listener(&storage)
+        .unwrap();
+    let mut store = SqliteStore::open(&path).unwrap();
+    assert_eq!(4, store.schema_version().unwrap());
+    assert_eq!(storage.last_sync, store.load_listener().unwrap().last_sync)
+}
+
+#[test]
+fn imports_ron_files_once() {
+    let dir = test_dir("import");
+    let mut ron = RonStore::new(dir.clone());
+    let storage = listener_storage();
+    ron.save_listener(&storage).unwrap();
+    ron.save_responder(&ResponderStorage { last_txn_id: 7 })
+        .unwrap();
+
+    let path = dir.join("matrix_bot.sqlite");
+    let mut store = SqliteStore::open(&path).unwrap();
+    let loaded = store.load_listener().unwrap();
+    assert_eq!(storage.last_sync, loaded.last_sync);
+    assert_eq!(storage.last_correction_time, loaded.last_correction_time);
+    assert_eq!(7, store.load_responder().unwrap().last_txn_id);
+
+    // Changes to the ron files after the import are ignored
+    ron.save_responder(&ResponderStorage { last_txn_id: 100 })
+        .unwrap();
+    let mut store = SqliteStore::open(&path).unwrap();
+    assert_eq!(7, store.load_responder().unwrap().last_txn_id)
+}
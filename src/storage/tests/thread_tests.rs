@@ -0,0 +1,41 @@
+use crate::config::ResponderStorage;
+use crate::storage::{SqliteStore, StoreThread};
+
+fn store_thread() -> StoreThread {
+    StoreThread::spawn(Box::new(SqliteStore::open_in_memory(None).unwrap()))
+}
+
+#[test]
+fn queued_writes_run_in_order() {
+    let store = store_thread();
+    for last_txn_id in 1..=5 {
+        store.queue(move |s| {
+            s.save_responder(&ResponderStorage { last_txn_id }).unwrap();
+        });
+    }
+    let loaded = store.run(|s| s.load_responder()).unwrap();
+    assert_eq!(5, loaded.last_txn_id)
+}
+
+#[test]
+fn flush_waits_for_queued_writes() {
+    let store = store_thread();
+    store.queue(|s| {
+        s.save_responder(&ResponderStorage { last_txn_id: 7 })
+            .unwrap();
+    });
+    store.flush();
+    let loaded = store.run(|s| s.load_responder()).unwrap();
+    assert_eq!(7, loaded.last_txn_id)
+}
+
+#[tokio::test]
+async fn run_async_returns_result() {
+    let store = store_thread();
+    store
+        .run_async(|s| s.save_responder(&ResponderStorage { last_txn_id: 3 }))
+        .await
+        .unwrap();
+    let loaded = store.run_async(|s| s.load_responder()).await.unwrap();
+    assert_eq!(3, loaded.last_txn_id)
+}
@@ -0,0 +1,128 @@
+//! Restarts long running subsystems if they panic or exit unexpectedly
+//!
+//! Restarts are delayed with exponential backoff. A subsystem that keeps failing is given up on
+//! so the bot can exit instead of restarting forever.
+
+use crate::messages::{MatrixMessage, MatrixMessageType};
+use crate::shutdown::{is_shutdown, wait_for_shutdown, ShutdownReceiver};
+use ruma::RoomId;
+use std::any::Any;
+use std::cmp;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tokio::time::delay_for;
+use tracing::{error, info, warn};
+
+/// Delay before the first restart of a failed subsystem
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay between restarts of a failed subsystem
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// Number of failures within `FAILURE_WINDOW` after which a subsystem is given up on
+const MAX_FAILURES: usize = 5;
+/// Period over which failures are counted
+const FAILURE_WINDOW: Duration = Duration::from_secs(600);
+
+#[derive(Error, Debug)]
+/// Type used to represent a subsystem that could not be kept running
+pub enum SupervisorError {
+    #[error("The {0} failed {1} times within {2:?} and will not be restarted")]
+    /// Returned once a subsystem has failed `MAX_FAILURES` times within `FAILURE_WINDOW`
+    TooManyFailures(&'static str, usize, Duration),
+}
+
+/// Room that crashes are reported to
+pub struct CrashReporter {
+    /// Room to send reports to
+    pub room_id: RoomId,
+    /// Channel used to send messages to the responder
+    pub send: Sender<MatrixMessage>,
+}
+
+/// Runs the future returned by `start` and restarts it until it exits after a shutdown request.
+///
+/// Crashes are logged and, if a reporter is supplied, sent to its room. Returns an error if the
+/// subsystem fails too often.
+pub async fn supervise<F, Fut>(
+    name: &'static str,
+    mut shutdown: ShutdownReceiver,
+    mut reporter: Option<CrashReporter>,
+    mut start: F,
+) -> Result<(), SupervisorError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut failures: VecDeque<Instant> = VecDeque::new();
+    let mut delay = INITIAL_RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        let reason = match tokio::spawn(start()).await {
+            Ok(_) if is_shutdown(&shutdown) => return Ok(()),
+            Ok(_) => "exited unexpectedly".to_string(),
+            Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+            Err(e) => format!("was cancelled: {}", e),
+        };
+
+        // A subsystem that ran for a while before failing is not failing repeatedly
+        if started.elapsed() >= FAILURE_WINDOW {
+            delay = INITIAL_RESTART_DELAY;
+        }
+        let now = Instant::now();
+        failures.push_back(now);
+        while let Some(v) = failures.front() {
+            if now.duration_since(*v) > FAILURE_WINDOW {
+                failures.pop_front();
+            } else {
+                break;
+            }
+        }
+        if failures.len() >= MAX_FAILURES {
+            let e = SupervisorError::TooManyFailures(name, failures.len(), FAILURE_WINDOW);
+            error!("{}", e);
+            report(&mut reporter, format!("The {} {}. {}", name, reason, e));
+            return Err(e);
+        }
+
+        error!("The {} {}. Restarting in {:?}", name, reason, delay);
+        report(
+            &mut reporter,
+            format!("The {} {}. Restarting in {:?}", name, reason, delay),
+        );
+        tokio::select! {
+            _ = delay_for(delay) => (),
+            _ = wait_for_shutdown(&mut shutdown) => {
+                info!("Shutdown requested. Not restarting the {}", name);
+                return Ok(());
+            }
+        }
+        delay = cmp::min(delay * 2, MAX_RESTART_DELAY);
+    }
+}
+
+/// Sends a crash report to the reporter room without waiting on the responder
+fn report(reporter: &mut Option<CrashReporter>, message: String) {
+    if let Some(reporter) = reporter {
+        let message = MatrixMessage {
+            room_id: reporter.room_id.clone(),
+            message: MatrixMessageType::Notice(message),
+            resp: None,
+        };
+        if reporter.send.try_send(message).is_err() {
+            warn!("Unable to report crash to room {}", reporter.room_id);
+        }
+    }
+}
+
+/// Extracts the message from a panic payload
+fn panic_message(payload: Box<dyn Any + Send + 'static>) -> String {
+    match payload.downcast::<String>() {
+        Ok(v) => *v,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(v) => v.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
@@ -1,34 +1,48 @@
-use crate::config::{Config, WebhookListenerConfig};
+use crate::config::WebhookListenerConfig;
+use crate::matrix::rooms::RoomAliases;
 use crate::messages::MatrixMessage;
+use crate::reload::ConfigReceiver;
+use crate::shutdown::{wait_for_shutdown, ShutdownReceiver};
 use crate::webhook_handlers::register_handlers;
 use rocket::config::{self, Environment, LoggingLevel};
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
 pub struct WebhookListener {
     send: Sender<MatrixMessage>,
     config: WebhookListenerConfig,
+    aliases: Arc<RoomAliases>,
 }
 
 impl WebhookListener {
-    pub fn new(config: &Config, send: Sender<MatrixMessage>) -> Self {
-        let config = WebhookListenerConfig {
-            token: config.webhook_token.clone(),
-        };
-        WebhookListener { send, config }
+    pub fn new(
+        config: ConfigReceiver,
+        aliases: Arc<RoomAliases>,
+        send: Sender<MatrixMessage>,
+    ) -> Self {
+        let config = WebhookListenerConfig::new(config);
+        WebhookListener {
+            send,
+            config,
+            aliases,
+        }
     }
 
-    pub async fn start(self) {
+    pub async fn start(self, mut shutdown: ShutdownReceiver) {
         let rocket_config = config::Config::build(Environment::Production)
             .log_level(LoggingLevel::Off)
             .port(33333)
             .unwrap();
-        let rocket = rocket::custom(rocket_config);
-        match register_handlers(rocket)
+        let rocket = register_handlers(rocket::custom(rocket_config))
             .manage(self.send)
             .manage(self.config)
-            .launch()
-            .await
-        {
+            .manage(self.aliases);
+        let shutdown_handle = rocket.get_shutdown_handle();
+        tokio::spawn(async move {
+            wait_for_shutdown(&mut shutdown).await;
+            shutdown_handle.shutdown();
+        });
+        match rocket.launch().await {
             Ok(_) => (),
             Err(e) => panic!("Unable to launch webhook listener due to error {:?}", e),
         }
@@ -1,56 +1,157 @@
-use crate::config::WebhookListenerConfig;
+use crate::config::{RoomIdOrAlias, WebhookListenerConfig};
 use crate::helpers::MatrixFormattedTextResponse;
-use crate::messages::{MatrixFormattedMessage, MatrixMessage, MatrixMessageType};
+use crate::matrix::rooms::RoomAliases;
+use crate::messages::{
+    MatrixFormattedMessage, MatrixMessage, MatrixMessageError, MatrixMessageType, DELIVERY_TIMEOUT,
+};
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Request};
+use rocket::response::status;
 use rocket::State;
 use rocket_contrib::json::Json;
-use ruma::{RoomId, UserId};
+use ruma::{EventId, RoomId, UserId};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use tracing::{error, info};
 
+/// Sends a message, and pings the given users if any, on behalf of a webhook caller.
+///
+/// Responds with `Accepted` instead of `Ok` if a message was queued to be sent later rather than
+/// sent, so callers do not retry and post it twice.
 #[post("/", data = "<message>")]
 pub async fn message(
     req_token: MessageToken,
     message: Json<Message>,
     conf: State<'_, WebhookListenerConfig>,
     send: State<'_, Sender<MatrixMessage>>,
-) -> Status {
-    if req_token.0.eq(&conf.token) {
-        let matrix_message = MatrixMessage {
-            room_id: message.room_id.clone(),
-            message: MatrixMessageType::Notice(message.message.clone()),
-        };
-        match send.clone().send(matrix_message).await {
-            Ok(_) => (),
-            Err(_) => return Status::InternalServerError,
-        };
-        if let Some(pings) = &message.ping {
-            let mut response = MatrixFormattedTextResponse::default();
-            let pings: HashSet<UserId> = pings.iter().cloned().collect();
-            response.set_users(pings);
-            let matrix_message = MatrixMessage {
-                room_id: message.room_id.clone(),
-                message: MatrixMessageType::FormattedText(MatrixFormattedMessage {
+    aliases: State<'_, Arc<RoomAliases>>,
+) -> Result<status::Custom<Json<MessageResponse>>, Status> {
+    if req_token.0.eq(&conf.token()) {
+        let room = RoomIdOrAlias::try_from(message.room_id.as_str()).map_err(|e| {
+            error!("Unable to send webhook message: {}", e);
+            Status::BadRequest
+        })?;
+        let room_id = aliases.resolve(&room).await.ok_or(Status::NotFound)?;
+        let event_id = send_and_wait(
+            send.clone(),
+            room_id.clone(),
+            MatrixMessageType::Notice(message.message.clone()),
+        )
+        .await?;
+        let ping_event_id = match &message.ping {
+            Some(pings) => {
+                let mut response = MatrixFormattedTextResponse::default();
+                let pings: HashSet<UserId> = pings.iter().cloned().collect();
+                response.set_users(pings);
+                let message_type = MatrixMessageType::FormattedText(MatrixFormattedMessage {
                     plain_text: response.to_string(),
                     formatted_text: response.format_text(),
-                }),
-            };
-            match send.clone().send(matrix_message).await {
-                Ok(_) => (),
-                Err(_) => return Status::InternalServerError,
-            };
+                });
+                Some(send_and_wait(send.clone(), room_id, message_type).await?)
+            }
+            None => None,
         };
-        Status::Ok
+        Ok(message_response(event_id, ping_event_id))
     } else {
-        Status::Unauthorized
+        Err(Status::Unauthorized)
+    }
+}
+
+/// Builds the response to a webhook message from the ids of the events sent for it.
+///
+/// `ping_event_id` is `None` if no users were pinged. Responds with `Accepted` if either message is
+/// still queued.
+pub(super) fn message_response(
+    event_id: Option<EventId>,
+    ping_event_id: Option<Option<EventId>>,
+) -> status::Custom<Json<MessageResponse>> {
+    let queued = event_id.is_none() || matches!(ping_event_id, Some(None));
+    let status = if queued { Status::Accepted } else { Status::Ok };
+    status::Custom(
+        status,
+        Json(MessageResponse {
+            event_id,
+            ping_event_id: ping_event_id.flatten(),
+        }),
+    )
+}
+
+/// Queues a message for the responder and waits until it has been sent.
+///
+/// Returns the id of the sent event, or `None` if the message is still in the outbox because it
+/// was not sent within `DELIVERY_TIMEOUT`. Queued messages are still sent once the homeserver
+/// accepts them.
+async fn send_and_wait(
+    mut send: Sender<MatrixMessage>,
+    room_id: RoomId,
+    message: MatrixMessageType,
+) -> Result<Option<EventId>, Status> {
+    let (resp, recv) = oneshot::channel();
+    let matrix_message = MatrixMessage {
+        room_id,
+        message,
+        resp: Some(resp),
+    };
+    if send.send(matrix_message).await.is_err() {
+        return Err(Status::InternalServerError);
+    }
+    match timeout(DELIVERY_TIMEOUT, recv).await {
+        Ok(Ok(result)) => delivery_status(result),
+        Ok(Err(_)) => Err(Status::InternalServerError),
+        Err(_) => {
+            info!(
+                "Webhook message not sent within {:?}. It will keep being retried",
+                DELIVERY_TIMEOUT
+            );
+            Ok(None)
+        }
     }
 }
 
+/// Maps the responder's result for a webhook message to the event id to respond with.
+///
+/// Only messages the homeserver rejected are reported as `BadGateway`. Queued messages return
+/// `None` since they are still delivered later.
+pub(super) fn delivery_status(
+    result: Result<EventId, MatrixMessageError>,
+) -> Result<Option<EventId>, Status> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(MatrixMessageError::Queued) => {
+            info!("Webhook message queued. It will keep being retried");
+            Ok(None)
+        }
+        Err(MatrixMessageError::Rejected(e)) => {
+            error!("Homeserver rejected webhook message due to error {}", e);
+            Err(Status::BadGateway)
+        }
+        Err(MatrixMessageError::NotAMessage) => {
+            error!("Webhook message was not sent as a message");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Body returned once a message has been sent or queued
+pub struct MessageResponse {
+    /// Id of the event containing the message. `None` if it was queued to be sent later
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<EventId>,
+    /// Id of the event pinging users, if any were supplied and the ping was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ping_event_id: Option<EventId>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Message {
-    room_id: RoomId,
+    /// Room id or alias of the room to send to
+    room_id: String,
     message: String,
     ping: Option<Vec<UserId>>,
 }
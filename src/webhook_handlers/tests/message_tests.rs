@@ -0,0 +1,60 @@
+use crate::messages::MatrixMessageError;
+use crate::webhook_handlers::message::{delivery_status, message_response};
+use rocket::http::Status;
+use ruma::EventId;
+use std::convert::TryFrom;
+
+fn event_id(id: &str) -> EventId {
+    EventId::try_from(format!("${}:homeserver.com", id).as_str()).unwrap()
+}
+
+#[test]
+fn sent_message_returns_event_id() {
+    assert_eq!(
+        Ok(Some(event_id("sent"))),
+        delivery_status(Ok(event_id("sent")))
+    )
+}
+
+#[test]
+fn queued_message_returns_no_event_id() {
+    assert_eq!(Ok(None), delivery_status(Err(MatrixMessageError::Queued)))
+}
+
+#[test]
+fn rejected_message_is_bad_gateway() {
+    assert_eq!(
+        Err(Status::BadGateway),
+        delivery_status(Err(MatrixMessageError::Rejected("M_FORBIDDEN".to_string())))
+    )
+}
+
+#[test]
+fn non_message_is_internal_error() {
+    assert_eq!(
+        Err(Status::InternalServerError),
+        delivery_status(Err(MatrixMessageError::NotAMessage))
+    )
+}
+
+#[test]
+fn sent_messages_are_ok() {
+    assert_eq!(Status::Ok, message_response(Some(event_id("sent")), None).0);
+    assert_eq!(
+        Status::Ok,
+        message_response(Some(event_id("sent")), Some(Some(event_id("ping")))).0
+    );
+}
+
+#[test]
+fn queued_messages_are_accepted() {
+    assert_eq!(Status::Accepted, message_response(None, None).0);
+    assert_eq!(
+        Status::Accepted,
+        message_response(Some(event_id("sent")), Some(None)).0
+    );
+    assert_eq!(
+        Status::Accepted,
+        message_response(None, Some(Some(event_id("ping")))).0
+    );
+}
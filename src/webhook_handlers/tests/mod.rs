@@ -0,0 +1 @@
+mod message_tests;